@@ -0,0 +1,20 @@
+-- This file should undo anything in `up.sql`
+
+create table aliases_old
+(
+    alias       text    not null primary key,
+    destination text    not null,
+    creator     integer not null,
+    foreign key (creator)
+        references users (id)
+        on delete cascade
+);
+
+insert into aliases_old (alias, destination, creator)
+select alias, destination, creator
+from aliases;
+
+drop table aliases;
+alter table aliases_old rename to aliases;
+
+create index alias_creators on aliases(creator);
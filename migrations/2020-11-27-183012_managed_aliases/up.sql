@@ -0,0 +1,5 @@
+-- Aliases created through `alias-client apply` are flagged so that ad-hoc
+-- edits don't silently diverge from the checked-in file.
+
+alter table aliases
+    add column managed boolean not null default 0;
@@ -0,0 +1,2 @@
+alter table aliases
+    add column redirect_status integer not null default 302;
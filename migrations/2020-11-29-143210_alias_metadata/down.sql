@@ -0,0 +1,27 @@
+-- This file should undo anything in `up.sql`
+
+drop trigger aliases_fts_update;
+drop trigger aliases_fts_delete;
+drop trigger aliases_fts_insert;
+drop table aliases_fts;
+
+create table aliases_old
+(
+    alias           text    not null primary key,
+    destination     text    not null,
+    creator         integer not null,
+    managed         boolean not null default 0,
+    redirect_status integer not null default 302,
+    foreign key (creator)
+        references users (id)
+        on delete cascade
+);
+
+insert into aliases_old (alias, destination, creator, managed, redirect_status)
+select alias, destination, creator, managed, redirect_status
+from aliases;
+
+drop table aliases;
+alter table aliases_old rename to aliases;
+
+create index alias_creators on aliases(creator);
@@ -0,0 +1,50 @@
+alter table aliases
+    add column description text not null default '';
+-- Space-separated, normalized by aliasd before they're written.
+alter table aliases
+    add column tags text not null default '';
+alter table aliases
+    add column created_at bigint not null default 0;
+alter table aliases
+    add column updated_at bigint not null default 0;
+alter table aliases
+    add column modified_by integer null references users (id) on delete set null;
+
+update aliases
+set created_at  = cast(strftime('%s', 'now') as integer),
+    updated_at  = cast(strftime('%s', 'now') as integer),
+    modified_by = creator;
+
+-- Full-text index over the searchable columns, kept in sync with triggers.
+-- Writes to `aliases` must not use `replace into`, since REPLACE doesn't fire the delete trigger.
+create virtual table aliases_fts using fts5
+(
+    alias,
+    destination,
+    description,
+    tags,
+    content = 'aliases',
+    content_rowid = 'rowid'
+);
+
+insert into aliases_fts(aliases_fts) values ('rebuild');
+
+create trigger aliases_fts_insert after insert on aliases
+begin
+    insert into aliases_fts(rowid, alias, destination, description, tags)
+    values (new.rowid, new.alias, new.destination, new.description, new.tags);
+end;
+
+create trigger aliases_fts_delete after delete on aliases
+begin
+    insert into aliases_fts(aliases_fts, rowid, alias, destination, description, tags)
+    values ('delete', old.rowid, old.alias, old.destination, old.description, old.tags);
+end;
+
+create trigger aliases_fts_update after update on aliases
+begin
+    insert into aliases_fts(aliases_fts, rowid, alias, destination, description, tags)
+    values ('delete', old.rowid, old.alias, old.destination, old.description, old.tags);
+    insert into aliases_fts(rowid, alias, destination, description, tags)
+    values (new.rowid, new.alias, new.destination, new.description, new.tags);
+end;
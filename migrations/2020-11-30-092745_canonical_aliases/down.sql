@@ -0,0 +1,57 @@
+-- This file should undo anything in `up.sql`
+
+drop trigger aliases_fts_update;
+drop trigger aliases_fts_delete;
+drop trigger aliases_fts_insert;
+drop index alias_canonicals;
+
+create table aliases_old
+(
+    alias           text    not null primary key,
+    destination     text    not null,
+    creator         integer not null,
+    managed         boolean not null default 0,
+    redirect_status integer not null default 302,
+    description     text    not null default '',
+    tags            text    not null default '',
+    created_at      bigint  not null default 0,
+    updated_at      bigint  not null default 0,
+    modified_by     integer null references users (id) on delete set null,
+    foreign key (creator)
+        references users (id)
+        on delete cascade
+);
+
+insert into aliases_old (alias, destination, creator, managed, redirect_status,
+                         description, tags, created_at, updated_at, modified_by)
+select alias, destination, creator, managed, redirect_status,
+       description, tags, created_at, updated_at, modified_by
+from aliases
+where canonical is null;
+
+drop table aliases;
+alter table aliases_old rename to aliases;
+
+create index alias_creators on aliases(creator);
+
+insert into aliases_fts(aliases_fts) values ('rebuild');
+
+create trigger aliases_fts_insert after insert on aliases
+begin
+    insert into aliases_fts(rowid, alias, destination, description, tags)
+    values (new.rowid, new.alias, new.destination, new.description, new.tags);
+end;
+
+create trigger aliases_fts_delete after delete on aliases
+begin
+    insert into aliases_fts(aliases_fts, rowid, alias, destination, description, tags)
+    values ('delete', old.rowid, old.alias, old.destination, old.description, old.tags);
+end;
+
+create trigger aliases_fts_update after update on aliases
+begin
+    insert into aliases_fts(aliases_fts, rowid, alias, destination, description, tags)
+    values ('delete', old.rowid, old.alias, old.destination, old.description, old.tags);
+    insert into aliases_fts(rowid, alias, destination, description, tags)
+    values (new.rowid, new.alias, new.destination, new.description, new.tags);
+end;
@@ -0,0 +1,5 @@
+-- An alias with a canonical resolves through that alias instead of its own destination.
+alter table aliases
+    add column canonical text null;
+
+create index alias_canonicals on aliases(canonical);
@@ -0,0 +1,18 @@
+-- How a request picks among an alias's destinations when it has more than one.
+alter table aliases
+    add column strategy text not null default 'weighted';
+
+-- Extra destinations for an alias. When there are any, they replace `aliases.destination`,
+-- which keeps the first of them so listings and search still have something to show.
+create table alias_destinations
+(
+    id          integer not null primary key,
+    alias       text    not null,
+    position    integer not null,
+    destination text    not null,
+    weight      integer not null default 1,
+    unique (alias, position),
+    foreign key (alias)
+        references aliases (alias)
+        on delete cascade
+);
@@ -0,0 +1,14 @@
+-- Conditional destinations, tried in order before an alias's usual ones.
+create table alias_rules
+(
+    id          integer not null primary key,
+    alias       text    not null,
+    position    integer not null,
+    -- A JSON-encoded `Condition`, checked by aliasd when the rule is written.
+    condition   text    not null,
+    destination text    not null,
+    unique (alias, position),
+    foreign key (alias)
+        references aliases (alias)
+        on delete cascade
+);
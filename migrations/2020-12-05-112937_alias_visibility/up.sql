@@ -0,0 +1,3 @@
+-- One of `public`, `authenticated` or `owner`.
+alter table aliases
+    add column visibility text not null default 'public';
@@ -0,0 +1,12 @@
+-- The last health check of each distinct destination URL, written by aliasd's link checker.
+create table destination_health
+(
+    destination text    not null primary key,
+    checked_at  bigint  not null,
+    -- Null when the request didn't get a response at all.
+    status      integer null,
+    latency_ms  integer null,
+    error       text    null,
+    -- Consecutive failed checks; reset by a successful one.
+    failures    integer not null default 0
+);
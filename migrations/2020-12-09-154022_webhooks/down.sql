@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+
+drop table webhook_attempts;
+drop table webhook_deliveries;
+drop table webhook_endpoints;
+drop table admins;
@@ -0,0 +1,56 @@
+-- Users allowed to use the admin API.
+create table admins
+(
+    user_id integer not null primary key,
+    foreign key (user_id)
+        references users (id)
+        on delete cascade
+);
+
+create table webhook_endpoints
+(
+    id         integer not null primary key autoincrement,
+    url        text    not null,
+    -- Used to sign each delivery so the receiver can check it came from aliasd.
+    secret     text    not null,
+    -- Space-separated event names; empty means every event.
+    events     text    not null default '',
+    created_at bigint  not null,
+    created_by integer null references users (id) on delete set null
+);
+
+-- The outbox. Rows are written in the same transaction as the change they describe,
+-- and stay until they're delivered or given up on.
+create table webhook_deliveries
+(
+    id              integer not null primary key autoincrement,
+    endpoint        integer not null,
+    event           text    not null,
+    payload         text    not null,
+    created_at      bigint  not null,
+    attempts        integer not null default 0,
+    next_attempt_at bigint  not null,
+    delivered_at    bigint  null,
+    failed          boolean not null default 0,
+    foreign key (endpoint)
+        references webhook_endpoints (id)
+        on delete cascade
+);
+
+create index webhook_deliveries_due on webhook_deliveries(next_attempt_at)
+    where delivered_at is null and failed = 0;
+
+create table webhook_attempts
+(
+    id           integer not null primary key autoincrement,
+    delivery     integer not null,
+    attempted_at bigint  not null,
+    status       integer null,
+    error        text    null,
+    duration_ms  integer not null,
+    foreign key (delivery)
+        references webhook_deliveries (id)
+        on delete cascade
+);
+
+create index webhook_attempts_delivery on webhook_attempts(delivery);
@@ -0,0 +1,126 @@
+-- Hostnames with their own set of aliases. Requests for any other host, and aliases from
+-- before domains existed, use the default domain, which is stored as ''.
+create table domains
+(
+    name       text   not null primary key,
+    created_at bigint not null
+);
+
+-- Users allowed to change a domain's aliases. A domain without any is open to everyone.
+create table domain_owners
+(
+    domain  text    not null,
+    user_id integer not null,
+    primary key (domain, user_id),
+    foreign key (domain)
+        references domains (name)
+        on delete cascade,
+    foreign key (user_id)
+        references users (id)
+        on delete cascade
+);
+
+drop trigger aliases_fts_update;
+drop trigger aliases_fts_delete;
+drop trigger aliases_fts_insert;
+
+create table aliases_new
+(
+    domain          text    not null default '',
+    alias           text    not null,
+    destination     text    not null,
+    creator         integer not null,
+    managed         boolean not null default 0,
+    redirect_status integer not null default 302,
+    description     text    not null default '',
+    tags            text    not null default '',
+    created_at      bigint  not null default 0,
+    updated_at      bigint  not null default 0,
+    modified_by     integer null references users (id) on delete set null,
+    -- Always in the same domain as the alias itself.
+    canonical       text    null,
+    strategy        text    not null default 'weighted',
+    visibility      text    not null default 'public',
+    primary key (domain, alias),
+    foreign key (creator)
+        references users (id)
+        on delete cascade
+);
+
+insert into aliases_new (alias, destination, creator, managed, redirect_status, description, tags,
+                         created_at, updated_at, modified_by, canonical, strategy, visibility)
+select alias, destination, creator, managed, redirect_status, description, tags,
+       created_at, updated_at, modified_by, canonical, strategy, visibility
+from aliases;
+
+drop table aliases;
+alter table aliases_new rename to aliases;
+
+create index alias_creators on aliases(creator);
+create index alias_canonicals on aliases(domain, canonical);
+
+create table alias_destinations_new
+(
+    id          integer not null primary key,
+    domain      text    not null default '',
+    alias       text    not null,
+    position    integer not null,
+    destination text    not null,
+    weight      integer not null default 1,
+    unique (domain, alias, position),
+    foreign key (domain, alias)
+        references aliases (domain, alias)
+        on delete cascade
+);
+
+insert into alias_destinations_new (id, alias, position, destination, weight)
+select id, alias, position, destination, weight
+from alias_destinations;
+
+drop table alias_destinations;
+alter table alias_destinations_new rename to alias_destinations;
+
+create table alias_rules_new
+(
+    id          integer not null primary key,
+    domain      text    not null default '',
+    alias       text    not null,
+    position    integer not null,
+    -- A JSON-encoded `Condition`, checked by aliasd when the rule is written.
+    condition   text    not null,
+    destination text    not null,
+    unique (domain, alias, position),
+    foreign key (domain, alias)
+        references aliases (domain, alias)
+        on delete cascade
+);
+
+insert into alias_rules_new (id, alias, position, condition, destination)
+select id, alias, position, condition, destination
+from alias_rules;
+
+drop table alias_rules;
+alter table alias_rules_new rename to alias_rules;
+
+-- The rebuilt table has new rowids, so the index has to be rebuilt to match.
+insert into aliases_fts(aliases_fts) values ('rebuild');
+
+create trigger aliases_fts_insert after insert on aliases
+begin
+    insert into aliases_fts(rowid, alias, destination, description, tags)
+    values (new.rowid, new.alias, new.destination, new.description, new.tags);
+end;
+
+create trigger aliases_fts_delete after delete on aliases
+begin
+    insert into aliases_fts(aliases_fts, rowid, alias, destination, description, tags)
+    values ('delete', old.rowid, old.alias, old.destination, old.description, old.tags);
+end;
+
+create trigger aliases_fts_update after update on aliases
+begin
+    insert into aliases_fts(aliases_fts, rowid, alias, destination, description, tags)
+    values ('delete', old.rowid, old.alias, old.destination, old.description, old.tags);
+    insert into aliases_fts(rowid, alias, destination, description, tags)
+    values (new.rowid, new.alias, new.destination, new.description, new.tags);
+end;
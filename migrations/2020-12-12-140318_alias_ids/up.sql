@@ -0,0 +1,75 @@
+-- The full-text index points at its rows by rowid, which SQLite only keeps stable across
+-- VACUUM when it's an INTEGER PRIMARY KEY, so `aliases` gets one and the index follows it.
+drop trigger aliases_fts_update;
+drop trigger aliases_fts_delete;
+drop trigger aliases_fts_insert;
+drop table aliases_fts;
+
+create table aliases_new
+(
+    id              integer not null primary key,
+    domain          text    not null default '',
+    alias           text    not null,
+    destination     text    not null,
+    creator         integer not null,
+    managed         boolean not null default 0,
+    redirect_status integer not null default 302,
+    description     text    not null default '',
+    tags            text    not null default '',
+    created_at      bigint  not null default 0,
+    updated_at      bigint  not null default 0,
+    modified_by     integer null references users (id) on delete set null,
+    -- Always in the same domain as the alias itself.
+    canonical       text    null,
+    strategy        text    not null default 'weighted',
+    visibility      text    not null default 'public',
+    unique (domain, alias),
+    foreign key (creator)
+        references users (id)
+        on delete cascade
+);
+
+insert into aliases_new (domain, alias, destination, creator, managed, redirect_status, description, tags,
+                         created_at, updated_at, modified_by, canonical, strategy, visibility)
+select domain, alias, destination, creator, managed, redirect_status, description, tags,
+       created_at, updated_at, modified_by, canonical, strategy, visibility
+from aliases;
+
+drop table aliases;
+alter table aliases_new rename to aliases;
+
+create index alias_creators on aliases(creator);
+create index alias_canonicals on aliases(domain, canonical);
+
+-- Writes to `aliases` must not use `replace into`, since REPLACE doesn't fire the delete trigger.
+create virtual table aliases_fts using fts5
+(
+    alias,
+    destination,
+    description,
+    tags,
+    content = 'aliases',
+    content_rowid = 'id'
+);
+
+insert into aliases_fts(aliases_fts) values ('rebuild');
+
+create trigger aliases_fts_insert after insert on aliases
+begin
+    insert into aliases_fts(rowid, alias, destination, description, tags)
+    values (new.id, new.alias, new.destination, new.description, new.tags);
+end;
+
+create trigger aliases_fts_delete after delete on aliases
+begin
+    insert into aliases_fts(aliases_fts, rowid, alias, destination, description, tags)
+    values ('delete', old.id, old.alias, old.destination, old.description, old.tags);
+end;
+
+create trigger aliases_fts_update after update on aliases
+begin
+    insert into aliases_fts(aliases_fts, rowid, alias, destination, description, tags)
+    values ('delete', old.id, old.alias, old.destination, old.description, old.tags);
+    insert into aliases_fts(rowid, alias, destination, description, tags)
+    values (new.id, new.alias, new.destination, new.description, new.tags);
+end;
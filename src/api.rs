@@ -0,0 +1,553 @@
+//! The routes `aliasd` serves, described once so the OpenAPI document at `/openapi.json`, the route
+//! check in `aliasd check` and `alias-client` can't drift apart.
+
+use std::any::TypeId;
+
+use schemars::JsonSchema;
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Map, Value};
+
+use crate::model::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Method {
+    Get,
+    Post,
+    Put,
+    Delete,
+}
+
+impl Method {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+        }
+    }
+}
+
+/// Who may call an endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Auth {
+    Anyone,
+    /// A bearer token from `POST /login`, or the `auth` cookie it sets.
+    User,
+    /// A user made an admin with `aliasd user admin`.
+    Admin,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum In {
+    Path,
+    Query,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Param {
+    pub name: &'static str,
+    pub location: In,
+    /// The JSON schema type, like `string` or `integer`.
+    pub kind: &'static str,
+    pub description: &'static str,
+}
+
+/// Picks the set of aliases to work with, instead of going by the host the request was sent to.
+pub const DOMAIN: Param = Param {
+    name: "domain",
+    location: In::Query,
+    kind: "string",
+    description: "The domain to work in. Empty for the default domain.",
+};
+
+const ALIAS: Param = Param {
+    name: "alias",
+    location: In::Path,
+    kind: "string",
+    description: "The alias name.",
+};
+
+const HOOK: Param = Param {
+    name: "hook",
+    location: In::Path,
+    kind: "integer",
+    description: "The webhook endpoint's id.",
+};
+
+const DOMAIN_NAME: Param = Param {
+    name: "name",
+    location: In::Path,
+    kind: "string",
+    description: "The domain, like go.example.com.",
+};
+
+/// A JSON endpoint, along with the types it takes and returns.
+pub trait Endpoint {
+    const METHOD: Method;
+    /// In OpenAPI form, like `/admin/webhooks/{hook}`.
+    const PATH: &'static str;
+    const SUMMARY: &'static str;
+    const AUTH: Auth = Auth::User;
+    const PARAMS: &'static [Param] = &[];
+    /// The status of a successful response, or a range like `3XX`.
+    const STATUS: &'static str = "200";
+
+    /// `()` for endpoints that don't take a body.
+    type Body: Serialize + JsonSchema + 'static;
+    type Response: DeserializeOwned + JsonSchema;
+
+    /// The path's segments with its parameters filled in, not yet percent-encoded. Each is appended
+    /// to the server URL as one segment, so a name with `/`, `?` or `#` in it stays a name.
+    fn segments(&self) -> Vec<String> {
+        Self::PATH.split('/').filter(|s| !s.is_empty()).map(String::from).collect()
+    }
+
+    fn query(&self) -> Vec<(&'static str, String)> {
+        Vec::new()
+    }
+
+    fn body(&self) -> Option<&Self::Body> {
+        None
+    }
+}
+
+pub struct LoginRequest(pub Login);
+
+impl Endpoint for LoginRequest {
+    const METHOD: Method = Method::Post;
+    const PATH: &'static str = "/login";
+    const SUMMARY: &'static str = "Logs in, setting the auth cookie and returning the same token for use as a bearer token.";
+    const AUTH: Auth = Auth::Anyone;
+    type Body = Login;
+    type Response = LoginToken;
+
+    fn body(&self) -> Option<&Login> {
+        Some(&self.0)
+    }
+}
+
+/// Follows an alias without following the redirect.
+pub struct ResolveAlias {
+    pub alias: String,
+}
+
+impl Endpoint for ResolveAlias {
+    const METHOD: Method = Method::Get;
+    const PATH: &'static str = "/{alias}";
+    const SUMMARY: &'static str = "Redirects to the alias's destination. Ending the alias with `+` shows where it goes instead.";
+    const AUTH: Auth = Auth::Anyone;
+    const PARAMS: &'static [Param] = &[ALIAS, DOMAIN];
+    const STATUS: &'static str = "3XX";
+    type Body = ();
+    type Response = Redirected;
+
+    fn segments(&self) -> Vec<String> {
+        vec![self.alias.clone()]
+    }
+}
+
+pub struct ListAliases;
+
+impl Endpoint for ListAliases {
+    const METHOD: Method = Method::Get;
+    const PATH: &'static str = "/alias";
+    const SUMMARY: &'static str = "Lists the domain's aliases that the user can see.";
+    const PARAMS: &'static [Param] = &[DOMAIN];
+    type Body = ();
+    type Response = Vec<AliasInfo>;
+}
+
+pub struct SearchAliases {
+    pub q: String,
+}
+
+impl Endpoint for SearchAliases {
+    const METHOD: Method = Method::Get;
+    const PATH: &'static str = "/search";
+    const SUMMARY: &'static str = "Searches alias names, destinations, descriptions and tags.";
+    const PARAMS: &'static [Param] = &[
+        Param { name: "q", location: In::Query, kind: "string", description: "The search terms." },
+        DOMAIN,
+    ];
+    type Body = ();
+    type Response = Vec<SearchHit>;
+
+    fn query(&self) -> Vec<(&'static str, String)> {
+        vec![("q", self.q.clone())]
+    }
+}
+
+/// Creates an alias, or replaces it if the user already has one by that name.
+pub struct SaveAlias(pub AliasForm);
+
+impl Endpoint for SaveAlias {
+    const METHOD: Method = Method::Post;
+    const PATH: &'static str = "/alias";
+    const SUMMARY: &'static str = "Creates or replaces an alias.";
+    const PARAMS: &'static [Param] = &[DOMAIN];
+    const STATUS: &'static str = "201";
+    type Body = AliasForm;
+    type Response = AliasSaved;
+
+    fn body(&self) -> Option<&AliasForm> {
+        Some(&self.0)
+    }
+}
+
+pub struct DeleteAlias {
+    pub alias: String,
+    /// Needed to delete an alias `alias-client apply` manages.
+    pub managed: bool,
+}
+
+impl Endpoint for DeleteAlias {
+    const METHOD: Method = Method::Delete;
+    const PATH: &'static str = "/{alias}";
+    const SUMMARY: &'static str = "Deletes one of the user's aliases.";
+    const PARAMS: &'static [Param] = &[
+        ALIAS,
+        Param { name: "managed", location: In::Query, kind: "boolean", description: "Allows deleting a managed alias." },
+        DOMAIN,
+    ];
+    type Body = ();
+    type Response = Message;
+
+    fn segments(&self) -> Vec<String> {
+        vec![self.alias.clone()]
+    }
+
+    fn query(&self) -> Vec<(&'static str, String)> {
+        if self.managed {
+            vec![("managed", "true".to_string())]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+pub struct GetAccount;
+
+impl Endpoint for GetAccount {
+    const METHOD: Method = Method::Get;
+    const PATH: &'static str = "/account";
+    const SUMMARY: &'static str = "Describes the logged-in user.";
+    type Body = ();
+    type Response = Account;
+}
+
+pub struct ChangePassword(pub PasswordChange);
+
+impl Endpoint for ChangePassword {
+    const METHOD: Method = Method::Post;
+    const PATH: &'static str = "/account/password";
+    const SUMMARY: &'static str = "Changes the user's password.";
+    type Body = PasswordChange;
+    type Response = Message;
+
+    fn body(&self) -> Option<&PasswordChange> {
+        Some(&self.0)
+    }
+}
+
+pub struct ListWebhooks;
+
+impl Endpoint for ListWebhooks {
+    const METHOD: Method = Method::Get;
+    const PATH: &'static str = "/admin/webhooks";
+    const SUMMARY: &'static str = "Lists the webhook endpoints, without their secrets.";
+    const AUTH: Auth = Auth::Admin;
+    type Body = ();
+    type Response = Vec<WebhookEndpoint>;
+}
+
+pub struct AddWebhook(pub NewWebhook);
+
+impl Endpoint for AddWebhook {
+    const METHOD: Method = Method::Post;
+    const PATH: &'static str = "/admin/webhooks";
+    const SUMMARY: &'static str = "Registers a webhook endpoint. The response is the only place its secret is shown.";
+    const AUTH: Auth = Auth::Admin;
+    const STATUS: &'static str = "201";
+    type Body = NewWebhook;
+    type Response = WebhookEndpoint;
+
+    fn body(&self) -> Option<&NewWebhook> {
+        Some(&self.0)
+    }
+}
+
+pub struct DeleteWebhook {
+    pub hook: i32,
+}
+
+impl Endpoint for DeleteWebhook {
+    const METHOD: Method = Method::Delete;
+    const PATH: &'static str = "/admin/webhooks/{hook}";
+    const SUMMARY: &'static str = "Deletes a webhook endpoint and its deliveries.";
+    const AUTH: Auth = Auth::Admin;
+    const PARAMS: &'static [Param] = &[HOOK];
+    type Body = ();
+    type Response = Message;
+
+    fn segments(&self) -> Vec<String> {
+        vec!["admin".to_string(), "webhooks".to_string(), self.hook.to_string()]
+    }
+}
+
+pub struct ListDeliveries {
+    pub hook: i32,
+}
+
+impl Endpoint for ListDeliveries {
+    const METHOD: Method = Method::Get;
+    const PATH: &'static str = "/admin/webhooks/{hook}/deliveries";
+    const SUMMARY: &'static str = "Lists the endpoint's most recent deliveries, with every attempt made so far.";
+    const AUTH: Auth = Auth::Admin;
+    const PARAMS: &'static [Param] = &[HOOK];
+    type Body = ();
+    type Response = Vec<WebhookDelivery>;
+
+    fn segments(&self) -> Vec<String> {
+        vec!["admin".to_string(), "webhooks".to_string(), self.hook.to_string(), "deliveries".to_string()]
+    }
+}
+
+pub struct ListDomains;
+
+impl Endpoint for ListDomains {
+    const METHOD: Method = Method::Get;
+    const PATH: &'static str = "/admin/domains";
+    const SUMMARY: &'static str = "Lists the domains, with their owners and how many aliases they have.";
+    const AUTH: Auth = Auth::Admin;
+    type Body = ();
+    type Response = Vec<DomainInfo>;
+}
+
+pub struct PutDomain {
+    pub name: String,
+    pub form: DomainForm,
+}
+
+impl Endpoint for PutDomain {
+    const METHOD: Method = Method::Put;
+    const PATH: &'static str = "/admin/domains/{name}";
+    const SUMMARY: &'static str = "Creates a domain, or replaces its owners.";
+    const AUTH: Auth = Auth::Admin;
+    const PARAMS: &'static [Param] = &[DOMAIN_NAME];
+    type Body = DomainForm;
+    type Response = Message;
+
+    fn segments(&self) -> Vec<String> {
+        vec!["admin".to_string(), "domains".to_string(), self.name.clone()]
+    }
+
+    fn body(&self) -> Option<&DomainForm> {
+        Some(&self.form)
+    }
+}
+
+pub struct DeleteDomain {
+    pub name: String,
+}
+
+impl Endpoint for DeleteDomain {
+    const METHOD: Method = Method::Delete;
+    const PATH: &'static str = "/admin/domains/{name}";
+    const SUMMARY: &'static str = "Deletes a domain that has no aliases left.";
+    const AUTH: Auth = Auth::Admin;
+    const PARAMS: &'static [Param] = &[DOMAIN_NAME];
+    type Body = ();
+    type Response = Message;
+
+    fn segments(&self) -> Vec<String> {
+        vec!["admin".to_string(), "domains".to_string(), self.name.clone()]
+    }
+}
+
+/// Something done with each [`Endpoint`] in turn.
+pub trait Visit {
+    fn endpoint<E: Endpoint>(&mut self);
+}
+
+/// Calls `v` with every endpoint `aliasd` serves.
+pub fn visit(v: &mut impl Visit) {
+    v.endpoint::<LoginRequest>();
+    v.endpoint::<ResolveAlias>();
+    v.endpoint::<ListAliases>();
+    v.endpoint::<SearchAliases>();
+    v.endpoint::<SaveAlias>();
+    v.endpoint::<DeleteAlias>();
+    v.endpoint::<GetAccount>();
+    v.endpoint::<ChangePassword>();
+    v.endpoint::<ListWebhooks>();
+    v.endpoint::<AddWebhook>();
+    v.endpoint::<DeleteWebhook>();
+    v.endpoint::<ListDeliveries>();
+    v.endpoint::<ListDomains>();
+    v.endpoint::<PutDomain>();
+    v.endpoint::<DeleteDomain>();
+}
+
+/// A route that doesn't answer with one of the model's types, so it has no [`Endpoint`].
+#[derive(Debug, Clone, Copy)]
+pub struct Page {
+    pub method: Method,
+    pub path: &'static str,
+    pub summary: &'static str,
+    pub status: &'static str,
+    pub content_type: Option<&'static str>,
+}
+
+pub const PAGES: &[Page] = &[
+    Page { method: Method::Get, path: "/", summary: "Redirects to the web UI.", status: "303", content_type: None },
+    Page { method: Method::Get, path: "/ui", summary: "The web UI.", status: "200", content_type: Some("text/html") },
+    Page {
+        method: Method::Get,
+        path: "/ui/{file}",
+        summary: "The web UI's scripts and styles.",
+        status: "200",
+        content_type: None,
+    },
+    Page {
+        method: Method::Delete,
+        path: "/login",
+        summary: "Logs out by clearing the auth cookie. Bearer tokens stay valid until they expire.",
+        status: "200",
+        content_type: None,
+    },
+    Page {
+        method: Method::Get,
+        path: "/healthz",
+        summary: "Answers `ok` while the process is up.",
+        status: "200",
+        content_type: Some("text/plain"),
+    },
+    Page {
+        method: Method::Get,
+        path: "/readyz",
+        summary: "Whether the instance can serve aliases, with the checks behind it. 503 when it can't.",
+        status: "200",
+        content_type: Some("application/json"),
+    },
+    Page {
+        method: Method::Get,
+        path: "/openapi.json",
+        summary: "This document.",
+        status: "200",
+        content_type: Some("application/json"),
+    },
+];
+
+/// Every documented route's method and path.
+pub fn routes() -> Vec<(Method, &'static str)> {
+    struct Routes(Vec<(Method, &'static str)>);
+
+    impl Visit for Routes {
+        fn endpoint<E: Endpoint>(&mut self) {
+            self.0.push((E::METHOD, E::PATH));
+        }
+    }
+
+    let mut routes = Routes(PAGES.iter().map(|p| (p.method, p.path)).collect());
+    visit(&mut routes);
+    routes.0
+}
+
+/// The OpenAPI 3 document for every route, with schemas generated from the model types.
+pub fn openapi() -> Value {
+    struct Document {
+        gen: SchemaGenerator,
+        paths: Map<String, Value>,
+        error: Value,
+    }
+
+    impl Document {
+        fn add(&mut self, method: Method, path: &str, operation: Value) {
+            let item = self.paths.entry(path).or_insert_with(|| json!({}));
+            item[method.as_str().to_lowercase()] = operation;
+        }
+    }
+
+    impl Visit for Document {
+        fn endpoint<E: Endpoint>(&mut self) {
+            let params = E::PARAMS.iter().map(|p| json!({
+                "name": p.name,
+                "in": match p.location { In::Path => "path", In::Query => "query" },
+                "required": p.location == In::Path,
+                "description": p.description,
+                "schema": { "type": p.kind },
+            })).collect::<Vec<_>>();
+            let security = match E::AUTH {
+                Auth::Anyone => json!([]),
+                Auth::User | Auth::Admin => json!([{ "bearer": [] }, { "cookie": [] }]),
+            };
+            let response = self.gen.subschema_for::<E::Response>();
+            let mut op = json!({
+                "summary": E::SUMMARY,
+                "parameters": params,
+                "security": security,
+                "responses": {
+                    (E::STATUS): {
+                        "description": "Success",
+                        "content": { "application/json": { "schema": response } },
+                    },
+                    "default": {
+                        "description": "An error",
+                        "content": { "application/json": { "schema": self.error.clone() } },
+                    },
+                },
+            });
+            if E::AUTH == Auth::Admin {
+                op["description"] = "Only for admins.".into();
+            }
+            if TypeId::of::<E::Body>() != TypeId::of::<()>() {
+                let body = self.gen.subschema_for::<E::Body>();
+                op["requestBody"] = json!({
+                    "required": true,
+                    "content": { "application/json": { "schema": body } },
+                });
+            }
+            self.add(E::METHOD, E::PATH, op);
+        }
+    }
+
+    let mut gen = SchemaSettings::openapi3().into_generator();
+    let error = json!(gen.subschema_for::<ErrorBody>());
+    let mut doc = Document { gen, paths: Map::new(), error };
+    visit(&mut doc);
+
+    for page in PAGES {
+        let params = page.path.split('/')
+            .filter_map(|s| s.strip_prefix('{')?.strip_suffix('}'))
+            .map(|name| json!({ "name": name, "in": "path", "required": true, "schema": { "type": "string" } }))
+            .collect::<Vec<_>>();
+        let mut response = json!({ "description": "Success" });
+        if let Some(content_type) = page.content_type {
+            response["content"] = json!({ (content_type): {} });
+        }
+        doc.add(page.method, page.path, json!({
+            "summary": page.summary,
+            "parameters": params,
+            "responses": { (page.status): response },
+        }));
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "aliasd",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": doc.paths,
+        "components": {
+            "schemas": doc.gen.definitions(),
+            "securitySchemes": {
+                "bearer": { "type": "http", "scheme": "bearer", "bearerFormat": "JWT" },
+                "cookie": { "type": "apiKey", "in": "cookie", "name": "auth" },
+            },
+        },
+    })
+}
@@ -0,0 +1,187 @@
+use std::ffi::{CStr, CString};
+use std::os::raw::c_int;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::time::Duration;
+
+use libsqlite3_sys as ffi;
+
+use crate::db::db_path;
+
+/// Pages copied per backup step; the source is only locked while a step runs.
+const PAGES_PER_STEP: c_int = 256;
+const BUSY_SLEEP: Duration = Duration::from_millis(50);
+
+#[derive(Debug, thiserror::Error)]
+pub enum BackupError {
+    #[error("The path {0} can't be handed to SQLite.")]
+    BadPath(String),
+    #[error("SQLite error {code}: {message}")]
+    Sqlite { code: c_int, message: String },
+    #[error("Couldn't move the finished backup into place.")]
+    Io(#[from] std::io::Error),
+}
+
+struct Handle(*mut ffi::sqlite3);
+
+impl Handle {
+    fn open(path: &str, flags: c_int) -> Result<Handle, BackupError> {
+        let c_path = CString::new(path).map_err(|_| BackupError::BadPath(path.to_string()))?;
+        let mut db = ptr::null_mut();
+        let rc = unsafe { ffi::sqlite3_open_v2(c_path.as_ptr(), &mut db, flags, ptr::null()) };
+        let handle = Handle(db);
+
+        if rc != ffi::SQLITE_OK {
+            return Err(handle.error(rc));
+        }
+
+        Ok(handle)
+    }
+
+    fn error(&self, code: c_int) -> BackupError {
+        let message = if self.0.is_null() {
+            unsafe { CStr::from_ptr(ffi::sqlite3_errstr(code)) }
+        } else {
+            unsafe { CStr::from_ptr(ffi::sqlite3_errmsg(self.0)) }
+        };
+
+        BackupError::Sqlite { code, message: message.to_string_lossy().into_owned() }
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        unsafe { ffi::sqlite3_close(self.0); }
+    }
+}
+
+/// A backup in progress from one database file to another.
+///
+/// The copy is written next to the destination and renamed into place by [`BackupJob::finish`].
+/// Dropping an unfinished job removes the partial copy.
+pub struct BackupJob {
+    backup: *mut ffi::sqlite3_backup,
+    // Closed once `backup` is finished.
+    target: Option<Handle>,
+    _source: Handle,
+    partial: PathBuf,
+    dest: PathBuf,
+}
+
+// Each connection is only used by whichever thread holds the job, and SQLite is built threadsafe.
+unsafe impl Send for BackupJob {}
+
+/// How a call to [`BackupJob::step`] went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    More,
+    /// Another connection holds a lock on the source; try again shortly.
+    Busy,
+    Done,
+}
+
+impl BackupJob {
+    pub fn start(src: &str, dest: &Path) -> Result<BackupJob, BackupError> {
+        let partial = dest.with_extension("partial");
+        let partial_str = partial.to_str()
+            .ok_or_else(|| BackupError::BadPath(partial.to_string_lossy().into_owned()))?;
+
+        let source = Handle::open(src, ffi::SQLITE_OPEN_READONLY)?;
+        let target = Handle::open(partial_str, ffi::SQLITE_OPEN_READWRITE | ffi::SQLITE_OPEN_CREATE)?;
+
+        let main = CString::new("main").unwrap();
+        let backup = unsafe {
+            ffi::sqlite3_backup_init(target.0, main.as_ptr(), source.0, main.as_ptr())
+        };
+
+        if backup.is_null() {
+            let err = target.error(unsafe { ffi::sqlite3_errcode(target.0) });
+            std::mem::drop(target);
+            std::fs::remove_file(&partial).ok();
+            return Err(err);
+        }
+
+        Ok(BackupJob { backup, target: Some(target), _source: source, partial, dest: dest.to_path_buf() })
+    }
+
+    /// Copies the next [`PAGES_PER_STEP`] pages. The source is only locked while this runs.
+    pub fn step(&mut self) -> Result<Step, BackupError> {
+        match unsafe { ffi::sqlite3_backup_step(self.backup, PAGES_PER_STEP) } {
+            ffi::SQLITE_OK => Ok(Step::More),
+            ffi::SQLITE_BUSY | ffi::SQLITE_LOCKED => Ok(Step::Busy),
+            ffi::SQLITE_DONE => Ok(Step::Done),
+            rc => Err(self.target.as_ref().unwrap().error(rc)),
+        }
+    }
+
+    /// Moves the finished copy into place. Only call it once [`BackupJob::step`] returns [`Step::Done`].
+    pub fn finish(mut self) -> Result<(), BackupError> {
+        let rc = unsafe { ffi::sqlite3_backup_finish(self.backup) };
+        self.backup = ptr::null_mut();
+        let target = self.target.take().unwrap();
+        if rc != ffi::SQLITE_OK {
+            let err = target.error(rc);
+            std::mem::drop(target);
+            std::fs::remove_file(&self.partial).ok();
+            return Err(err);
+        }
+
+        // The copy's handle has to be closed before it's renamed on some platforms.
+        std::mem::drop(target);
+        std::fs::rename(&self.partial, &self.dest)?;
+        Ok(())
+    }
+}
+
+impl Drop for BackupJob {
+    fn drop(&mut self) {
+        if !self.backup.is_null() {
+            unsafe { ffi::sqlite3_backup_finish(self.backup); }
+            self.target.take();
+            std::fs::remove_file(&self.partial).ok();
+        }
+    }
+}
+
+/// Copies the database at `src` to `dest` with SQLite's online backup API, blocking until it's done.
+///
+/// Other connections, including ones in other processes, can keep using `src` while this runs.
+pub fn backup_file(src: &str, dest: &Path) -> Result<(), BackupError> {
+    let mut job = BackupJob::start(src, dest)?;
+    loop {
+        match job.step()? {
+            Step::More => {}
+            Step::Busy => std::thread::sleep(BUSY_SLEEP),
+            Step::Done => break,
+        }
+    }
+    job.finish()
+}
+
+/// Backs up the live database to `dest`.
+///
+/// The copy goes through its own connection, a batch of pages at a time on the blocking pool, so
+/// requests keep being served in between. SQLite restarts the copy if this process writes meanwhile.
+pub async fn backup(dest: impl Into<PathBuf>) -> Result<(), BackupError> {
+    let dest = dest.into();
+    let src = db_path().into_owned();
+    let mut job = tokio::task::spawn_blocking(move || BackupJob::start(&src, &dest))
+        .await
+        .expect("backup task panicked")?;
+
+    loop {
+        let (j, step) = tokio::task::spawn_blocking(move || {
+            let step = job.step();
+            (job, step)
+        }).await.expect("backup task panicked");
+        job = j;
+
+        match step? {
+            Step::More => tokio::task::yield_now().await,
+            Step::Busy => tokio::time::delay_for(BUSY_SLEEP).await,
+            Step::Done => break,
+        }
+    }
+
+    tokio::task::spawn_blocking(move || job.finish()).await.expect("backup task panicked")
+}
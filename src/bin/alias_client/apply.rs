@@ -0,0 +1,292 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use alias::api;
+use alias::model::{normalize_tags, AliasForm, AliasInfo, Rule, Strategy, Visibility, WeightedDestination, DEFAULT_REDIRECT, REDIRECT_STATUSES};
+
+use crate::error::ClientError;
+use crate::output::Tabular;
+use crate::session::Session;
+
+/// A checked-in file describing the aliases that should exist on the server.
+///
+/// ```yaml
+/// aliases:
+///   docs: https://docs.example.com/
+///   oncall:
+///     to: https://pager.example.com/schedules
+///     redirect: 307
+///     visibility: authenticated
+///     description: Who's on call this week
+///     tags: [ops, pager]
+///   guide:
+///     destinations:
+///       - to: https://docs.example.com/guide
+///         weight: 9
+///       - to: https://beta.docs.example.com/guide
+///     strategy: sticky
+///   wiki:
+///     to: https://wiki.example.com/
+///     rules:
+///       - when: network
+///         cidrs: [10.0.0.0/8]
+///         to: https://wiki.internal.example.com/
+///       # In the server's timezone; 22:00 to 06:00 would run overnight.
+///       - when: schedule
+///         days: [mon, tue, wed, thu, fri]
+///         from: "09:00"
+///         until: "17:00"
+///         to: https://wiki.example.com/office-hours
+/// ```
+#[derive(Debug, Deserialize)]
+struct ManifestFile {
+    #[serde(default)]
+    aliases: BTreeMap<String, Entry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Entry {
+    Destination(String),
+    Detailed {
+        #[serde(default)]
+        to: String,
+        #[serde(default)]
+        destinations: Vec<WeightedDestination>,
+        #[serde(default)]
+        strategy: Strategy,
+        #[serde(default)]
+        rules: Vec<Rule>,
+        #[serde(default)]
+        visibility: Visibility,
+        #[serde(default)]
+        redirect: Option<u16>,
+        #[serde(default)]
+        description: String,
+        #[serde(default)]
+        tags: Vec<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Desired {
+    pub to: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub destinations: Vec<WeightedDestination>,
+    pub strategy: Strategy,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<Rule>,
+    pub visibility: Visibility,
+    pub redirect: u16,
+    pub description: String,
+    pub tags: Vec<String>,
+}
+
+impl Desired {
+    fn matches(&self, info: &AliasInfo) -> bool {
+        info.destination == self.to
+            && info.destinations == self.destinations
+            && info.strategy == self.strategy
+            && info.rules == self.rules
+            && info.visibility == self.visibility
+            && info.redirect == self.redirect
+            && info.description == self.description
+            && info.tags == self.tags
+    }
+}
+
+#[derive(Debug)]
+pub struct Manifest {
+    pub aliases: BTreeMap<String, Desired>,
+}
+
+impl Manifest {
+    pub fn load(path: impl AsRef<Path>) -> Result<Manifest, ClientError> {
+        let text = std::fs::read_to_string(path)?;
+        let file: ManifestFile = serde_yaml::from_str(&text)
+            .map_err(anyhow::Error::from)?;
+
+        let mut aliases = BTreeMap::new();
+        for (from, entry) in file.aliases {
+            let (to, destinations, strategy, rules, visibility, redirect, description, tags) = match entry {
+                Entry::Destination(to) => {
+                    (to, Vec::new(), Strategy::default(), Vec::new(), Visibility::default(), None, String::new(), Vec::new())
+                }
+                Entry::Detailed { to, destinations, strategy, rules, visibility, redirect, description, tags } => {
+                    (to, destinations, strategy, rules, visibility, redirect, description, tags)
+                }
+            };
+
+            // Normalize the same way the server does so unchanged entries don't show up as updates.
+            let normalize = |to: &str| Url::parse(to)
+                .map(|u| u.to_string())
+                .map_err(|e| anyhow::anyhow!("Invalid destination for {}: {}", from, e));
+            let destinations = destinations.into_iter()
+                .map(|d| Ok(WeightedDestination { to: normalize(&d.to)?, weight: d.weight }))
+                .collect::<Result<Vec<_>, anyhow::Error>>()?;
+            let rules = rules.into_iter()
+                .map(|r| Ok(Rule { to: normalize(&r.to)?, condition: r.condition }))
+                .collect::<Result<Vec<_>, anyhow::Error>>()?;
+            let to = match destinations.first() {
+                Some(first) => first.to.clone(),
+                None => normalize(&to)?,
+            };
+            let redirect = redirect.unwrap_or(DEFAULT_REDIRECT);
+            if !REDIRECT_STATUSES.contains(&redirect) {
+                return Err(anyhow::anyhow!("Invalid redirect status {} for {}", redirect, from).into());
+            }
+
+            let tags = normalize_tags(&tags)
+                .map_err(|e| anyhow::anyhow!("{} for {}", e, from))?;
+
+            aliases.insert(from, Desired { to, destinations, strategy, rules, visibility, redirect, description, tags });
+        }
+
+        Ok(Manifest { aliases })
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum Change {
+    Create {
+        alias: String,
+        #[serde(flatten)]
+        want: Desired,
+    },
+    Update {
+        alias: String,
+        from: String,
+        #[serde(flatten)]
+        want: Desired,
+    },
+    Delete { alias: String },
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Plan {
+    pub changes: Vec<Change>,
+    pub applied: bool,
+}
+
+impl Plan {
+    pub fn new(manifest: &Manifest, current: &[AliasInfo], username: &str, prune: bool) -> Plan {
+        let existing: BTreeMap<&str, &AliasInfo> = current.iter()
+            .map(|a| (a.alias.as_str(), a))
+            .collect();
+
+        let mut changes = Vec::new();
+
+        for (alias, want) in &manifest.aliases {
+            match existing.get(alias.as_str()) {
+                None => changes.push(Change::Create {
+                    alias: alias.clone(),
+                    want: want.clone(),
+                }),
+                Some(info) if !want.matches(info) || !info.managed => {
+                    changes.push(Change::Update {
+                        alias: alias.clone(),
+                        from: info.destination.clone(),
+                        want: want.clone(),
+                    })
+                }
+                Some(_) => {}
+            }
+        }
+
+        if prune {
+            // The server only lets users delete their own aliases, so only those can be pruned.
+            for info in current {
+                if info.owner == username && !manifest.aliases.contains_key(&info.alias) {
+                    changes.push(Change::Delete { alias: info.alias.clone() });
+                }
+            }
+        }
+
+        Plan { changes, applied: false }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn print(&self) {
+        for change in &self.changes {
+            match change {
+                Change::Create { alias, want } => eprintln!("  + {} -> {} ({})", alias, want.to, want.redirect),
+                Change::Update { alias, from, want } => {
+                    eprintln!("  ~ {}: {} -> {} ({})", alias, from, want.to, want.redirect)
+                }
+                Change::Delete { alias } => eprintln!("  - {}", alias),
+            }
+        }
+    }
+
+    pub async fn apply(&mut self, session: &mut Session) -> Result<(), ClientError> {
+        for change in &self.changes {
+            match change {
+                Change::Create { alias, want } | Change::Update { alias, want, .. } => {
+                    info!("Setting alias {} to {}", alias, want.to);
+                    let form = AliasForm {
+                        from: alias.clone(),
+                        domain: None,
+                        to: want.to.clone(),
+                        canonical: None,
+                        destinations: want.destinations.clone(),
+                        strategy: want.strategy,
+                        rules: want.rules.clone(),
+                        visibility: want.visibility,
+                        managed: true,
+                        redirect: Some(want.redirect),
+                        description: want.description.clone(),
+                        tags: want.tags.clone(),
+                    };
+                    session.call(&api::SaveAlias(form)).await?;
+                }
+                Change::Delete { alias } => {
+                    info!("Deleting alias {}", alias);
+                    session.call(&api::DeleteAlias { alias: alias.clone(), managed: true }).await?;
+                }
+            };
+        }
+
+        self.applied = true;
+        Ok(())
+    }
+}
+
+impl Tabular for Plan {
+    fn headers(&self) -> &'static [&'static str] {
+        &["ACTION", "ALIAS", "FROM", "TO"]
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.changes.iter()
+            .map(|c| match c {
+                Change::Create { alias, want } => {
+                    vec!["create".into(), alias.clone(), String::new(), want.to.clone()]
+                }
+                Change::Update { alias, from, want } => {
+                    vec!["update".into(), alias.clone(), from.clone(), want.to.clone()]
+                }
+                Change::Delete { alias } => {
+                    vec!["delete".into(), alias.clone(), String::new(), String::new()]
+                }
+            })
+            .collect()
+    }
+}
+
+pub fn confirm() -> Result<bool, ClientError> {
+    eprint!("Apply these changes? [y/N] ");
+    std::io::stderr().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
@@ -0,0 +1,103 @@
+use alias::client::{self, ApiError};
+
+/// Exit codes, kept stable so scripts can branch on them.
+pub const EXIT_OTHER: i32 = 1;
+pub const EXIT_AUTH: i32 = 2;
+pub const EXIT_NOT_FOUND: i32 = 3;
+pub const EXIT_VALIDATION: i32 = 4;
+pub const EXIT_NETWORK: i32 = 5;
+pub const EXIT_SERVER: i32 = 6;
+
+pub const EXIT_CODE_HELP: &str = "\
+EXIT CODES:
+    0    Success
+    1    Other failure, e.g. bad arguments or an unreadable file
+    2    Authentication failed or the session isn't allowed to do that
+    3    The alias doesn't exist
+    4    The server rejected the request as invalid
+    5    Couldn't reach the server
+    6    The server had an internal error";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    #[error("Authentication failed: {0}")]
+    Auth(ApiError),
+    #[error("Not found: {0}")]
+    NotFound(ApiError),
+    #[error("Rejected by the server: {0}")]
+    Validation(ApiError),
+    #[error("Couldn't reach the server: {0}")]
+    Network(#[from] reqwest::Error),
+    #[error("The server had an error: {0}")]
+    Server(ApiError),
+    #[error("{0}")]
+    Other(#[from] anyhow::Error),
+}
+
+impl From<url::ParseError> for ClientError {
+    fn from(e: url::ParseError) -> Self {
+        ClientError::Other(e.into())
+    }
+}
+
+impl From<std::io::Error> for ClientError {
+    fn from(e: std::io::Error) -> Self {
+        ClientError::Other(e.into())
+    }
+}
+
+impl From<clap::Error> for ClientError {
+    fn from(e: clap::Error) -> Self {
+        ClientError::Other(anyhow::anyhow!("{}", e))
+    }
+}
+
+impl From<client::Error> for ClientError {
+    fn from(e: client::Error) -> Self {
+        match e {
+            client::Error::Auth(e) => ClientError::Auth(e),
+            client::Error::NotFound(e) => ClientError::NotFound(e),
+            client::Error::Validation(e) => ClientError::Validation(e),
+            client::Error::Server(e) => ClientError::Server(e),
+            client::Error::Network(e) => ClientError::Network(e),
+            e @ client::Error::Url(_) | e @ client::Error::Unexpected(_) => ClientError::Other(e.into()),
+        }
+    }
+}
+
+impl ClientError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ClientError::Auth(_) => EXIT_AUTH,
+            ClientError::NotFound(_) => EXIT_NOT_FOUND,
+            ClientError::Validation(_) => EXIT_VALIDATION,
+            ClientError::Network(_) => EXIT_NETWORK,
+            ClientError::Server(_) => EXIT_SERVER,
+            ClientError::Other(_) => EXIT_OTHER,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ClientError::Auth(_) => "auth",
+            ClientError::NotFound(_) => "not_found",
+            ClientError::Validation(_) => "validation",
+            ClientError::Network(_) => "network",
+            ClientError::Server(_) => "server",
+            ClientError::Other(_) => "other",
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut v = match self {
+            ClientError::Auth(e)
+            | ClientError::NotFound(e)
+            | ClientError::Validation(e)
+            | ClientError::Server(e) => serde_json::to_value(e).unwrap(),
+            ClientError::Network(e) => serde_json::json!({ "message": e.to_string() }),
+            ClientError::Other(e) => serde_json::json!({ "message": e.to_string() }),
+        };
+        v["error"] = self.kind().into();
+        v
+    }
+}
@@ -0,0 +1,470 @@
+use std::borrow::Cow;
+use std::io::Stdout;
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
+use fuzzy_matcher::FuzzyMatcher;
+use fuzzy_matcher::skim::SkimMatcherV2;
+use tui::{Frame, Terminal};
+use tui::backend::CrosstermBackend;
+use tui::layout::{Constraint, Direction, Layout, Rect};
+use tui::style::{Color, Modifier, Style};
+use tui::text::{Span, Spans};
+use tui::widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph, Wrap};
+use url::Url;
+
+use alias::model::{AliasForm, AliasInfo, Rule, Strategy, Visibility, WeightedDestination};
+
+use crate::error::ClientError;
+use crate::session::Session;
+
+type Backend = CrosstermBackend<Stdout>;
+
+const HELP: &str = "/ search  n new  e edit  d delete  r refresh  q quit";
+
+enum Mode {
+    Browse,
+    Search,
+    Edit(EditForm),
+    ConfirmDelete(String),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Alias,
+    Destination,
+}
+
+struct EditForm {
+    alias: String,
+    destination: String,
+    redirect: Option<u16>,
+    // Not editable here, but carried along so saving doesn't clear them.
+    description: String,
+    tags: Vec<String>,
+    canonical: Option<String>,
+    destinations: Vec<WeightedDestination>,
+    strategy: Strategy,
+    rules: Vec<Rule>,
+    visibility: Visibility,
+    field: Field,
+    is_new: bool,
+}
+
+impl EditForm {
+    fn current(&mut self) -> &mut String {
+        match self.field {
+            Field::Alias => &mut self.alias,
+            Field::Destination => &mut self.destination,
+        }
+    }
+}
+
+/// What the event loop should do after a key press; anything that talks to the server happens there.
+enum Action {
+    Nothing,
+    Quit,
+    Refresh,
+    Save(AliasForm),
+    Delete(String),
+}
+
+struct App {
+    aliases: Vec<AliasInfo>,
+    query: String,
+    visible: Vec<usize>,
+    list: ListState,
+    mode: Mode,
+    status: String,
+    matcher: SkimMatcherV2,
+}
+
+impl App {
+    fn new(aliases: Vec<AliasInfo>) -> App {
+        let mut app = App {
+            aliases,
+            query: String::new(),
+            visible: Vec::new(),
+            list: ListState::default(),
+            mode: Mode::Browse,
+            status: HELP.to_string(),
+            matcher: SkimMatcherV2::default(),
+        };
+        app.refilter();
+        app
+    }
+
+    fn set_aliases(&mut self, aliases: Vec<AliasInfo>) {
+        self.aliases = aliases;
+        self.refilter();
+    }
+
+    /// Orders the visible aliases by how well they fuzzy-match the search query.
+    fn refilter(&mut self) {
+        let mut scored: Vec<(i64, usize)> = self.aliases.iter()
+            .enumerate()
+            .filter_map(|(i, a)| {
+                if self.query.is_empty() {
+                    return Some((0, i));
+                }
+                let haystack = format!("{} {} {} {} {}", a.alias, a.destination, a.owner, a.description, a.tags.join(" "));
+                self.matcher.fuzzy_match(&haystack, &self.query).map(|score| (score, i))
+            })
+            .collect();
+
+        scored.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+        self.visible = scored.into_iter().map(|(_, i)| i).collect();
+        self.list.select(if self.visible.is_empty() { None } else { Some(0) });
+    }
+
+    fn selected(&self) -> Option<&AliasInfo> {
+        self.list.selected()
+            .and_then(|i| self.visible.get(i))
+            .map(|&i| &self.aliases[i])
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        if self.visible.is_empty() {
+            return;
+        }
+        let last = self.visible.len() as isize - 1;
+        let current = self.list.selected().unwrap_or(0) as isize;
+        self.list.select(Some((current + delta).max(0).min(last) as usize));
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> Action {
+        if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+            return Action::Quit;
+        }
+
+        match std::mem::replace(&mut self.mode, Mode::Browse) {
+            Mode::Browse => match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Action::Quit,
+                KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+                KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+                KeyCode::PageDown => self.move_selection(10),
+                KeyCode::PageUp => self.move_selection(-10),
+                KeyCode::Char('/') => self.mode = Mode::Search,
+                KeyCode::Char('r') => return Action::Refresh,
+                KeyCode::Char('n') => {
+                    self.mode = Mode::Edit(EditForm {
+                        alias: String::new(),
+                        destination: String::new(),
+                        redirect: None,
+                        description: String::new(),
+                        tags: Vec::new(),
+                        canonical: None,
+                        destinations: Vec::new(),
+                        strategy: Strategy::default(),
+                        rules: Vec::new(),
+                        visibility: Visibility::default(),
+                        field: Field::Alias,
+                        is_new: true,
+                    });
+                }
+                KeyCode::Char('e') | KeyCode::Enter => {
+                    if let Some(a) = self.selected().cloned() {
+                        self.mode = Mode::Edit(EditForm {
+                            alias: a.alias.clone(),
+                            destination: a.destination.clone(),
+                            redirect: Some(a.redirect),
+                            description: a.description.clone(),
+                            tags: a.tags.clone(),
+                            canonical: a.canonical.clone(),
+                            destinations: a.destinations.clone(),
+                            strategy: a.strategy,
+                            rules: a.rules.clone(),
+                            visibility: a.visibility,
+                            field: Field::Destination,
+                            is_new: false,
+                        });
+                    }
+                }
+                KeyCode::Char('d') => {
+                    if let Some(a) = self.selected().cloned() {
+                        self.status = format!("Delete {}? (y/n)", a.alias);
+                        self.mode = Mode::ConfirmDelete(a.alias);
+                    }
+                }
+                _ => {}
+            },
+            Mode::Search => {
+                self.mode = Mode::Search;
+                match key.code {
+                    KeyCode::Esc => {
+                        self.query.clear();
+                        self.refilter();
+                        self.mode = Mode::Browse;
+                    }
+                    KeyCode::Enter => self.mode = Mode::Browse,
+                    KeyCode::Down => self.move_selection(1),
+                    KeyCode::Up => self.move_selection(-1),
+                    KeyCode::Backspace => {
+                        self.query.pop();
+                        self.refilter();
+                    }
+                    KeyCode::Char(c) => {
+                        self.query.push(c);
+                        self.refilter();
+                    }
+                    _ => {}
+                }
+            }
+            Mode::Edit(mut form) => match key.code {
+                KeyCode::Esc => self.status = HELP.to_string(),
+                KeyCode::Enter => {
+                    return Action::Save(AliasForm {
+                        from: form.alias,
+                        // The session sends its domain with every request.
+                        domain: None,
+                        to: form.destination,
+                        managed: false,
+                        redirect: form.redirect,
+                        description: form.description,
+                        tags: form.tags,
+                        // Typing a destination turns an alias of another alias back into a plain one.
+                        canonical: form.canonical.filter(|_| form.destination.is_empty()),
+                        // Likewise, changing the first destination replaces the whole split with it.
+                        destinations: if form.destinations.first().map(|d| &d.to) == Some(&form.destination) {
+                            form.destinations
+                        } else {
+                            Vec::new()
+                        },
+                        strategy: form.strategy,
+                        rules: form.rules,
+                        visibility: form.visibility,
+                    });
+                }
+                KeyCode::Tab | KeyCode::BackTab if form.is_new => {
+                    form.field = match form.field {
+                        Field::Alias => Field::Destination,
+                        Field::Destination => Field::Alias,
+                    };
+                    self.mode = Mode::Edit(form);
+                }
+                KeyCode::Backspace => {
+                    form.current().pop();
+                    self.mode = Mode::Edit(form);
+                }
+                KeyCode::Char(c) => {
+                    form.current().push(c);
+                    self.mode = Mode::Edit(form);
+                }
+                _ => self.mode = Mode::Edit(form),
+            },
+            Mode::ConfirmDelete(alias) => {
+                self.status = HELP.to_string();
+                if let KeyCode::Char('y') | KeyCode::Char('Y') = key.code {
+                    return Action::Delete(alias);
+                }
+            }
+        }
+
+        Action::Nothing
+    }
+}
+
+/// Runs the interactive browser until the user quits.
+///
+/// The first listing happens before the terminal is taken over, so a password prompt still works.
+pub async fn run(session: &mut Session) -> Result<(), ClientError> {
+    let aliases = session.list_aliases().await?;
+    let mut app = App::new(aliases);
+
+    enable_raw_mode().map_err(anyhow::Error::from)?;
+    let mut stdout = std::io::stdout();
+    execute!(stdout, EnterAlternateScreen).map_err(anyhow::Error::from)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+
+    session.set_prompts(false);
+    let res = event_loop(&mut terminal, &mut app, session).await;
+    session.set_prompts(true);
+
+    disable_raw_mode().ok();
+    execute!(terminal.backend_mut(), LeaveAlternateScreen).ok();
+    terminal.show_cursor().ok();
+
+    res
+}
+
+async fn event_loop(terminal: &mut Terminal<Backend>, app: &mut App, session: &mut Session) -> Result<(), ClientError> {
+    loop {
+        terminal.draw(|f| draw(f, app))?;
+
+        let event = tokio::task::block_in_place(event::read).map_err(anyhow::Error::from)?;
+        let key = match event {
+            Event::Key(key) => key,
+            _ => continue,
+        };
+
+        match app.handle_key(key) {
+            Action::Nothing => {}
+            Action::Quit => return Ok(()),
+            Action::Refresh => {
+                app.set_aliases(session.list_aliases().await?);
+                app.status = format!("Loaded {} aliases.", app.aliases.len());
+            }
+            Action::Save(form) => {
+                let name = form.from.clone();
+                app.status = match validate(&form) {
+                    Err(msg) => msg,
+                    Ok(()) => match session.save_alias(form).await {
+                        Ok(()) => {
+                            app.set_aliases(session.list_aliases().await?);
+                            format!("Saved {}.", name)
+                        }
+                        Err(e) => e.to_string(),
+                    },
+                };
+            }
+            Action::Delete(alias) => {
+                app.status = match session.delete_alias(&alias).await {
+                    Ok(()) => {
+                        app.set_aliases(session.list_aliases().await?);
+                        format!("Deleted {}.", alias)
+                    }
+                    Err(e) => e.to_string(),
+                };
+            }
+        }
+    }
+}
+
+fn validate(form: &AliasForm) -> Result<(), String> {
+    if form.from.is_empty() {
+        return Err("The alias can't be empty.".to_string());
+    }
+    if form.canonical.is_some() {
+        return Ok(());
+    }
+    Url::parse(&form.to)
+        .map(|_| ())
+        .map_err(|e| format!("Invalid destination: {}", e))
+}
+
+fn draw(f: &mut Frame<Backend>, app: &mut App) {
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(3), Constraint::Min(3), Constraint::Length(1)].as_ref())
+        .split(f.size());
+
+    let searching = matches!(app.mode, Mode::Search);
+    let search = Paragraph::new(app.query.as_str())
+        .block(Block::default()
+            .borders(Borders::ALL)
+            .title("Search")
+            .border_style(highlight_if(searching)));
+    f.render_widget(search, rows[0]);
+    if searching {
+        f.set_cursor(rows[0].x + 1 + app.query.chars().count() as u16, rows[0].y + 1);
+    }
+
+    let panes = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(40), Constraint::Percentage(60)].as_ref())
+        .split(rows[1]);
+
+    let items: Vec<ListItem> = app.visible.iter()
+        .map(|&i| ListItem::new(app.aliases[i].alias.as_str()))
+        .collect();
+    let list = List::new(items)
+        .block(Block::default()
+            .borders(Borders::ALL)
+            .title(format!("Aliases ({}/{})", app.visible.len(), app.aliases.len())))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+        .highlight_symbol("> ");
+    f.render_stateful_widget(list, panes[0], &mut app.list);
+
+    let detail = match app.selected() {
+        Some(a) => vec![
+            field("Alias", &a.alias),
+            match &a.canonical {
+                Some(c) => field("Alias of", c),
+                None if !a.destinations.is_empty() => field("Destination", a.destinations.iter()
+                    .map(|d| format!("{} ({})", d.to, d.weight))
+                    .collect::<Vec<_>>()
+                    .join(", ")),
+                None => field("Destination", &a.destination),
+            },
+            field("Strategy", a.strategy.as_str()),
+            field("Rules", a.rules.len().to_string()),
+            field("Visibility", a.visibility.as_str()),
+            field("Health", match &a.health {
+                None => "not checked yet".to_string(),
+                Some(h) if h.dead => format!("dead ({})", h.error.clone()
+                    .or_else(|| h.status.map(|s| format!("HTTP {}", s)))
+                    .unwrap_or_default()),
+                Some(h) => format!("ok, {}ms", h.latency_ms.unwrap_or_default()),
+            }),
+            field("Description", &a.description),
+            field("Tags", a.tags.join(", ")),
+            field("Owner", &a.owner),
+            Spans::from(vec![
+                Span::styled(format!("{:<12}", "Redirect"), Style::default().add_modifier(Modifier::BOLD)),
+                Span::raw(a.redirect.to_string()),
+            ]),
+            field("Managed", if a.managed { "yes" } else { "no" }),
+        ],
+        None => vec![Spans::from("No alias selected.")],
+    };
+    let detail = Paragraph::new(detail)
+        .block(Block::default().borders(Borders::ALL).title("Details"))
+        .wrap(Wrap { trim: false });
+    f.render_widget(detail, panes[1]);
+
+    f.render_widget(Paragraph::new(app.status.as_str()), rows[2]);
+
+    if let Mode::Edit(form) = &app.mode {
+        draw_form(f, form);
+    }
+}
+
+fn draw_form(f: &mut Frame<Backend>, form: &EditForm) {
+    let area = centered(f.size(), 70, 8);
+    let title = if form.is_new { "New alias" } else { "Edit alias" };
+
+    let text = vec![
+        Spans::from(Span::styled("Alias", highlight_if(form.field == Field::Alias))),
+        Spans::from(form.alias.as_str()),
+        Spans::from(Span::styled("Destination", highlight_if(form.field == Field::Destination))),
+        Spans::from(form.destination.as_str()),
+        Spans::from(Span::styled("Enter saves, Esc cancels", Style::default().fg(Color::DarkGray))),
+    ];
+
+    f.render_widget(Clear, area);
+    f.render_widget(Paragraph::new(text)
+                        .block(Block::default().borders(Borders::ALL).title(title)),
+                    area);
+
+    let (row, len) = match form.field {
+        Field::Alias => (1, form.alias.chars().count()),
+        Field::Destination => (3, form.destination.chars().count()),
+    };
+    f.set_cursor(area.x + 1 + len as u16, area.y + 1 + row);
+}
+
+fn field<'a>(name: &'a str, value: impl Into<Cow<'a, str>>) -> Spans<'a> {
+    Spans::from(vec![
+        Span::styled(format!("{:<12}", name), Style::default().add_modifier(Modifier::BOLD)),
+        Span::raw(value),
+    ])
+}
+
+fn highlight_if(cond: bool) -> Style {
+    if cond {
+        Style::default().fg(Color::Yellow)
+    } else {
+        Style::default()
+    }
+}
+
+fn centered(area: Rect, width_percent: u16, height: u16) -> Rect {
+    let width = area.width * width_percent / 100;
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + area.height.saturating_sub(height) / 2,
+        width,
+        height: height.min(area.height),
+    }
+}
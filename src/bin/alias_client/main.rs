@@ -1,14 +1,26 @@
-use clap::{App, Arg, AppSettings};
-use reqwest::redirect::Policy;
+use clap::{App, Arg, AppSettings, ArgMatches};
 use url::Url;
-use alias::model::{Login, AliasForm};
+use alias::client::AliasClient;
+use alias::model::{AliasForm, Strategy, Visibility, WeightedDestination};
+
+use crate::error::ClientError;
+use crate::output::{AliasRecord, Deleted, OutputFormat, ProfileRecord, SearchResults};
+use crate::profiles::ClientConfig;
+use crate::session::Session;
 
 #[macro_use]
 extern crate tracing;
 
+mod apply;
+mod error;
+mod interactive;
+mod output;
+mod profiles;
+mod session;
+
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() {
     better_panic::install();
     dotenv::dotenv().ok();
 
@@ -19,6 +31,7 @@ async fn main() -> anyhow::Result<()> {
         .about("An alias for redirection with an aliasd server.");
 
     let matches = App::new("alias-client")
+        .after_help(error::EXIT_CODE_HELP)
         .arg(Arg::new("verbosity")
             .takes_value(false)
             .multiple_occurrences(true)
@@ -29,7 +42,6 @@ async fn main() -> anyhow::Result<()> {
             .env("ALIAS_USERNAME")
             .takes_value(true)
             .required(false)
-            .default_value("")
             .short('u'))
         .arg(Arg::new("server")
             .about("The alias server to connect to")
@@ -38,6 +50,30 @@ async fn main() -> anyhow::Result<()> {
             .takes_value(true)
             .short('s')
         )
+        .arg(Arg::new("domain")
+            .about("The server's domain to work in, instead of the one its URL's host is for. Empty for the default domain.")
+            .env("ALIAS_DOMAIN")
+            .takes_value(true)
+            .long("domain"))
+        .arg(Arg::new("profile")
+            .about("The saved profile to use; defaults to the one last logged in with")
+            .env("ALIAS_PROFILE")
+            .takes_value(true)
+            .short('p')
+            .long("profile"))
+        .arg(Arg::new("output")
+            .about("How results are printed. Errors go to stderr, as JSON when using json.")
+            .short('o')
+            .long("output")
+            .takes_value(true)
+            .possible_values(&["json", "table", "plain"])
+            .default_value("table"))
+        .subcommand(
+            App::new("login")
+                .about("Logs in and saves the session to the profile, along with the server and username."))
+        .subcommand(
+            App::new("logout")
+                .about("Forgets the profile's saved session."))
         .subcommand(
             App::new("check")
                 .about("Retrieves the current value of an alias from the alias server.")
@@ -52,107 +88,269 @@ async fn main() -> anyhow::Result<()> {
                 .arg(alias_arg)
                 .arg(
                     Arg::new("destination")
-                        .about("The destination for redirection when the alias is used.")
-                        .required(true)
+                        .about("The destination for redirection when the alias is used. \
+                        Give more than one to split requests between them.")
+                        .required_unless_present("canonical")
+                        .multiple_values(true)
                         .index(2)
                 )
+                .arg(
+                    Arg::new("weight")
+                        .about("The weight of each destination, in the order they're given. Defaults to 1.")
+                        .short('w')
+                        .long("weight")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                )
+                .arg(
+                    Arg::new("strategy")
+                        .about("How a request picks among several destinations.")
+                        .long("strategy")
+                        .takes_value(true)
+                        .possible_values(&Strategy::ALL)
+                        .default_value("weighted")
+                )
+                .arg(
+                    Arg::new("visibility")
+                        .about("Who the alias resolves for.")
+                        .long("visibility")
+                        .takes_value(true)
+                        .possible_values(&Visibility::ALL)
+                        .default_value("public")
+                )
+                .arg(
+                    Arg::new("canonical")
+                        .about("Makes the alias resolve through another alias instead of a destination.")
+                        .long("of")
+                        .takes_value(true)
+                        .conflicts_with("destination")
+                )
+                .arg(
+                    Arg::new("redirect")
+                        .about("The HTTP status used for the redirect.")
+                        .long("redirect")
+                        .takes_value(true)
+                        .possible_values(&["301", "302", "307", "308"])
+                )
+                .arg(
+                    Arg::new("description")
+                        .about("A description to help people find the alias.")
+                        .short('d')
+                        .long("description")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::new("tag")
+                        .about("A tag for the alias. May be given more than once.")
+                        .short('t')
+                        .long("tag")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                )
         )
+        .subcommand(
+            App::new("apply")
+                .about("Reconciles the server's aliases against a declarative alias file.")
+                .arg(Arg::new("file")
+                    .about("A YAML file with an `aliases` map of alias names to destinations.")
+                    .short('f')
+                    .long("file")
+                    .takes_value(true)
+                    .required(true))
+                .arg(Arg::new("prune")
+                    .about("Also deletes your aliases that aren't in the file.")
+                    .long("prune"))
+                .arg(Arg::new("dry-run")
+                    .about("Only prints the plan.")
+                    .long("dry-run"))
+                .arg(Arg::new("yes")
+                    .about("Applies the plan without asking for confirmation.")
+                    .short('y')
+                    .long("yes"))
+        )
+        .subcommand(
+            App::new("search")
+                .about("Searches alias names, destinations, descriptions and tags.")
+                .arg(Arg::new("query")
+                    .required(true)
+                    .multiple_values(true)
+                    .index(1)))
+        .subcommand(
+            App::new("tui")
+                .about("Browses, searches and edits aliases interactively."))
         .setting(AppSettings::SubcommandRequired)
         .get_matches();
 
-    let username = matches.value_of_t::<String>("username")?;
     let verbosity = matches.occurrences_of("verbosity");
 
     let log_level = log_level(verbosity);
     let sub = tracing_subscriber::FmtSubscriber::builder()
         .with_max_level(log_level)
+        .with_writer(std::io::stderr)
         .finish();
 
     tracing::subscriber::set_global_default(sub).unwrap();
 
-    let server_url = matches.value_of_t::<Url>("server")?;
+    let format = matches.value_of_t::<OutputFormat>("output").unwrap();
 
-    match matches.subcommand().unwrap() {
-        ("check", m) => {
-            let alias = m.value_of_t::<String>("alias")?;
-            let url = server_url.join(&alias)?;
+    if let Err(e) = run(&matches, format).await {
+        output::emit_error(format, &e);
+        std::process::exit(e.exit_code());
+    }
+}
 
-            let client = reqwest::ClientBuilder::default()
-                .redirect(Policy::custom(|a| {
-                    a.stop()
-                }))
-                .build()?;
+async fn run(matches: &ArgMatches, format: OutputFormat) -> Result<(), ClientError> {
+    let mut config = ClientConfig::load()?;
+    let profile_name = matches.value_of("profile")
+        .map(String::from)
+        .unwrap_or_else(|| config.default_name());
+    let stored = config.profiles.get(&profile_name).cloned();
 
-            let resp = client.get(url)
-                .send()
-                .await?;
+    let mut client = match (matches.value_of("server"), &stored) {
+        (Some(s), _) => AliasClient::new(s)?,
+        (None, Some(p)) => AliasClient::new(&p.server)?,
+        (None, None) => {
+            return Err(anyhow::anyhow!("No server given. Pass -s, or set up a profile with `alias-client login`.").into());
+        }
+    };
+    if let Some(d) = matches.value_of("domain") {
+        client = client.with_domain(d);
+    }
+    let server_url = client.server_url().clone();
+    let username = matches.value_of("username")
+        .map(String::from)
+        .or_else(|| stored.as_ref().map(|p| p.username.clone()))
+        .unwrap_or_default();
 
-            if !resp.status().is_redirection() {
-                warn!("Didn't get a redirect: {}", resp.status());
-                std::process::exit(1);
-            } else {
-                let dest = resp.headers()
-                    .get("Location")
-                    .expect("Got a redirect but no location.");
+    // Tokens are saved to the profile being set up with `login`, or to an existing one when it's
+    // used as is. A one-off -s or -u doesn't change what the profile points at.
+    let persist = match &stored {
+        _ if matches.subcommand_name() == Some("login") => true,
+        Some(p) => p.server == server_url.as_str() && p.username == username,
+        None => false,
+    };
+    let mut session = Session::new(
+        client,
+        username.clone(),
+        stored.as_ref(),
+        if persist { Some(profile_name.clone()) } else { None },
+    );
 
-                let dest = dest.to_str().expect("Got URL, but it wasn't valid UTF-8");
-                info!("Alias {} redirects to {}", &alias, dest);
-            }
+    match matches.subcommand().unwrap() {
+        ("login", _) => {
+            let token = session.login().await?;
+            output::emit(format, &ProfileRecord {
+                profile: profile_name,
+                server: server_url.to_string(),
+                username,
+                expires: Some(token.expires),
+            });
         }
-        (op, m) => {
+        ("logout", _) => {
+            let mut profile = stored
+                .ok_or_else(|| anyhow::anyhow!("There's no profile named {}.", &profile_name))?;
+            profile.token = None;
+            profile.expires = None;
+            config.profiles.insert(profile_name.clone(), profile);
+            config.save()?;
+            output::emit(format, &ProfileRecord {
+                profile: profile_name,
+                server: server_url.to_string(),
+                username,
+                expires: None,
+            });
+        }
+        ("search", m) => {
+            let query = m.values_of("query").unwrap().collect::<Vec<_>>().join(" ");
+            let hits = session.search(&query).await?;
+            output::emit(format, &SearchResults(hits));
+        }
+        ("tui", _) => {
+            interactive::run(&mut session).await?;
+        }
+        ("check", m) => {
             let alias = m.value_of_t::<String>("alias")?;
-            let dest = if op == "add" {
-                m.value_of_t::<Url>("destination")?
-            } else {
-                Url::parse("http://localhost").unwrap()
-            };
-
-            let pass = std::env::var("ALIAS_PASSWORD")
-                .unwrap_or_else(|_| {
-                    rpassword::read_password_from_tty(Some("Please enter your password: ")).unwrap()
-                });
+            let dest = session.client().resolve(&alias).await?;
+            debug!("Alias {} redirects to {}", &alias, &dest);
+            output::emit(format, &AliasRecord { alias, destination: dest, canonical: None });
+        }
+        ("apply", m) => {
+            let manifest = apply::Manifest::load(m.value_of("file").unwrap())?;
 
-            let client = reqwest::ClientBuilder::default()
-                .cookie_store(true)
-                .build()?;
+            let current = session.list_aliases().await?;
+            let mut plan = apply::Plan::new(&manifest, &current, &username, m.is_present("prune"));
 
-            let login = server_url.join("login")?;
+            if plan.is_empty() {
+                info!("Aliases are up to date.");
+                output::emit(format, &plan);
+                return Ok(());
+            }
 
-            info!("Logging in as {}...", &username);
-            let resp = client.post(login)
-                .json(&Login { username, password: pass })
-                .send()
-                .await?;
+            if m.is_present("dry-run") {
+                output::emit(format, &plan);
+                return Ok(());
+            }
 
-            if !resp.status().is_success() {
-                error!("Failed to login: {}", resp.status());
-                std::process::exit(1);
+            if !m.is_present("yes") {
+                eprintln!("Plan:");
+                plan.print();
+                if !apply::confirm()? {
+                    info!("Not applying.");
+                    return Ok(());
+                }
             }
-            info!("Logged in.");
 
+            plan.apply(&mut session).await?;
+            output::emit(format, &plan);
+        }
+        (op, m) => {
+            let alias = m.value_of_t::<String>("alias")?;
             if op == "add" {
-                info!("Adding alias from {} to {}", &alias, &dest);
-                let resp = client.post(server_url.join("alias")?)
-                    .json(&AliasForm { from: alias, to: dest.into_string() })
-                    .send()
-                    .await?;
-                if !resp.status().is_success() {
-                    let body = resp.text().await?;
-                    error!("Couldn't add alias: {}", body);
-                    std::process::exit(1);
+                let canonical = m.value_of("canonical").map(String::from);
+                let dests = match &canonical {
+                    Some(_) => Vec::new(),
+                    None => m.values_of_t::<Url>("destination")?,
+                };
+                let dest = dests.first().map(Url::to_string).unwrap_or_default();
+                let weights = match m.values_of("weight") {
+                    Some(w) => w.map(|w| w.parse::<u32>()).collect::<Result<Vec<_>, _>>().map_err(anyhow::Error::from)?,
+                    None => Vec::new(),
+                };
+                if weights.len() > dests.len() {
+                    return Err(anyhow::anyhow!("Got {} weights for {} destinations.", weights.len(), dests.len()).into());
                 }
-                info!("Added alias.");
+                let destinations = if dests.len() > 1 {
+                    dests.iter()
+                        .enumerate()
+                        .map(|(i, d)| WeightedDestination { to: d.to_string(), weight: weights.get(i).copied().unwrap_or(1) })
+                        .collect()
+                } else {
+                    Vec::new()
+                };
+                info!("Adding alias from {} to {}", &alias, canonical.as_deref().unwrap_or(&dest));
+                let redirect = match m.value_of("redirect") {
+                    Some(r) => Some(r.parse::<u16>().map_err(anyhow::Error::from)?),
+                    None => None,
+                };
+                session.save_alias(AliasForm {
+                    from: alias.clone(),
+                    domain: None,
+                    to: dest.clone(),
+                    canonical: canonical.clone(),
+                    destinations,
+                    strategy: m.value_of_t::<Strategy>("strategy")?,
+                    rules: Vec::new(),
+                    visibility: m.value_of_t::<Visibility>("visibility")?,
+                    managed: false,
+                    redirect,
+                    description: m.value_of("description").unwrap_or_default().to_string(),
+                    tags: m.values_of("tag").map(|t| t.map(String::from).collect()).unwrap_or_default(),
+                }).await?;
+                output::emit(format, &AliasRecord { alias, destination: dest, canonical });
             } else {
                 info!("Deleting alias {}", &alias);
-                let resp = client.delete(server_url.join(&alias)?)
-                    .send()
-                    .await?;
-                if !resp.status().is_success() {
-                    let body = resp.text().await?;
-                    error!("Couldn't delete alias: {}", body);
-                    std::process::exit(1);
-                }
-                info!("Deleted alias.");
+                session.delete_alias(&alias).await?;
+                output::emit(format, &Deleted { alias });
             }
         }
     };
@@ -166,4 +364,4 @@ fn log_level(i: u64) -> tracing::Level {
         1 => tracing::Level::DEBUG,
         _ => tracing::Level::TRACE
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,150 @@
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use alias::model::SearchHit;
+
+use crate::error::ClientError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Json,
+    Table,
+    Plain,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(OutputFormat::Json),
+            "table" => Ok(OutputFormat::Table),
+            "plain" => Ok(OutputFormat::Plain),
+            _ => anyhow::bail!("Unknown output format {}", s),
+        }
+    }
+}
+
+/// A command result that can be printed in any of the output formats.
+///
+/// `plain` prints the rows tab-separated without headers, which is the easiest to consume from a shell.
+pub trait Tabular: Serialize {
+    fn headers(&self) -> &'static [&'static str];
+    fn rows(&self) -> Vec<Vec<String>>;
+}
+
+#[derive(Debug, Serialize)]
+pub struct AliasRecord {
+    pub alias: String,
+    pub destination: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub canonical: Option<String>,
+}
+
+impl Tabular for AliasRecord {
+    fn headers(&self) -> &'static [&'static str] {
+        &["ALIAS", "DESTINATION"]
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        let destination = match &self.canonical {
+            Some(c) => format!("alias of {}", c),
+            None => self.destination.clone(),
+        };
+        vec![vec![self.alias.clone(), destination]]
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Deleted {
+    pub alias: String,
+}
+
+impl Tabular for Deleted {
+    fn headers(&self) -> &'static [&'static str] {
+        &["DELETED"]
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        vec![vec![self.alias.clone()]]
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProfileRecord {
+    pub profile: String,
+    pub server: String,
+    pub username: String,
+    pub expires: Option<i64>,
+}
+
+impl Tabular for ProfileRecord {
+    fn headers(&self) -> &'static [&'static str] {
+        &["PROFILE", "SERVER", "USERNAME", "EXPIRES"]
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        let expires = self.expires
+            .map(|e| chrono::NaiveDateTime::from_timestamp(e, 0).to_string())
+            .unwrap_or_default();
+        vec![vec![self.profile.clone(), self.server.clone(), self.username.clone(), expires]]
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(transparent)]
+pub struct SearchResults(pub Vec<SearchHit>);
+
+impl Tabular for SearchResults {
+    fn headers(&self) -> &'static [&'static str] {
+        &["ALIAS", "DESTINATION", "DESCRIPTION", "TAGS"]
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.0.iter()
+            .map(|h| vec![h.alias.clone(), h.destination.clone(), h.description.clone(), h.tags.join(",")])
+            .collect()
+    }
+}
+
+pub fn emit<T: Tabular>(format: OutputFormat, value: &T) {
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(value).unwrap()),
+        OutputFormat::Plain => {
+            for row in value.rows() {
+                println!("{}", row.join("\t"));
+            }
+        }
+        OutputFormat::Table => print_table(value.headers(), &value.rows()),
+    }
+}
+
+pub fn emit_error(format: OutputFormat, err: &ClientError) {
+    match format {
+        OutputFormat::Json => eprintln!("{}", serde_json::to_string_pretty(&err.to_json()).unwrap()),
+        OutputFormat::Table | OutputFormat::Plain => eprintln!("error: {}", err),
+    }
+}
+
+fn print_table(headers: &[&str], rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.len()).collect();
+    for row in rows {
+        for (w, cell) in widths.iter_mut().zip(row) {
+            *w = (*w).max(cell.len());
+        }
+    }
+
+    let line = |cells: Vec<&str>| {
+        let padded: Vec<String> = cells.iter()
+            .zip(&widths)
+            .map(|(c, w)| format!("{:<width$}", c, width = w))
+            .collect();
+        println!("{}", padded.join("  ").trim_end());
+    };
+
+    line(headers.to_vec());
+    for row in rows {
+        line(row.iter().map(String::as_str).collect());
+    }
+}
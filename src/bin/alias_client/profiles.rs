@@ -0,0 +1,89 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::ClientError;
+
+/// Sessions are refreshed this long before they actually expire.
+const EXPIRY_MARGIN_SECS: i64 = 60;
+
+/// Per-user client settings, stored in `config.toml` under `dirs::config_dir()/alias-client`.
+///
+/// The file holds session tokens, so it's only readable by its owner.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ClientConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Profile {
+    pub server: String,
+    pub username: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<i64>,
+}
+
+impl Profile {
+    /// The stored token, if there is one that hasn't expired yet.
+    pub fn fresh_token(&self) -> Option<&str> {
+        let now = chrono::Local::now().timestamp();
+        match (&self.token, self.expires) {
+            (Some(t), Some(exp)) if exp - EXPIRY_MARGIN_SECS > now => Some(t),
+            _ => None,
+        }
+    }
+}
+
+impl ClientConfig {
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|d| d.join("alias-client").join("config.toml"))
+    }
+
+    pub fn load() -> Result<ClientConfig, ClientError> {
+        let path = match Self::path() {
+            Some(p) if p.exists() => p,
+            _ => return Ok(ClientConfig::default()),
+        };
+
+        let text = std::fs::read_to_string(&path)?;
+        toml::from_str(&text)
+            .map_err(|e| anyhow::anyhow!("Couldn't read {}: {}", path.display(), e).into())
+    }
+
+    pub fn save(&self) -> Result<(), ClientError> {
+        let path = Self::path()
+            .ok_or_else(|| anyhow::anyhow!("Couldn't find a config directory for this user."))?;
+        std::fs::create_dir_all(path.parent().unwrap())?;
+
+        let text = toml::to_string_pretty(self).map_err(anyhow::Error::from)?;
+
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+            options.mode(0o600);
+            // `mode` only applies when the file is created, so tighten files from older versions too.
+            if path.exists() {
+                std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+            }
+        }
+
+        let mut file = options.open(&path)?;
+        file.write_all(text.as_bytes())?;
+
+        Ok(())
+    }
+
+    /// The profile to use when none is given on the command line.
+    pub fn default_name(&self) -> String {
+        self.default_profile.clone().unwrap_or_else(|| "default".to_string())
+    }
+}
@@ -0,0 +1,120 @@
+use alias::api::{self, Endpoint};
+use alias::client::{self, AliasClient};
+use alias::model::{AliasForm, AliasInfo, LoginToken, SearchHit};
+
+use crate::error::ClientError;
+use crate::profiles::{ClientConfig, Profile};
+
+/// An authenticated connection to an aliasd server.
+///
+/// Reuses the token stored in the profile when it's still valid, and logs in again (saving the new
+/// token) when it has expired or the server rejects it.
+pub struct Session {
+    client: AliasClient,
+    username: String,
+    reused_token: bool,
+    profile: Option<String>,
+    /// Whether `login` may ask for a password on the terminal.
+    prompts: bool,
+}
+
+impl Session {
+    /// `profile` is the name of the profile new tokens are saved to, if any.
+    pub fn new(client: AliasClient,
+               username: String,
+               stored: Option<&Profile>,
+               profile: Option<String>) -> Session {
+        let token = stored
+            .filter(|p| p.server == client.server_url().as_str() && p.username == username)
+            .and_then(|p| p.fresh_token())
+            .map(String::from);
+
+        Session {
+            reused_token: token.is_some(),
+            client: match token {
+                Some(t) => client.with_token(t),
+                None => client,
+            },
+            username,
+            profile,
+            prompts: true,
+        }
+    }
+
+    /// The underlying client, for calls that don't need a login.
+    pub fn client(&self) -> &AliasClient {
+        &self.client
+    }
+
+    /// Turned off while the TUI has the terminal in raw mode, where a prompt would never return.
+    pub fn set_prompts(&mut self, allowed: bool) {
+        self.prompts = allowed;
+    }
+
+    pub async fn login(&mut self) -> Result<LoginToken, ClientError> {
+        let pass = match std::env::var("ALIAS_PASSWORD") {
+            Ok(p) => p,
+            Err(_) if self.prompts => rpassword::read_password_from_tty(Some("Please enter your password: "))?,
+            Err(_) => {
+                return Err(anyhow::anyhow!("The session expired. Run `alias-client login`, then try again.").into());
+            }
+        };
+
+        info!("Logging in as {}...", &self.username);
+        let token = self.client.login(&self.username, &pass).await?;
+        info!("Logged in.");
+        self.reused_token = false;
+
+        if let Some(name) = &self.profile {
+            let mut config = ClientConfig::load()?;
+            config.profiles.insert(name.clone(), Profile {
+                server: self.client.server_url().to_string(),
+                username: self.username.clone(),
+                token: Some(token.token.clone()),
+                expires: Some(token.expires),
+            });
+            if config.default_profile.is_none() {
+                config.default_profile = Some(name.clone());
+            }
+            config.save()?;
+        }
+
+        Ok(token)
+    }
+
+    /// Calls one of the endpoints in [`alias::api`], logging in first if there's no token, and again
+    /// if the stored one turns out to be stale.
+    pub async fn call<E: Endpoint>(&mut self, endpoint: &E) -> Result<E::Response, ClientError> {
+        if self.client.token().is_none() {
+            self.login().await?;
+        }
+
+        // Only a 401 means the token itself was refused; a 403 is about what the user asked for.
+        match self.client.call(endpoint).await {
+            Err(client::Error::Auth(e)) if e.status == 401 && self.reused_token => {
+                info!("The stored session was rejected.");
+                self.login().await?;
+                Ok(self.client.call(endpoint).await?)
+            }
+            res => Ok(res?),
+        }
+    }
+
+    pub async fn list_aliases(&mut self) -> Result<Vec<AliasInfo>, ClientError> {
+        self.call(&api::ListAliases).await
+    }
+
+    pub async fn search(&mut self, q: &str) -> Result<Vec<SearchHit>, ClientError> {
+        self.call(&api::SearchAliases { q: q.to_string() }).await
+    }
+
+    pub async fn save_alias(&mut self, form: AliasForm) -> Result<(), ClientError> {
+        self.call(&api::SaveAlias(form)).await?;
+        Ok(())
+    }
+
+    pub async fn delete_alias(&mut self, alias: &str) -> Result<(), ClientError> {
+        self.call(&api::DeleteAlias { alias: alias.to_string(), managed: false }).await?;
+        Ok(())
+    }
+}
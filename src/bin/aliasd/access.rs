@@ -0,0 +1,71 @@
+use diesel::QueryResult;
+use rocket::Request;
+use rocket::request::{FromRequest, Outcome};
+
+use alias::db::conn;
+use alias::model::{user_exists, Claims, Visibility};
+
+use crate::cache::Restriction;
+use crate::domains;
+
+/// Whoever sent the request, if they sent a token.
+///
+/// Unlike [`Claims`] this never fails and doesn't touch the database, so public redirects stay cheap;
+/// [`check`] only confirms the user still exists when an alias is actually restricted.
+pub struct Viewer(pub Option<Claims>);
+
+#[rocket::async_trait]
+impl<'a, 'r> FromRequest<'a, 'r> for Viewer {
+    type Error = ();
+
+    async fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(Viewer(Claims::unverified(request)))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Allowed,
+    LoginRequired,
+    Forbidden,
+}
+
+/// Whether `viewer` may follow an alias in `domain` with these restrictions.
+pub async fn check(domain: &str, restrictions: &[Restriction], viewer: &Viewer) -> QueryResult<Access> {
+    if restrictions.is_empty() {
+        return Ok(Access::Allowed);
+    }
+
+    let claims = match &viewer.0 {
+        Some(c) => c,
+        None => return Ok(Access::LoginRequired),
+    };
+    if !user_exists(claims.user.clone()).await? {
+        return Ok(Access::LoginRequired);
+    }
+
+    let uid = claims.user_id;
+    // Only looked up once something needs it, and then only once for the whole chain.
+    let mut domain_owner = None;
+    for r in restrictions {
+        let allowed = match r.visibility {
+            Visibility::Public | Visibility::Authenticated => true,
+            Visibility::Owner => r.owner == uid,
+            Visibility::Domain if r.owner == uid => true,
+            Visibility::Domain => match domain_owner {
+                Some(o) => o,
+                None => {
+                    let name = domain.to_string();
+                    let o = conn().with_conn(move |c| domains::is_owner(c, &name, uid)).await?;
+                    domain_owner = Some(o);
+                    o
+                }
+            },
+        };
+        if !allowed {
+            return Ok(Access::Forbidden);
+        }
+    }
+
+    Ok(Access::Allowed)
+}
@@ -0,0 +1,79 @@
+use std::io::Cursor;
+
+use rocket::Response;
+use rocket::http::{ContentType, Status};
+use rocket_contrib::json::Json;
+
+use alias::model::{self, Account, Claims, LoginFailure, PasswordChange};
+use alias::pass::create_pass_hash;
+
+use crate::logging::RequestSpan;
+
+const MIN_PASSWORD_LEN: usize = 8;
+
+#[get("/account")]
+pub async fn account(user: Claims) -> Json<Account> {
+    Json(Account {
+        user: user.user,
+        user_id: user.user_id,
+        expires: user.exp,
+    })
+}
+
+#[post("/account/password", data = "<change>")]
+pub async fn change_password(user: Claims, change: Json<PasswordChange>, span: RequestSpan) -> Response<'static> {
+    span.run(async move {
+        let mut resp = Response::new();
+        resp.set_header(ContentType::JSON);
+
+        let change = change.into_inner();
+        let valid = model::validate_user(user.user.clone(), change.old_password.as_bytes()).await;
+
+        let body = match valid {
+            Err(LoginFailure::BadLogin) => {
+                resp.set_status(Status::Forbidden);
+                json!({
+                    "message": "Current password is incorrect."
+                })
+            }
+            Err(LoginFailure::SqlError(e)) => {
+                error!("{}", e);
+                resp.set_status(Status::InternalServerError);
+                json!({
+                    "message": "An internal error occurred."
+                })
+            }
+            Ok(_) if change.new_password.len() < MIN_PASSWORD_LEN => {
+                resp.set_status(Status::BadRequest);
+                json!({
+                    "message": "Password too short.",
+                    "info": format!("Must be at least {} bytes.", MIN_PASSWORD_LEN)
+                })
+            }
+            Ok(u) => {
+                let hash = tokio::task::spawn_blocking(move || create_pass_hash(change.new_password))
+                    .await
+                    .unwrap();
+
+                match model::set_password(u.id, hash).await {
+                    Ok(_) => {
+                        info!("Changed password for user {}", &u.username);
+                        json!({
+                            "message": "Password changed."
+                        })
+                    }
+                    Err(e) => {
+                        error!("{}", e);
+                        resp.set_status(Status::InternalServerError);
+                        json!({
+                            "message": "An internal error occurred."
+                        })
+                    }
+                }
+            }
+        }.to_string();
+        resp.set_sized_body(body.len(), Cursor::new(body));
+
+        resp
+    }).await
+}
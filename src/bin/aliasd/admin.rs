@@ -0,0 +1,417 @@
+use std::collections::HashMap;
+use std::io::Cursor;
+
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, QueryResult, RunQueryDsl};
+use rocket::{Request, Response};
+use rocket::http::{ContentType, Status};
+use rocket::request::{FromRequest, Outcome};
+use rocket_contrib::json::Json;
+use url::Url;
+
+use alias::db::conn;
+use alias::model::{Claims, DomainForm, DomainInfo, NewWebhook, WebhookAttempt, WebhookDelivery, WebhookEndpoint, WebhookEvent};
+
+use crate::{domains, tls, webhooks};
+use crate::logging::RequestSpan;
+
+const MAX_DELIVERIES: i64 = 100;
+
+/// A logged-in user who's also in the `admins` table. Granted with `aliasd user admin <name>`.
+///
+/// With `tls.client_ca` set, the request also has to have come through the TLS listener with a
+/// client certificate.
+pub struct Admin(pub Claims);
+
+#[rocket::async_trait]
+impl<'a, 'r> FromRequest<'a, 'r> for Admin {
+    type Error = ();
+
+    async fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        let tls = &alias::config::get().tls;
+        if tls.enabled() && tls.client_ca.is_some() && !tls::has_client_cert(request) {
+            return Outcome::Failure((Status::Forbidden, ()));
+        }
+
+        let claims = match Claims::from_request(request).await {
+            Outcome::Success(c) => c,
+            Outcome::Failure(f) => return Outcome::Failure(f),
+            Outcome::Forward(f) => return Outcome::Forward(f),
+        };
+
+        match is_admin(claims.user_id).await {
+            Ok(true) => Outcome::Success(Admin(claims)),
+            Ok(false) => Outcome::Failure((Status::Forbidden, ())),
+            Err(e) => {
+                error!("{}", e);
+                Outcome::Failure((Status::InternalServerError, ()))
+            }
+        }
+    }
+}
+
+pub async fn is_admin(uid: i32) -> QueryResult<bool> {
+    conn().with_conn(move |c| {
+        use ::alias::schema::admins::dsl::*;
+        admins.select(user_id)
+            .filter(user_id.eq(uid))
+            .get_result::<i32>(c)
+            .optional()
+            .map(|a| a.is_some())
+    }).await
+}
+
+pub async fn set_admin(name: String, admin: bool) -> anyhow::Result<()> {
+    conn().with_conn(move |c| {
+        use ::alias::schema::{admins, users};
+        let uid: i32 = users::table.select(users::id)
+            .filter(users::username.eq(&name))
+            .get_result(c)
+            .optional()?
+            .ok_or_else(|| anyhow::anyhow!("There's no user named {}.", name))?;
+
+        if admin {
+            diesel::replace_into(admins::table)
+                .values(admins::user_id.eq(uid))
+                .execute(c)?;
+        } else {
+            diesel::delete(admins::table.filter(admins::user_id.eq(uid))).execute(c)?;
+        }
+        Ok(())
+    }).await
+}
+
+fn parse_events(events: &str) -> Vec<WebhookEvent> {
+    events.split_whitespace().filter_map(|e| e.parse().ok()).collect()
+}
+
+fn json_response(status: Status, body: serde_json::Value) -> Response<'static> {
+    let mut resp = Response::new();
+    resp.set_status(status);
+    resp.set_header(ContentType::JSON);
+    let body = body.to_string();
+    resp.set_sized_body(body.len(), Cursor::new(body));
+    resp
+}
+
+fn internal_error(e: impl std::fmt::Display) -> Response<'static> {
+    error!("{}", e);
+    json_response(Status::InternalServerError, json!({
+        "message": "Internal server error"
+    }))
+}
+
+#[get("/admin/webhooks")]
+pub async fn list_webhooks(_admin: Admin, span: RequestSpan) -> Response<'static> {
+    span.run(async move {
+        let res = conn().with_conn(|c| {
+            use ::alias::schema::webhook_endpoints::dsl::*;
+            webhook_endpoints.select((id, url, events, created_at))
+                .order(id)
+                .load::<(i32, String, String, i64)>(c)
+        }).await;
+
+        match res {
+            Ok(rows) => {
+                let list: Vec<WebhookEndpoint> = rows.into_iter()
+                    .map(|(id, url, events, created_at)| WebhookEndpoint {
+                        id,
+                        url,
+                        events: parse_events(&events),
+                        created_at,
+                        secret: None,
+                    })
+                    .collect();
+                json_response(Status::Ok, json!(list))
+            }
+            Err(e) => internal_error(e),
+        }
+    }).await
+}
+
+#[post("/admin/webhooks", data = "<hook>")]
+pub async fn add_webhook(admin: Admin, hook: Json<NewWebhook>, span: RequestSpan) -> Response<'static> {
+    span.run(async move {
+        let hook = hook.into_inner();
+
+        let url = match Url::parse(&hook.url) {
+            Ok(u) if u.scheme() == "http" || u.scheme() == "https" => u.to_string(),
+            Ok(u) => return json_response(Status::BadRequest, json!({
+                "message": "Invalid URL",
+                "info": format!("Webhooks can only be sent over http or https, not {}.", u.scheme())
+            })),
+            Err(e) => return json_response(Status::BadRequest, json!({
+                "message": "Invalid URL",
+                "info": e.to_string()
+            })),
+        };
+
+        let secret = hook.secret.filter(|s| !s.is_empty()).unwrap_or_else(webhooks::new_secret);
+        let event_names = hook.events.iter().map(|e| e.as_str()).collect::<Vec<_>>().join(" ");
+        let now = chrono::Local::now().timestamp();
+
+        let endpoint = WebhookEndpoint {
+            id: 0,
+            url,
+            events: hook.events,
+            created_at: now,
+            secret: Some(secret),
+        };
+        let row = endpoint.clone();
+        let res = conn().with_conn(move |c| {
+            use ::alias::schema::webhook_endpoints as e;
+            diesel::insert_into(e::table)
+                .values((e::url.eq(&row.url),
+                         e::secret.eq(row.secret.as_deref().unwrap_or_default()),
+                         e::events.eq(&event_names),
+                         e::created_at.eq(row.created_at),
+                         e::created_by.eq(admin.0.user_id)))
+                .execute(c)?;
+            e::table.select(e::id)
+                .order(e::id.desc())
+                .first::<i32>(c)
+        }).await;
+
+        match res {
+            Ok(new_id) => {
+                info!("Registered webhook {} for {}", new_id, &endpoint.url);
+                // The secret is only ever shown here.
+                json_response(Status::Created, json!(WebhookEndpoint { id: new_id, ..endpoint }))
+            }
+            Err(e) => internal_error(e),
+        }
+    }).await
+}
+
+#[delete("/admin/webhooks/<hook>")]
+pub async fn delete_webhook(_admin: Admin, hook: i32, span: RequestSpan) -> Response<'static> {
+    span.run(async move {
+        let res = conn().with_conn(move |c| {
+            use ::alias::schema::{webhook_attempts, webhook_deliveries, webhook_endpoints};
+            let c: &diesel::SqliteConnection = c;
+            diesel::Connection::transaction(c, || {
+                // Foreign keys aren't enforced on this connection, so the cascade has to be done by hand.
+                let deliveries = webhook_deliveries::table.select(webhook_deliveries::id)
+                    .filter(webhook_deliveries::endpoint.eq(hook));
+                diesel::delete(webhook_attempts::table.filter(webhook_attempts::delivery.eq_any(deliveries)))
+                    .execute(c)?;
+                diesel::delete(webhook_deliveries::table.filter(webhook_deliveries::endpoint.eq(hook)))
+                    .execute(c)?;
+                diesel::delete(webhook_endpoints::table.filter(webhook_endpoints::id.eq(hook)))
+                    .execute(c)
+            })
+        }).await;
+
+        match res {
+            Ok(1) => json_response(Status::Ok, json!({
+                "message": "Deleted webhook.",
+                "id": hook
+            })),
+            Ok(_) => json_response(Status::NotFound, json!({
+                "message": "No such webhook",
+                "id": hook
+            })),
+            Err(e) => internal_error(e),
+        }
+    }).await
+}
+
+/// The most recent deliveries to an endpoint, each with every attempt made so far.
+#[get("/admin/webhooks/<hook>/deliveries")]
+pub async fn list_deliveries(_admin: Admin, hook: i32, span: RequestSpan) -> Response<'static> {
+    span.run(async move {
+        let res: QueryResult<Vec<WebhookDelivery>> = conn().with_conn(move |c| {
+            use ::alias::schema::{webhook_attempts as a, webhook_deliveries as d};
+
+            let rows = d::table
+                .select((d::id, d::event, d::created_at, d::attempts, d::next_attempt_at, d::delivered_at, d::failed))
+                .filter(d::endpoint.eq(hook))
+                .order(d::id.desc())
+                .limit(MAX_DELIVERIES)
+                .load::<(i32, String, i64, i32, i64, Option<i64>, bool)>(c)?;
+
+            let ids: Vec<i32> = rows.iter().map(|r| r.0).collect();
+            let mut history: HashMap<i32, Vec<WebhookAttempt>> = HashMap::new();
+            for (delivery, attempted_at, status, error, duration_ms) in a::table
+                .select((a::delivery, a::attempted_at, a::status, a::error, a::duration_ms))
+                .filter(a::delivery.eq_any(ids))
+                .order(a::id)
+                .load::<(i32, i64, Option<i32>, Option<String>, i32)>(c)? {
+                history.entry(delivery).or_default().push(WebhookAttempt {
+                    attempted_at,
+                    status: status.map(|s| s as u16),
+                    error,
+                    duration_ms,
+                });
+            }
+
+            Ok(rows.into_iter()
+                .map(|(id, event, created_at, attempts, next_attempt_at, delivered_at, failed)| WebhookDelivery {
+                    history: history.remove(&id).unwrap_or_default(),
+                    id,
+                    event,
+                    created_at,
+                    attempts,
+                    next_attempt_at,
+                    delivered_at,
+                    failed,
+                })
+                .collect())
+        }).await;
+
+        match res {
+            Ok(list) => json_response(Status::Ok, json!(list)),
+            Err(e) => internal_error(e),
+        }
+    }).await
+}
+
+#[get("/admin/domains")]
+pub async fn list_domains(_admin: Admin, span: RequestSpan) -> Response<'static> {
+    span.run(async move {
+        let res: QueryResult<Vec<DomainInfo>> = conn().with_conn(|c| {
+            use ::alias::schema::{aliases, domain_owners, domains, users};
+
+            let mut owners: HashMap<String, Vec<String>> = HashMap::new();
+            for (domain, name) in domain_owners::table.inner_join(users::table)
+                .select((domain_owners::domain, users::username))
+                .order((domain_owners::domain, users::username))
+                .load::<(String, String)>(c)? {
+                owners.entry(domain).or_default().push(name);
+            }
+
+            let mut counts: HashMap<String, i64> = HashMap::new();
+            for domain in aliases::table.select(aliases::domain).load::<String>(c)? {
+                *counts.entry(domain).or_default() += 1;
+            }
+
+            Ok(domains::table.select((domains::name, domains::created_at))
+                .order(domains::name)
+                .load::<(String, i64)>(c)?
+                .into_iter()
+                .map(|(name, created_at)| DomainInfo {
+                    owners: owners.remove(&name).unwrap_or_default(),
+                    aliases: counts.get(&name).copied().unwrap_or(0),
+                    name,
+                    created_at,
+                })
+                .collect())
+        }).await;
+
+        match res {
+            Ok(list) => json_response(Status::Ok, json!(list)),
+            Err(e) => internal_error(e),
+        }
+    }).await
+}
+
+enum DomainWrite {
+    Written,
+    NoSuchUser(String),
+}
+
+/// Creates the domain if it doesn't exist, and sets its owners to exactly the ones given.
+#[put("/admin/domains/<name>", data = "<form>")]
+pub async fn put_domain(_admin: Admin, name: String, form: Json<DomainForm>, span: RequestSpan) -> Response<'static> {
+    span.run(async move {
+        let name = domains::normalize(&name);
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.') {
+            return json_response(Status::BadRequest, json!({
+                "message": "Invalid domain",
+                "info": "Domains are hostnames, like go.example.com."
+            }));
+        }
+
+        let form = form.into_inner();
+        let qn = name.clone();
+        let now = chrono::Local::now().timestamp();
+        let res = conn().with_conn(move |c| {
+            use ::alias::schema::{domain_owners, domains, users};
+            let c: &diesel::SqliteConnection = c;
+            diesel::Connection::transaction(c, || {
+                let mut ids = Vec::with_capacity(form.owners.len());
+                for owner in &form.owners {
+                    match users::table.select(users::id).filter(users::username.eq(owner)).get_result::<i32>(c).optional()? {
+                        Some(id) => ids.push(id),
+                        None => return Ok(DomainWrite::NoSuchUser(owner.clone())),
+                    }
+                }
+
+                diesel::insert_or_ignore_into(domains::table)
+                    .values((domains::name.eq(&qn), domains::created_at.eq(now)))
+                    .execute(c)?;
+                diesel::delete(domain_owners::table.filter(domain_owners::domain.eq(&qn))).execute(c)?;
+                for id in ids {
+                    diesel::insert_or_ignore_into(domain_owners::table)
+                        .values((domain_owners::domain.eq(&qn), domain_owners::user_id.eq(id)))
+                        .execute(c)?;
+                }
+                Ok(DomainWrite::Written)
+            })
+        }).await;
+
+        match res {
+            Ok(DomainWrite::Written) => {
+                domains::forget().await;
+                info!("Set up domain {}", &name);
+                json_response(Status::Ok, json!({
+                    "message": "Saved domain.",
+                    "domain": name
+                }))
+            }
+            Ok(DomainWrite::NoSuchUser(user)) => json_response(Status::BadRequest, json!({
+                "message": "No such user",
+                "info": format!("There's no user named {}.", user)
+            })),
+            Err(e) => internal_error(e),
+        }
+    }).await
+}
+
+enum DomainDelete {
+    Deleted(usize),
+    NotEmpty(i64),
+}
+
+/// Only empty domains can be deleted, so nobody's aliases disappear along with one.
+#[delete("/admin/domains/<name>")]
+pub async fn delete_domain(_admin: Admin, name: String, span: RequestSpan) -> Response<'static> {
+    span.run(async move {
+        let name = domains::normalize(&name);
+        let qn = name.clone();
+        let res = conn().with_conn(move |c| {
+            use ::alias::schema::{aliases, domain_owners, domains};
+            let c: &diesel::SqliteConnection = c;
+            diesel::Connection::transaction(c, || {
+                let count: i64 = aliases::table.filter(aliases::domain.eq(&qn)).count().get_result(c)?;
+                if count > 0 {
+                    return Ok(DomainDelete::NotEmpty(count));
+                }
+                // Foreign keys aren't enforced on this connection, so the cascade has to be done by hand.
+                diesel::delete(domain_owners::table.filter(domain_owners::domain.eq(&qn))).execute(c)?;
+                diesel::delete(domains::table.filter(domains::name.eq(&qn)))
+                    .execute(c)
+                    .map(DomainDelete::Deleted)
+            })
+        }).await;
+
+        match res {
+            Ok(DomainDelete::Deleted(1)) => {
+                domains::forget().await;
+                json_response(Status::Ok, json!({
+                    "message": "Deleted domain.",
+                    "domain": name
+                }))
+            }
+            Ok(DomainDelete::Deleted(_)) => json_response(Status::NotFound, json!({
+                "message": "No such domain",
+                "domain": name
+            })),
+            Ok(DomainDelete::NotEmpty(n)) => json_response(Status::Conflict, json!({
+                "message": "Domain still has aliases",
+                "info": format!("Delete its {} aliases first.", n),
+                "domain": name
+            })),
+            Err(e) => internal_error(e),
+        }
+    }).await
+}
@@ -2,20 +2,111 @@ use lru::LruCache;
 use once_cell::sync::Lazy;
 use tokio::sync::{Mutex};
 use alias::db::conn;
-use diesel::{QueryDsl, ExpressionMethods, RunQueryDsl};
-use std::sync::Arc;
+use alias::metrics;
+use diesel::{QueryDsl, ExpressionMethods, RunQueryDsl, QueryResult, SqliteConnection};
+use std::collections::{HashMap, HashSet};
 use diesel::result::Error;
+use alias::model::{Strategy, Visibility, WeightedDestination};
+use crate::rotate;
+use crate::rules::{self, CompiledRule};
 
-const CACHE_SIZE: usize = 4096;
+/// How many canonical hops a lookup will follow before giving up.
+pub const MAX_DEPTH: usize = 8;
 
-static ALIAS_CACHE: Lazy<Mutex<LruCache<String, String>>> = Lazy::new(
-    || Mutex::new(LruCache::new(CACHE_SIZE))
+#[derive(Debug, Clone)]
+pub struct CachedAlias {
+    pub domain: String,
+    /// The alias the lookup ended on, after following canonicals.
+    pub name: String,
+    pub destination: String,
+    pub redirect: u16,
+    pub strategy: Strategy,
+    /// Empty unless the alias has more than its one destination.
+    pub targets: Vec<WeightedDestination>,
+    /// Checked before `targets`, compiled here so redirects don't have to.
+    pub rules: Vec<CompiledRule>,
+    /// Every non-public alias passed through on the way, all of which have to let the request in.
+    pub restrictions: Vec<Restriction>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Restriction {
+    pub visibility: Visibility,
+    pub owner: i32,
+}
+
+/// A domain and an alias in it.
+type Key = (String, String);
+
+struct Entry {
+    alias: CachedAlias,
+    /// The canonicals the lookup went through, whose `dependents` list this entry.
+    via: Vec<String>,
+}
+
+struct AliasCache {
+    entries: LruCache<Key, Entry>,
+    /// For each alias, the cached names that were resolved through it.
+    dependents: HashMap<Key, HashSet<Key>>,
+    /// Bumped by every [`evict_alias`], so a lookup that read the database before a write doesn't
+    /// cache what it found after the write's eviction already ran.
+    generation: u64,
+}
+
+impl AliasCache {
+    /// Drops `key` and its place in `dependents`, returning whether it was cached.
+    fn remove(&mut self, key: &Key) -> bool {
+        match self.entries.pop(key) {
+            Some(entry) => {
+                self.unlink(key, entry.via);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Makes room for one more entry by dropping the least recently used one, if the cache is full.
+    fn make_room(&mut self, size: usize) -> u64 {
+        let mut evicted = 0;
+        while self.entries.len() >= size {
+            match self.entries.pop_lru() {
+                Some((key, entry)) => self.unlink(&key, entry.via),
+                None => break,
+            }
+            evicted += 1;
+        }
+        evicted
+    }
+
+    fn unlink(&mut self, key: &Key, via: Vec<String>) {
+        for link in via {
+            let link = (key.0.clone(), link);
+            if let Some(deps) = self.dependents.get_mut(&link) {
+                deps.remove(key);
+                if deps.is_empty() {
+                    self.dependents.remove(&link);
+                }
+            }
+        }
+    }
+}
+
+static ALIAS_CACHE: Lazy<Mutex<AliasCache>> = Lazy::new(
+    || Mutex::new(AliasCache {
+        entries: LruCache::new(alias::config::get().cache.size),
+        dependents: HashMap::new(),
+        generation: 0,
+    })
 );
 
 #[derive(Debug, thiserror::Error)]
 pub enum AliasSearchFailure {
     #[error("No such alias was present")]
     NoSuchAlias,
+    #[error("Alias {0} is part of a canonical cycle")]
+    Cycle(String),
+    #[error("Alias {0} goes through more than {} canonical aliases", MAX_DEPTH)]
+    TooDeep(String),
     #[error("Ran into a problem running the query.")]
     Sql(diesel::result::Error)
 }
@@ -29,37 +120,148 @@ impl From<diesel::result::Error> for AliasSearchFailure {
     }
 }
 
-pub async fn get_alias(s: impl Into<String>) -> Result<String, AliasSearchFailure> {
-    let s = s.into();
+/// Follows `name` through its canonicals, which are always in the same domain, returning every
+/// alias on the way and the final record.
+pub fn resolve(c: &SqliteConnection, dom: &str, name: &str) -> Result<(Vec<String>, CachedAlias), AliasSearchFailure> {
+    use ::alias::schema::aliases::dsl::*;
+
+    let mut chain = vec![name.to_string()];
+    let mut restrictions = Vec::new();
+    loop {
+        let current = chain.last().unwrap();
+        let (d, r, st, vis, owner, next) = aliases
+            .select((destination, redirect_status, strategy, visibility, creator, canonical))
+            .filter(domain.eq(dom))
+            .filter(alias.eq(current))
+            .get_result::<(String, i32, String, String, i32, Option<String>)>(c)?;
+
+        let vis: Visibility = vis.parse().unwrap_or(Visibility::Owner);
+        if vis != Visibility::Public {
+            restrictions.push(Restriction { visibility: vis, owner });
+        }
+
+        match next {
+            None => {
+                let found = CachedAlias {
+                    domain: dom.to_string(),
+                    name: current.clone(),
+                    destination: d,
+                    redirect: r as u16,
+                    strategy: st.parse().unwrap_or_default(),
+                    targets: destinations_of(c, dom, current)?,
+                    rules: rules::rules_of(c, dom, current)?
+                        .iter()
+                        .filter_map(|r| rules::compile(r).ok())
+                        .collect(),
+                    restrictions,
+                };
+                return Ok((chain, found));
+            }
+            Some(next) if chain.contains(&next) => return Err(AliasSearchFailure::Cycle(name.to_string())),
+            Some(_) if chain.len() > MAX_DEPTH => return Err(AliasSearchFailure::TooDeep(name.to_string())),
+            Some(next) => chain.push(next),
+        }
+    }
+}
+
+/// Whether pointing `name` at `target` would make a chain loop back to `name`, or exceed [`MAX_DEPTH`].
+pub fn check_canonical(c: &SqliteConnection, dom: &str, name: &str, target: &str) -> Result<(), AliasSearchFailure> {
+    if name == target {
+        return Err(AliasSearchFailure::Cycle(name.to_string()));
+    }
+
+    let (chain, _) = resolve(c, dom, target)?;
+    if chain.iter().any(|a| a == name) {
+        return Err(AliasSearchFailure::Cycle(name.to_string()));
+    }
+    if chain.len() > MAX_DEPTH {
+        return Err(AliasSearchFailure::TooDeep(name.to_string()));
+    }
+    Ok(())
+}
+
+pub fn destinations_of(c: &SqliteConnection, dom: &str, name: &str) -> QueryResult<Vec<WeightedDestination>> {
+    use ::alias::schema::alias_destinations::dsl::*;
+    alias_destinations.select((destination, weight))
+        .filter(domain.eq(dom))
+        .filter(alias.eq(name))
+        .order(position)
+        .load::<(String, i32)>(c)
+        .map(|rows| rows.into_iter()
+            .map(|(to, w)| WeightedDestination { to, weight: w as u32 })
+            .collect())
+}
+
+/// The aliases that point directly at `name`.
+pub fn direct_dependents(c: &SqliteConnection, dom: &str, name: &str) -> QueryResult<Vec<String>> {
+    use ::alias::schema::aliases::dsl::*;
+    aliases.select(alias)
+        .filter(domain.eq(dom))
+        .filter(canonical.eq(name))
+        .order(alias)
+        .load(c)
+}
+
+pub async fn get_alias(domain: impl Into<String>, s: impl Into<String>) -> Result<CachedAlias, AliasSearchFailure> {
+    let s = (domain.into(), s.into());
 
     let mut cache_g = ALIAS_CACHE.lock().await;
 
-    let res = cache_g.get(&s);
+    let res = cache_g.entries.get(&s);
 
-    if let Some(s) = res {
-        return Ok(s.clone());
+    if let Some(e) = res {
+        metrics::CACHE_HITS.inc();
+        return Ok(e.alias.clone());
     }
+    metrics::CACHE_MISSES.inc();
 
+    let generation = cache_g.generation;
     std::mem::drop(cache_g);
 
-    let s = Arc::<String>::from(s);
-    let qs = s.clone();
-    let dest: String = conn().with_conn(move |c| {
-        use ::alias::schema::aliases::dsl::*;
-        aliases.select(destination)
-            .filter(alias.eq(&*qs))
-            .get_result(c)
-            .map_err(AliasSearchFailure::from)
-    }).await?;
+    let (qd, qs) = s.clone();
+    let (chain, dest) = conn().with_conn(move |c| resolve(c, &qd, &qs)).await?;
 
     let mut cache_g = ALIAS_CACHE.lock().await;
-    cache_g.put(Arc::try_unwrap(s).unwrap(), dest.clone());
+    if cache_g.generation != generation {
+        // Something was written meanwhile, so this may already be out of date. It's still what the
+        // database said when the request came in, so it's answered, just not kept.
+        return Ok(dest);
+    }
+    // Another request may have cached it meanwhile, through a chain that has since changed.
+    cache_g.remove(&s);
+    let cap = cache_g.entries.cap();
+    let evicted = cache_g.make_room(cap);
+    metrics::CACHE_EVICTIONS.inc_by(evicted);
+
+    let via = chain.into_iter().skip(1).collect::<Vec<_>>();
+    for link in &via {
+        cache_g.dependents.entry((s.0.clone(), link.clone())).or_default().insert(s.clone());
+    }
+    cache_g.entries.put(s, Entry { alias: dest.clone(), via });
 
     Ok(dest)
 }
 
-pub async fn evict_alias(s: impl Into<String>) {
-    let s = s.into();
+/// Changes how many aliases are kept, dropping the least recently used ones if it shrank.
+pub async fn resize(size: usize) {
     let mut cache_g = ALIAS_CACHE.lock().await;
-    cache_g.pop(&s);
-}
\ No newline at end of file
+    // Evicted here rather than by `resize`, so their `dependents` go with them.
+    let evicted = cache_g.make_room(size + 1);
+    cache_g.entries.resize(size);
+    metrics::CACHE_EVICTIONS.inc_by(evicted);
+}
+
+/// Drops `s` from the cache, along with every cached alias that resolved through it.
+pub async fn evict_alias(domain: impl Into<String>, s: impl Into<String>) {
+    let s = (domain.into(), s.into());
+    let mut cache_g = ALIAS_CACHE.lock().await;
+    cache_g.generation += 1;
+    rotate::forget(&s.0, &s.1);
+    let mut invalidated = cache_g.remove(&s) as u64;
+    if let Some(deps) = cache_g.dependents.remove(&s) {
+        for d in deps {
+            invalidated += cache_g.remove(&d) as u64;
+        }
+    }
+    metrics::CACHE_INVALIDATIONS.inc_by(invalidated);
+}
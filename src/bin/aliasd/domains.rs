@@ -0,0 +1,130 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use diesel::{select, ExpressionMethods, QueryDsl, QueryResult, RunQueryDsl, SqliteConnection};
+use diesel::expression::exists::exists;
+use once_cell::sync::Lazy;
+use rocket::Request;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use tokio::sync::RwLock;
+
+use alias::db::conn;
+
+use crate::forwarded::ClientInfo;
+
+/// Where aliases live when their domain isn't given, and what hosts without a domain of their own serve.
+pub const DEFAULT: &str = "";
+
+/// The names in the `domains` table, loaded on first use and dropped whenever it changes.
+static KNOWN: Lazy<RwLock<Option<Arc<HashSet<String>>>>> = Lazy::new(|| RwLock::new(None));
+
+/// The set of aliases a request is about.
+///
+/// Taken from `?domain=` when it's given, which is how `alias-client --domain` picks one. Otherwise
+/// it's the host the request was sent to, or [`DEFAULT`] if that host isn't a domain.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Domain(pub String);
+
+impl Domain {
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'a, 'r> FromRequest<'a, 'r> for Domain {
+    type Error = ();
+
+    async fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        let asked = request.uri().query()
+            .and_then(|q| url::form_urlencoded::parse(q.as_bytes()).find(|(k, _)| k == "domain"))
+            .map(|(_, v)| normalize(&v));
+        // Unknown domains are left for the handler, where they just have no aliases.
+        if let Some(asked) = asked {
+            return Outcome::Success(Domain(asked));
+        }
+
+        let host = match &ClientInfo::of(request).host {
+            Some(h) => normalize(h),
+            None => return Outcome::Success(Domain(DEFAULT.to_string())),
+        };
+        match known().await {
+            Ok(k) if k.contains(&host) => Outcome::Success(Domain(host)),
+            Ok(_) => Outcome::Success(Domain(DEFAULT.to_string())),
+            Err(e) => {
+                error!("{}", e);
+                Outcome::Failure((Status::InternalServerError, ()))
+            }
+        }
+    }
+}
+
+/// Lowercases a host and drops any port and trailing dot, so `Go.Example.:8000` is `go.example`.
+pub fn normalize(host: &str) -> String {
+    let host = host.trim();
+    let host = match host.rfind(':') {
+        // IPv6 addresses are bracketed when there's a port, and have more than one colon either way.
+        Some(i) if !host[..i].contains(':') || host[..i].ends_with(']') => &host[..i],
+        _ => host,
+    };
+    host.trim_end_matches('.').to_lowercase()
+}
+
+async fn known() -> QueryResult<Arc<HashSet<String>>> {
+    if let Some(k) = &*KNOWN.read().await {
+        return Ok(k.clone());
+    }
+
+    let names: Vec<String> = conn().with_conn(|c| {
+        use ::alias::schema::domains::dsl::*;
+        domains.select(name).load(c)
+    }).await?;
+    let k = Arc::new(names.into_iter().collect::<HashSet<_>>());
+    *KNOWN.write().await = Some(k.clone());
+    Ok(k)
+}
+
+/// Makes the next request reload the list of domains.
+pub async fn forget() {
+    *KNOWN.write().await = None;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteAccess {
+    Allowed,
+    NoSuchDomain,
+    /// The domain has owners, and this user isn't one of them.
+    NotOwner,
+}
+
+/// Whether `uid` may create, change or delete aliases in `name`.
+pub fn write_access(c: &SqliteConnection, name: &str, uid: i32) -> QueryResult<WriteAccess> {
+    use ::alias::schema::{domain_owners, domains};
+
+    if name == DEFAULT {
+        return Ok(WriteAccess::Allowed);
+    }
+    if !select(exists(domains::table.filter(domains::name.eq(name)))).get_result::<bool>(c)? {
+        return Ok(WriteAccess::NoSuchDomain);
+    }
+
+    let owners: Vec<i32> = domain_owners::table.select(domain_owners::user_id)
+        .filter(domain_owners::domain.eq(name))
+        .load(c)?;
+    Ok(if owners.is_empty() || owners.contains(&uid) {
+        WriteAccess::Allowed
+    } else {
+        WriteAccess::NotOwner
+    })
+}
+
+/// Whether `uid` is one of `name`'s owners. Unowned domains, like the default one, have none.
+pub fn is_owner(c: &SqliteConnection, name: &str, uid: i32) -> QueryResult<bool> {
+    use ::alias::schema::domain_owners;
+
+    select(exists(domain_owners::table
+        .filter(domain_owners::domain.eq(name))
+        .filter(domain_owners::user_id.eq(uid))))
+        .get_result(c)
+}
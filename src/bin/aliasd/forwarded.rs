@@ -0,0 +1,299 @@
+use std::net::IpAddr;
+
+use rocket::Request;
+use rocket::http::{Cookie, HeaderMap};
+use rocket::request::{FromRequest, Outcome};
+
+use alias::config::{self, ForwardedHeader};
+
+use crate::tls;
+
+/// Who a request came from and how, once the proxies in `server.trusted_proxies` are seen through.
+///
+/// Headers are only read when the peer is trusted, and only the kind `server.forwarded_header` names.
+/// They're then walked back from the peer until an address that isn't a trusted proxy, so a client
+/// can't pick its own address by sending them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientInfo {
+    pub ip: Option<IpAddr>,
+    /// Whether the client's own connection was HTTPS, going by `proto=` or `X-Forwarded-Proto`.
+    pub https: bool,
+    /// The host the client asked for, from `host=` or `X-Forwarded-Host` if a proxy sent one, else `Host`.
+    pub host: Option<String>,
+    /// The path the proxy serves aliasd under, from `X-Forwarded-Prefix`, like `/links`. Empty when at the root.
+    pub prefix: String,
+}
+
+impl ClientInfo {
+    /// Worked out once per request.
+    pub fn of<'r>(request: &'r Request<'_>) -> &'r ClientInfo {
+        request.local_cache(|| {
+            let cfg = config::get();
+            // With TLS on, Rocket only listens on loopback for the TLS listener. Other local processes
+            // can connect there too, so only requests carrying the listener's marker are believed.
+            let via_listener = cfg.tls.enabled() && tls::from_listener(request);
+            let trusted = |ip: IpAddr| (via_listener && ip.is_loopback())
+                || cfg.server.trusted_proxies.iter().any(|c| c.contains(ip));
+            ClientInfo::from_headers(request.remote().map(|a| a.ip()),
+                                     request.headers(),
+                                     cfg.server.forwarded_header,
+                                     trusted)
+        })
+    }
+
+    fn from_headers(peer: Option<IpAddr>,
+                    headers: &HeaderMap<'_>,
+                    kind: ForwardedHeader,
+                    trusted: impl Fn(IpAddr) -> bool) -> ClientInfo {
+        let mut info = ClientInfo {
+            ip: peer,
+            https: false,
+            host: headers.get_one("Host").map(String::from),
+            prefix: String::new(),
+        };
+        match peer {
+            Some(p) if trusted(p) => {}
+            _ => return info,
+        }
+
+        let (proto, host) = if kind == ForwardedHeader::Forwarded {
+            let forwarded = headers.get("Forwarded").collect::<Vec<_>>().join(",");
+            let elements = forwarded.split(',')
+                .filter(|e| !e.trim().is_empty())
+                .map(parse_element)
+                .collect::<Vec<_>>();
+            let (ip, i) = walk(elements.iter().map(|e| e.node.as_deref()), &trusted);
+            info.ip = ip.or(info.ip);
+            match i {
+                Some(i) => (elements[i].proto.clone(), elements[i].host.clone()),
+                None => (None, None),
+            }
+        } else {
+            let mut hops = headers.get("X-Forwarded-For").flat_map(|h| h.split(',')).collect::<Vec<_>>();
+            if hops.is_empty() {
+                hops.extend(headers.get_one("X-Real-IP"));
+            }
+            let (ip, _) = walk(hops.into_iter().map(Some), &trusted);
+            info.ip = ip.or(info.ip);
+            (last_value(headers, "X-Forwarded-Proto"), last_value(headers, "X-Forwarded-Host"))
+        };
+        info.https = proto.map_or(false, |p| p.eq_ignore_ascii_case("https"));
+        if host.is_some() {
+            info.host = host;
+        }
+
+        if let Some(prefix) = last_value(headers, "X-Forwarded-Prefix") {
+            let prefix = prefix.trim_end_matches('/');
+            // It ends up in Location headers and cookie paths, so anything odd is ignored, as is `//`,
+            // which would make those relative to another host.
+            let safe = prefix.starts_with('/') && !prefix.starts_with("//")
+                && prefix.chars().all(|c| c.is_ascii_alphanumeric() || "/-._~%".contains(c));
+            if safe {
+                info.prefix = prefix.to_string();
+            }
+        }
+        info
+    }
+
+    /// `path`, which should start with `/`, as the client has to ask for it.
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.prefix, path)
+    }
+
+    /// A cookie for the whole app, marked secure when the client is on HTTPS.
+    pub fn cookie(&self, name: &'static str, value: String) -> Cookie<'static> {
+        let mut cookie = Cookie::new(name, value);
+        cookie.set_path(if self.prefix.is_empty() { "/".to_string() } else { self.prefix.clone() });
+        cookie.set_secure(self.https);
+        cookie
+    }
+}
+
+#[rocket::async_trait]
+impl<'a, 'r> FromRequest<'a, 'r> for ClientInfo {
+    type Error = ();
+
+    async fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(ClientInfo::of(request).clone())
+    }
+}
+
+#[derive(Debug, Default)]
+struct Element {
+    node: Option<String>,
+    proto: Option<String>,
+    host: Option<String>,
+}
+
+/// One comma-separated element of a `Forwarded` header, like `for=192.0.2.60;proto=https;host=go.example`.
+fn parse_element(s: &str) -> Element {
+    let mut el = Element::default();
+    for pair in s.split(';') {
+        let mut kv = pair.splitn(2, '=');
+        let key = kv.next().unwrap_or_default().trim().to_ascii_lowercase();
+        let value = kv.next().unwrap_or_default().trim().trim_matches('"').to_string();
+        match key.as_str() {
+            "for" => el.node = Some(value),
+            "proto" => el.proto = Some(value),
+            "host" => el.host = Some(value),
+            _ => {}
+        }
+    }
+    el
+}
+
+/// Goes back through the hops, nearest first, to the first one that isn't a trusted proxy.
+///
+/// Returns that address and which hop it was. Hops that aren't addresses, like `unknown`, end the
+/// walk at the proxy that reported them.
+fn walk<'a>(hops: impl DoubleEndedIterator<Item = Option<&'a str>> + ExactSizeIterator,
+            trusted: impl Fn(IpAddr) -> bool) -> (Option<IpAddr>, Option<usize>) {
+    let mut found = (None, None);
+    for (i, hop) in hops.enumerate().rev() {
+        match hop.and_then(parse_node) {
+            Some(ip) => {
+                found = (Some(ip), Some(i));
+                if !trusted(ip) {
+                    break;
+                }
+            }
+            None => break,
+        }
+    }
+    found
+}
+
+/// Accepts `192.0.2.60`, `192.0.2.60:4711`, `2001:db8::1` and `[2001:db8::1]:4711`.
+fn parse_node(s: &str) -> Option<IpAddr> {
+    let s = s.trim().trim_matches('"');
+    if let Some(rest) = s.strip_prefix('[') {
+        return rest.split(']').next()?.parse().ok();
+    }
+    s.parse().ok().or_else(|| s.rsplitn(2, ':').nth(1)?.parse().ok())
+}
+
+/// The last value of a header that proxies append to, which is the one the nearest proxy set.
+fn last_value(headers: &HeaderMap<'_>, name: &str) -> Option<String> {
+    headers.get(name)
+        .flat_map(|h| h.split(','))
+        .last()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use alias::net::Cidr;
+
+    use super::*;
+
+    fn headers<'a>(pairs: &[(&'a str, &'a str)]) -> HeaderMap<'a> {
+        let mut h = HeaderMap::new();
+        for (name, value) in pairs {
+            h.add_raw(*name, *value);
+        }
+        h
+    }
+
+    fn info(peer: &str, kind: ForwardedHeader, pairs: &[(&str, &str)]) -> ClientInfo {
+        let proxies = Cidr::parse("10.0.0.0/8").unwrap();
+        ClientInfo::from_headers(Some(peer.parse().unwrap()), &headers(pairs), kind, |ip| proxies.contains(ip))
+    }
+
+    fn ip(s: &str) -> Option<IpAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn untrusted_peers_are_taken_at_their_word() {
+        let i = info("203.0.113.5", ForwardedHeader::XForwardedFor, &[
+            ("X-Forwarded-For", "198.51.100.7"),
+            ("X-Forwarded-Proto", "https"),
+        ]);
+        assert_eq!(i.ip, ip("203.0.113.5"));
+        assert!(!i.https);
+    }
+
+    #[test]
+    fn trusted_chains_are_walked_to_the_client() {
+        let i = info("10.0.0.1", ForwardedHeader::XForwardedFor, &[
+            ("X-Forwarded-For", "203.0.113.5, 10.0.0.2"),
+            ("X-Forwarded-Proto", "https"),
+            ("X-Forwarded-Host", "go.example"),
+        ]);
+        assert_eq!(i.ip, ip("203.0.113.5"));
+        assert!(i.https);
+        assert_eq!(i.host.as_deref(), Some("go.example"));
+    }
+
+    #[test]
+    fn walks_stop_at_the_first_untrusted_hop() {
+        // The client made up the first address; the proxies only vouch for the one they saw.
+        let i = info("10.0.0.1", ForwardedHeader::XForwardedFor, &[
+            ("X-Forwarded-For", "10.0.0.9, 203.0.113.5, 10.0.0.2"),
+        ]);
+        assert_eq!(i.ip, ip("203.0.113.5"));
+    }
+
+    #[test]
+    fn only_the_configured_header_is_read() {
+        let spoofed = &[("Forwarded", "for=198.51.100.7"), ("X-Forwarded-For", "203.0.113.5")];
+        assert_eq!(info("10.0.0.1", ForwardedHeader::XForwardedFor, spoofed).ip, ip("203.0.113.5"));
+        assert_eq!(info("10.0.0.1", ForwardedHeader::Forwarded, spoofed).ip, ip("198.51.100.7"));
+        assert_eq!(info("10.0.0.1", ForwardedHeader::Forwarded, &[("X-Forwarded-For", "203.0.113.5")]).ip,
+                   ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn forwarded_elements_carry_proto_and_host() {
+        let i = info("10.0.0.1", ForwardedHeader::Forwarded, &[
+            ("Forwarded", "for=203.0.113.5;proto=https;host=go.example, for=10.0.0.2;proto=http"),
+        ]);
+        assert_eq!(i.ip, ip("203.0.113.5"));
+        assert!(i.https);
+        assert_eq!(i.host.as_deref(), Some("go.example"));
+    }
+
+    #[test]
+    fn unknown_and_obfuscated_nodes_end_the_walk() {
+        for node in &["unknown", "_hidden", "\"_gazonk\""] {
+            let value = format!("for={}, for=10.0.0.2", node);
+            let i = info("10.0.0.1", ForwardedHeader::Forwarded, &[("Forwarded", &value)]);
+            assert_eq!(i.ip, ip("10.0.0.2"), "{}", node);
+        }
+    }
+
+    #[test]
+    fn parses_nodes_with_ports_and_brackets() {
+        assert_eq!(parse_node("192.0.2.60"), ip("192.0.2.60"));
+        assert_eq!(parse_node("192.0.2.60:4711"), ip("192.0.2.60"));
+        assert_eq!(parse_node("2001:db8::1"), ip("2001:db8::1"));
+        assert_eq!(parse_node("[2001:db8::1]"), ip("2001:db8::1"));
+        assert_eq!(parse_node("\"[2001:db8::1]:4711\""), ip("2001:db8::1"));
+        assert_eq!(parse_node("unknown"), None);
+        assert_eq!(parse_node("_hidden"), None);
+    }
+
+    #[test]
+    fn bracketed_ipv6_in_forwarded() {
+        let i = info("10.0.0.1", ForwardedHeader::Forwarded, &[("Forwarded", "for=\"[2001:db8::1]:4711\"")]);
+        assert_eq!(i.ip, ip("2001:db8::1"));
+    }
+
+    #[test]
+    fn mapped_proxies_are_trusted() {
+        let i = info("::ffff:10.0.0.1", ForwardedHeader::XForwardedFor, &[("X-Forwarded-For", "203.0.113.5")]);
+        assert_eq!(i.ip, ip("203.0.113.5"));
+    }
+
+    #[test]
+    fn prefixes_are_sanitized() {
+        let prefix = |p: &str| info("10.0.0.1", ForwardedHeader::XForwardedFor, &[("X-Forwarded-Prefix", p)]).prefix;
+        assert_eq!(prefix("/links/"), "/links");
+        assert_eq!(prefix("/"), "");
+        assert_eq!(prefix("links"), "");
+        assert_eq!(prefix("//evil.example"), "");
+        assert_eq!(prefix("/a b"), "");
+        assert_eq!(prefix("/a\"><script>"), "");
+    }
+}
@@ -0,0 +1,480 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use diesel::{Connection, ExpressionMethods, OptionalExtension, QueryDsl, QueryResult, RunQueryDsl, SqliteConnection};
+use futures::StreamExt;
+use hyper::{Body, Method, Request};
+use hyper::client::HttpConnector;
+use hyper::header::USER_AGENT;
+use hyper_rustls::HttpsConnector;
+use url::Url;
+
+use alias::config::HealthConfig;
+use alias::db::conn;
+use alias::model::{Health, WebhookEvent};
+
+/// How many failed checks in a row before a destination counts as dead.
+pub const DEAD_AFTER: i32 = 2;
+
+type HttpClient = hyper::Client<HttpsConnector<HttpConnector>>;
+
+pub struct HealthChecker {
+    /// Checks only run in the background when this is set; `aliasd check-links` ignores it.
+    pub interval: Option<Duration>,
+    pub concurrency: usize,
+    pub timeout: Duration,
+    /// The pause between two requests to the same host.
+    pub host_delay: Duration,
+    /// A shell command run for each alias whose destination just died.
+    pub notify: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub checked: usize,
+    pub dead: usize,
+}
+
+struct Probe {
+    destination: String,
+    status: Option<u16>,
+    latency_ms: u32,
+    error: Option<String>,
+}
+
+impl HealthChecker {
+    /// Background checks are disabled unless `health.interval_mins` is set.
+    pub fn from_config(cfg: &HealthConfig) -> HealthChecker {
+        HealthChecker {
+            interval: cfg.interval_mins.map(|m| Duration::from_secs(m * 60)),
+            concurrency: cfg.concurrency.max(1),
+            timeout: Duration::from_secs(cfg.timeout_secs),
+            host_delay: Duration::from_millis(cfg.host_delay_ms),
+            notify: cfg.notify_cmd.clone(),
+        }
+    }
+
+    pub async fn run(self, interval: Duration) {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            match self.check_all().await {
+                Ok(s) => info!("Checked {} destinations, {} dead", s.checked, s.dead),
+                Err(e) => error!("Destination health check failed: {}", e),
+            }
+        }
+    }
+
+    /// Checks every destination once, recording the results and notifying owners of newly dead links.
+    pub async fn check_all(&self) -> anyhow::Result<Summary> {
+        let users = conn().with_conn(|c| destinations(c)).await?;
+
+        // Requests to one host go one after another, so a big site with many aliases isn't hammered.
+        let mut by_host: HashMap<String, Vec<String>> = HashMap::new();
+        for dest in users.keys() {
+            let host = Url::parse(dest).ok()
+                .and_then(|u| u.host_str().map(String::from))
+                .unwrap_or_default();
+            by_host.entry(host).or_default().push(dest.clone());
+        }
+
+        let client: HttpClient = hyper::Client::builder().build(HttpsConnector::with_native_roots());
+        let probes: Vec<Probe> = futures::stream::iter(by_host.into_iter().map(|(_, dests)| self.check_host(&client, dests)))
+            .buffer_unordered(self.concurrency)
+            .collect::<Vec<Vec<Probe>>>()
+            .await
+            .into_iter()
+            .flatten()
+            .collect();
+
+        let summary = Summary {
+            checked: probes.len(),
+            dead: 0,
+        };
+        let now = chrono::Local::now().timestamp();
+        let (died, dead) = conn().with_conn(move |c| record(c, &probes, now)).await?;
+
+        for probe in &died {
+            for usage in users.get(&probe.destination).into_iter().flatten() {
+                self.notify(usage, probe).await;
+            }
+        }
+
+        Ok(Summary { dead, ..summary })
+    }
+
+    async fn check_host(&self, client: &HttpClient, dests: Vec<String>) -> Vec<Probe> {
+        let mut probes = Vec::with_capacity(dests.len());
+        for (i, dest) in dests.into_iter().enumerate() {
+            if i > 0 {
+                tokio::time::delay_for(self.host_delay).await;
+            }
+            probes.push(probe(client, dest, self.timeout).await);
+        }
+        probes
+    }
+
+    async fn notify(&self, usage: &Usage, probe: &Probe) {
+        let Usage { domain, alias, owner } = usage;
+        let reason = describe(probe);
+        warn!("Alias {} owned by {} points at dead link {} ({})", display_name(domain, alias), owner, probe.destination, reason);
+
+        let data = serde_json::json!({
+            "alias": alias,
+            "domain": domain,
+            "user": owner,
+            "destination": &probe.destination,
+            "error": &reason
+        });
+        if let Err(e) = conn().with_conn(move |c| ::alias::webhooks::enqueue(c, WebhookEvent::AliasDead, data)).await {
+            error!("Couldn't queue dead link webhook for {}: {}", alias, e);
+        }
+
+        let cmd = match &self.notify {
+            Some(c) => c,
+            None => return,
+        };
+        let status = tokio::process::Command::new("sh")
+            .arg("-c")
+            .arg(cmd)
+            .env("ALIAS_NAME", alias)
+            .env("ALIAS_DOMAIN", domain)
+            .env("ALIAS_OWNER", owner)
+            .env("ALIAS_DESTINATION", &probe.destination)
+            .env("ALIAS_HEALTH_ERROR", &reason)
+            .status()
+            .await;
+        match status {
+            Ok(s) if !s.success() => error!("Dead link notification for {} exited with {}", alias, s),
+            Err(e) => error!("Couldn't run dead link notification for {}: {}", alias, e),
+            _ => {}
+        }
+    }
+}
+
+/// Whether a response means the link still works. Pages behind a login count, since the link itself is fine.
+pub fn healthy(status: Option<u16>) -> bool {
+    match status {
+        Some(s) => s < 400 || s == 401 || s == 403,
+        None => false,
+    }
+}
+
+fn describe(probe: &Probe) -> String {
+    match (&probe.error, probe.status) {
+        (Some(e), _) => e.clone(),
+        (None, Some(s)) => format!("HTTP {}", s),
+        (None, None) => "no response".to_string(),
+    }
+}
+
+async fn probe(client: &HttpClient, destination: String, timeout: Duration) -> Probe {
+    let start = Instant::now();
+    let mut res = request(client, Method::HEAD, &destination, timeout).await;
+    // Plenty of servers don't implement HEAD, so try again the expensive way.
+    if let Ok(405) | Ok(501) = res {
+        res = request(client, Method::GET, &destination, timeout).await;
+    }
+    let latency_ms = start.elapsed().as_millis() as u32;
+
+    match res {
+        Ok(status) => Probe { destination, status: Some(status), latency_ms, error: None },
+        Err(e) => Probe { destination, status: None, latency_ms, error: Some(e) },
+    }
+}
+
+async fn request(client: &HttpClient, method: Method, url: &str, timeout: Duration) -> Result<u16, String> {
+    let req = Request::builder()
+        .method(method)
+        .uri(url)
+        .header(USER_AGENT, concat!("aliasd/", env!("CARGO_PKG_VERSION"), " (link checker)"))
+        .body(Body::empty())
+        .map_err(|e| e.to_string())?;
+
+    match tokio::time::timeout(timeout, client.request(req)).await {
+        Ok(Ok(resp)) => Ok(resp.status().as_u16()),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err(format!("Timed out after {}s", timeout.as_secs())),
+    }
+}
+
+/// An alias using a destination.
+struct Usage {
+    domain: String,
+    alias: String,
+    owner: String,
+}
+
+/// `alias` for the default domain, and `domain/alias` otherwise.
+fn display_name(domain: &str, alias: &str) -> String {
+    if domain.is_empty() {
+        alias.to_string()
+    } else {
+        format!("{}/{}", domain, alias)
+    }
+}
+
+/// Every destination that can be redirected to, with the aliases and owners that use it.
+fn destinations(c: &SqliteConnection) -> QueryResult<HashMap<String, Vec<Usage>>> {
+    use ::alias::schema::{alias_destinations, alias_rules, aliases, users};
+
+    let usernames: HashMap<i32, String> = users::table
+        .select((users::id, users::username))
+        .load::<(i32, String)>(c)?
+        .into_iter()
+        .collect();
+
+    let owners: HashMap<(String, String), String> = aliases::table
+        .select((aliases::domain, aliases::alias, aliases::creator))
+        .load::<(String, String, i32)>(c)?
+        .into_iter()
+        .map(|(d, a, creator)| ((d, a), usernames.get(&creator).cloned().unwrap_or_default()))
+        .collect();
+
+    let mut rows = aliases::table
+        .select((aliases::domain, aliases::alias, aliases::destination))
+        .filter(aliases::canonical.is_null())
+        .load::<(String, String, String)>(c)?;
+    rows.extend(alias_destinations::table
+        .select((alias_destinations::domain, alias_destinations::alias, alias_destinations::destination))
+        .load::<(String, String, String)>(c)?);
+    rows.extend(alias_rules::table
+        .select((alias_rules::domain, alias_rules::alias, alias_rules::destination))
+        .load::<(String, String, String)>(c)?);
+
+    let mut out: HashMap<String, Vec<Usage>> = HashMap::new();
+    for (domain, alias, dest) in rows {
+        if dest.is_empty() {
+            continue;
+        }
+        let owner = owners.get(&(domain.clone(), alias.clone())).cloned().unwrap_or_default();
+        let users = out.entry(dest).or_default();
+        if !users.iter().any(|u| u.domain == domain && u.alias == alias) {
+            users.push(Usage { domain, alias, owner });
+        }
+    }
+    Ok(out)
+}
+
+/// Stores the probes, returning the ones that just crossed [`DEAD_AFTER`] and the total number of dead links.
+///
+/// One transaction, so the database thread commits once rather than once per destination.
+fn record(c: &SqliteConnection, probes: &[Probe], now: i64) -> QueryResult<(Vec<Probe>, usize)> {
+    use ::alias::schema::{alias_destinations, alias_rules, aliases};
+    use ::alias::schema::destination_health::dsl::*;
+
+    c.transaction(|| {
+        let mut died = Vec::new();
+        let mut dead = 0;
+        for p in probes {
+            let previous: i32 = destination_health.select(failures)
+                .filter(destination.eq(&p.destination))
+                .get_result(c)
+                .optional()?
+                .unwrap_or(0);
+
+            let fails = if healthy(p.status) { 0 } else { previous + 1 };
+            if fails >= DEAD_AFTER {
+                dead += 1;
+            }
+            if fails == DEAD_AFTER {
+                died.push(Probe {
+                    destination: p.destination.clone(),
+                    status: p.status,
+                    latency_ms: p.latency_ms,
+                    error: p.error.clone(),
+                });
+            }
+
+            diesel::replace_into(destination_health)
+                .values((destination.eq(&p.destination),
+                         checked_at.eq(now),
+                         status.eq(p.status.map(i32::from)),
+                         latency_ms.eq(p.latency_ms as i32),
+                         error.eq(&p.error),
+                         failures.eq(fails)))
+                .execute(c)?;
+        }
+
+        // Forget destinations no alias uses anymore. Subqueries rather than a list of what was just
+        // checked, which would run into SQLite's limit on bound variables.
+        diesel::delete(destination_health
+            .filter(destination.ne_all(aliases::table.select(aliases::destination)))
+            .filter(destination.ne_all(alias_destinations::table.select(alias_destinations::destination)))
+            .filter(destination.ne_all(alias_rules::table.select(alias_rules::destination))))
+            .execute(c)?;
+
+        Ok((died, dead))
+    })
+}
+
+/// The last check of every destination, for the listing API.
+pub fn load_all(c: &SqliteConnection) -> QueryResult<HashMap<String, Health>> {
+    use ::alias::schema::destination_health::dsl::*;
+
+    Ok(destination_health
+        .select((destination, checked_at, status, latency_ms, error, failures))
+        .load::<(String, i64, Option<i32>, Option<i32>, Option<String>, i32)>(c)?
+        .into_iter()
+        .map(|(d, at, s, l, e, f)| (d, Health {
+            checked_at: at,
+            status: s.map(|s| s as u16),
+            latency_ms: l.map(|l| l as u32),
+            error: e,
+            dead: f >= DEAD_AFTER,
+        }))
+        .collect())
+}
+
+/// An alias's health is that of its worst destination.
+pub fn summarize<'a>(dests: impl IntoIterator<Item=&'a str>, all: &HashMap<String, Health>) -> Option<Health> {
+    dests.into_iter()
+        .filter_map(|d| all.get(d))
+        .fold(None, |worst: Option<&Health>, h| match worst {
+            Some(w) if w.dead || !h.dead => Some(w),
+            _ => Some(h),
+        })
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+
+    use hyper::{Response, StatusCode};
+    use hyper::service::{make_service_fn, service_fn};
+
+    use super::*;
+
+    /// Answers `/ok` with 200, `/missing` with 404, `/no-head` with 405 to HEAD and 200 otherwise,
+    /// and `/slow` after a few seconds.
+    async fn serve() -> SocketAddr {
+        async fn respond(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+            let status = match (req.method(), req.uri().path()) {
+                (_, "/ok") => StatusCode::OK,
+                (_, "/missing") => StatusCode::NOT_FOUND,
+                (&Method::HEAD, "/no-head") => StatusCode::METHOD_NOT_ALLOWED,
+                (_, "/no-head") => StatusCode::OK,
+                (_, "/slow") => {
+                    tokio::time::delay_for(Duration::from_secs(5)).await;
+                    StatusCode::OK
+                }
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            Ok(Response::builder().status(status).body(Body::empty()).unwrap())
+        }
+
+        let make = make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(respond)) });
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        addr
+    }
+
+    fn client() -> HttpClient {
+        let mut http = HttpConnector::new();
+        http.enforce_http(false);
+        hyper::Client::builder().build(HttpsConnector::from((http, rustls::ClientConfig::new())))
+    }
+
+    fn failed(dest: &str) -> Probe {
+        Probe { destination: dest.to_string(), status: Some(404), latency_ms: 1, error: None }
+    }
+
+    fn checked(dead: bool) -> Health {
+        Health { checked_at: 0, status: Some(if dead { 404 } else { 200 }), latency_ms: Some(1), error: None, dead }
+    }
+
+    fn database() -> SqliteConnection {
+        let c = SqliteConnection::establish(":memory:").unwrap();
+        crate::embedded_migrations::run(&c).unwrap();
+        c
+    }
+
+    #[tokio::test]
+    async fn probe_reports_status() {
+        let addr = serve().await;
+        let client = client();
+
+        let ok = probe(&client, format!("http://{}/ok", addr), Duration::from_secs(2)).await;
+        assert_eq!(ok.status, Some(200));
+        assert!(healthy(ok.status));
+
+        let missing = probe(&client, format!("http://{}/missing", addr), Duration::from_secs(2)).await;
+        assert_eq!(missing.status, Some(404));
+        assert!(!healthy(missing.status));
+    }
+
+    #[tokio::test]
+    async fn probe_falls_back_to_get() {
+        let addr = serve().await;
+        let p = probe(&client(), format!("http://{}/no-head", addr), Duration::from_secs(2)).await;
+        assert_eq!(p.status, Some(200));
+    }
+
+    #[tokio::test]
+    async fn probe_times_out() {
+        let addr = serve().await;
+        let p = probe(&client(), format!("http://{}/slow", addr), Duration::from_millis(200)).await;
+        assert_eq!(p.status, None);
+        assert!(p.error.unwrap().starts_with("Timed out"));
+    }
+
+    #[test]
+    fn healthy_accepts_login_pages() {
+        assert!(healthy(Some(200)));
+        assert!(healthy(Some(301)));
+        assert!(healthy(Some(401)));
+        assert!(healthy(Some(403)));
+        assert!(!healthy(Some(404)));
+        assert!(!healthy(Some(500)));
+        assert!(!healthy(None));
+    }
+
+    #[test]
+    fn destinations_die_after_enough_failures() {
+        let c = database();
+        diesel::sql_query("insert into alias_destinations (alias, position, destination) values ('a', 0, 'http://a/')")
+            .execute(&c)
+            .unwrap();
+
+        for i in 1..DEAD_AFTER {
+            let (died, dead) = record(&c, &[failed("http://a/")], i as i64).unwrap();
+            assert!(died.is_empty());
+            assert_eq!(dead, 0);
+        }
+        let (died, dead) = record(&c, &[failed("http://a/")], DEAD_AFTER as i64).unwrap();
+        assert_eq!(died.len(), 1);
+        assert_eq!(dead, 1);
+
+        // Only reported the once.
+        let (died, dead) = record(&c, &[failed("http://a/")], 10).unwrap();
+        assert!(died.is_empty());
+        assert_eq!(dead, 1);
+
+        let fixed = Probe { status: Some(200), ..failed("http://a/") };
+        let (_, dead) = record(&c, &[fixed], 11).unwrap();
+        assert_eq!(dead, 0);
+        assert!(!load_all(&c).unwrap()["http://a/"].dead);
+    }
+
+    #[test]
+    fn unused_destinations_are_forgotten() {
+        let c = database();
+        record(&c, &[failed("http://gone/")], 1).unwrap();
+        assert!(load_all(&c).unwrap().is_empty());
+    }
+
+    #[test]
+    fn summarize_picks_the_worst() {
+        let all = vec![
+            ("http://up/".to_string(), checked(false)),
+            ("http://down/".to_string(), checked(true)),
+        ].into_iter().collect::<HashMap<_, _>>();
+
+        assert_eq!(summarize(vec!["http://up/", "http://down/"], &all), Some(checked(true)));
+        assert_eq!(summarize(vec!["http://up/"], &all), Some(checked(false)));
+        assert_eq!(summarize(vec!["http://unchecked/"], &all), None);
+    }
+}
@@ -0,0 +1,100 @@
+use std::path::{Path, PathBuf};
+
+use tokio::signal::unix::{signal, Signal, SignalKind};
+
+use alias::config::{self, AliasConfig, AuthConfig, LoggingConfig};
+
+use crate::cache;
+use crate::logging::LogReloader;
+
+/// SIGHUP, SIGTERM and SIGINT, registered before the server starts so none arrive unhandled.
+pub struct Signals {
+    hangup: Signal,
+    terminate: Signal,
+    interrupt: Signal,
+}
+
+impl Signals {
+    pub fn new() -> std::io::Result<Signals> {
+        Ok(Signals {
+            hangup: signal(SignalKind::hangup())?,
+            terminate: signal(SignalKind::terminate())?,
+            interrupt: signal(SignalKind::interrupt())?,
+        })
+    }
+
+    /// Reloads the config on SIGHUP, and calls `shutdown` on the first SIGTERM or SIGINT.
+    ///
+    /// A second SIGTERM or SIGINT exits without waiting for requests in flight.
+    pub async fn watch(mut self, file: PathBuf, logs: LogReloader, shutdown: impl FnOnce() + Send + 'static) {
+        loop {
+            tokio::select! {
+                _ = self.hangup.recv() => reload(&file, &logs).await,
+                _ = self.terminate.recv() => break,
+                _ = self.interrupt.recv() => break,
+            }
+        }
+
+        info!("Shutting down: no longer accepting connections, waiting for requests in flight");
+        shutdown();
+
+        tokio::select! {
+            _ = self.terminate.recv() => {},
+            _ = self.interrupt.recv() => {},
+        }
+        warn!("Signalled again, exiting without waiting");
+        std::process::exit(1);
+    }
+}
+
+/// Applies the settings that can change while running: `logging.filter`, `cache.size` and
+/// `auth.token_lifetime_hours`. Anything else that changed is reported and waits for a restart.
+async fn reload(file: &Path, logs: &LogReloader) {
+    let new = match AliasConfig::load(Some(file)) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Not reloading, {} is invalid: {}", file.display(), e);
+            return;
+        }
+    };
+    let old = config::get();
+
+    let restart_only = [
+        ("server", old.server != new.server),
+        ("tls", old.tls != new.tls),
+        ("database", old.database != new.database),
+        // It's also the password hashing secret, so changing it would lock everyone out.
+        ("auth.secret_key", old.auth.secret_key != new.auth.secret_key),
+        ("backup", old.backup != new.backup),
+        ("health", old.health != new.health),
+        ("metrics", old.metrics != new.metrics),
+        ("logging.format", old.logging.format != new.logging.format),
+        ("logging.otlp_endpoint", old.logging.otlp_endpoint != new.logging.otlp_endpoint),
+    ];
+    for (key, _) in restart_only.iter().filter(|(_, changed)| *changed) {
+        warn!("{} changed, but only takes effect after a restart", key);
+    }
+
+    if let Err(e) = logs.reload(&new.logging) {
+        error!("Couldn't apply logging.filter: {}", e);
+        return;
+    }
+    if new.cache.size != old.cache.size {
+        cache::resize(new.cache.size).await;
+    }
+
+    // What's stored is what's in effect, so the restart-only settings keep their old values.
+    config::replace(AliasConfig {
+        cache: new.cache,
+        auth: AuthConfig {
+            token_lifetime_hours: new.auth.token_lifetime_hours,
+            ..old.auth.clone()
+        },
+        logging: LoggingConfig {
+            filter: new.logging.filter,
+            ..old.logging.clone()
+        },
+        ..(*old).clone()
+    });
+    info!("Reloaded configuration from {}", file.display());
+}
@@ -0,0 +1,238 @@
+use std::future::Future;
+use std::time::Instant;
+
+use rocket::{Data, Request, Response};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::request::{FromRequest, Outcome};
+use tracing::Span;
+use tracing::field::Empty;
+use tracing_subscriber::{EnvFilter, Registry};
+use tracing_subscriber::fmt;
+use tracing_subscriber::reload;
+use tracing_futures::Instrument;
+use tracing_subscriber::prelude::*;
+
+use alias::config::{LogFormat, LoggingConfig};
+use alias::model::Claims;
+
+use crate::forwarded::ClientInfo;
+
+pub const REQUEST_ID: &str = "X-Request-Id";
+
+/// Keeps the OTLP exporter alive; dropping it flushes any spans that haven't been sent yet.
+pub struct LogGuard {
+    reloader: LogReloader,
+    _otlp: Option<Box<dyn std::any::Any>>,
+}
+
+impl LogGuard {
+    pub fn reloader(&self) -> LogReloader {
+        self.reloader.clone()
+    }
+}
+
+/// Swaps the filter of the installed subscriber.
+#[derive(Clone)]
+pub struct LogReloader {
+    handle: reload::Handle<EnvFilter, Registry>,
+    level: tracing::Level,
+}
+
+impl LogReloader {
+    /// Applies a reloaded `logging.filter`. The format and OTLP endpoint only change on restart.
+    pub fn reload(&self, cfg: &LoggingConfig) -> anyhow::Result<()> {
+        self.handle.reload(filter(cfg, self.level)?)?;
+        Ok(())
+    }
+}
+
+fn filter(cfg: &LoggingConfig, level: tracing::Level) -> anyhow::Result<EnvFilter> {
+    match &cfg.filter {
+        Some(directives) => EnvFilter::try_new(directives)
+            .map_err(|e| anyhow::anyhow!("logging.filter: {}", e)),
+        None => Ok(EnvFilter::new(level.to_string().to_lowercase())),
+    }
+}
+
+/// Installs the global subscriber, which Rocket's and the `log` crate's records are routed through too.
+///
+/// `logging.filter` takes `EnvFilter` directives like `info,alias=debug,hyper=warn`; without it, `level`
+/// (from `-v`) applies to everything. `logging.format = "json"` switches to one JSON object per line,
+/// and `logging.otlp_endpoint` exports spans to an OpenTelemetry collector when built with `otlp`.
+pub fn init(cfg: &LoggingConfig, level: tracing::Level) -> anyhow::Result<LogGuard> {
+    let (filter, handle) = reload::Layer::new(filter(cfg, level)?);
+    let reloader = LogReloader { handle, level };
+
+    let json = cfg.format == LogFormat::Json;
+    let json_layer = if json {
+        Some(fmt::layer().json().with_current_span(true).with_span_list(true))
+    } else {
+        None
+    };
+    let text_layer = if json { None } else { Some(fmt::layer()) };
+
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with(json_layer)
+        .with(text_layer);
+
+    let endpoint = cfg.otlp_endpoint.clone();
+
+    #[cfg(feature = "otlp")]
+    {
+        let (otel_layer, guard) = match endpoint {
+            Some(endpoint) => {
+                let (tracer, uninstall) = opentelemetry_otlp::new_pipeline()
+                    .with_endpoint(endpoint)
+                    .with_service_name("aliasd")
+                    .install()
+                    .map_err(|e| anyhow::anyhow!("Couldn't start OTLP exporter: {}", e))?;
+                let guard: Box<dyn std::any::Any> = Box::new(uninstall);
+                (Some(tracing_opentelemetry::layer().with_tracer(tracer)), Some(guard))
+            }
+            None => (None, None),
+        };
+        registry.with(otel_layer).try_init()?;
+        Ok(LogGuard { reloader, _otlp: guard })
+    }
+
+    #[cfg(not(feature = "otlp"))]
+    {
+        registry.try_init()?;
+        if endpoint.is_some() {
+            warn!("logging.otlp_endpoint is set, but aliasd was built without the otlp feature.");
+        }
+        Ok(LogGuard { reloader, _otlp: None })
+    }
+}
+
+/// The span everything about one request is logged under, and the ID it's known by.
+///
+/// Handlers take it as a guard to run their body in the span, and to record which alias they're working on.
+#[derive(Debug, Clone)]
+pub struct RequestSpan {
+    pub id: String,
+    pub span: Span,
+    started: Instant,
+}
+
+impl RequestSpan {
+    pub fn record_alias(&self, name: &str) {
+        self.span.record("alias", &name);
+    }
+
+    /// Runs a handler's body inside the span. Rocket polls handlers outside of it, so without this
+    /// what they log wouldn't carry the request's ID.
+    pub async fn run<F: Future>(&self, body: F) -> F::Output {
+        body.instrument(self.span.clone()).await
+    }
+
+    fn detached() -> RequestSpan {
+        RequestSpan { id: new_id(), span: Span::none(), started: Instant::now() }
+    }
+}
+
+#[rocket::async_trait]
+impl<'a, 'r> FromRequest<'a, 'r> for RequestSpan {
+    type Error = ();
+
+    async fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(request.local_cache(RequestSpan::detached).clone())
+    }
+}
+
+fn new_id() -> String {
+    format!("{:016x}", rand::random::<u64>())
+}
+
+/// Only IDs that are safe to log and echo back are reused; anything else gets a fresh one.
+fn valid_id(id: &&str) -> bool {
+    !id.is_empty() && id.len() <= 128
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+/// Opens a span for every request, reusing the caller's `X-Request-Id` if it sent one, and echoes the ID back.
+pub struct RequestTracing;
+
+#[rocket::async_trait]
+impl Fairing for RequestTracing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request tracing",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &Data) {
+        let id = request.headers().get_one(REQUEST_ID)
+            .filter(valid_id)
+            .map(String::from)
+            .unwrap_or_else(new_id);
+
+        // Everything logged for the request carries this, so it needs to be the client rather than a proxy.
+        let client = ClientInfo::of(request).ip.map_or_else(|| "unknown".to_string(), |ip| ip.to_string());
+        let span = tracing::info_span!("request",
+            id = %id,
+            client = %client,
+            method = %request.method(),
+            path = %request.uri().path(),
+            user = Empty,
+            alias = Empty);
+
+        request.local_cache(|| RequestSpan { id, span, started: Instant::now() });
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let req = request.local_cache(RequestSpan::detached);
+        // Only the token's claims are recorded; checking them against the database is the handler's job.
+        if let Some(claims) = Claims::unverified(request) {
+            req.span.record("user", &claims.user.as_str());
+        }
+
+        response.set_raw_header(REQUEST_ID, req.id.clone());
+
+        let route = request.route().and_then(|r| r.name).unwrap_or("unmatched");
+        req.span.in_scope(|| tracing::info!(
+            route,
+            status = response.status().code,
+            latency_ms = req.started.elapsed().as_millis() as u64,
+            "finished request"));
+    }
+}
+
+#[cfg(all(test, feature = "otlp"))]
+mod tests {
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    use super::*;
+
+    /// The collector is a bare socket that doesn't speak gRPC, but the exporter connecting to it at
+    /// all shows finished spans are being sent there.
+    #[test]
+    fn exports_spans_to_the_collector() {
+        let collector = TcpListener::bind("127.0.0.1:0").unwrap();
+        let cfg = LoggingConfig {
+            otlp_endpoint: Some(format!("http://{}", collector.local_addr().unwrap())),
+            ..LoggingConfig::default()
+        };
+
+        let (connected, accepted) = mpsc::channel();
+        std::thread::spawn(move || {
+            if let Ok((stream, _)) = collector.accept() {
+                let _ = connected.send(());
+                // Held open so the exporter doesn't see a reset before it's done writing.
+                std::thread::sleep(Duration::from_secs(1));
+                drop(stream);
+            }
+        });
+
+        let guard = init(&cfg, tracing::Level::INFO).unwrap();
+        tracing::info_span!("request", id = "0123456789abcdef").in_scope(|| info!("finished request"));
+        // Flushes whatever hasn't been exported yet.
+        drop(guard);
+
+        accepted.recv_timeout(Duration::from_secs(10)).expect("the exporter never connected");
+    }
+}
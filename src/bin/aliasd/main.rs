@@ -5,227 +5,936 @@ extern crate rocket;
 #[macro_use]
 extern crate serde_json;
 
+use std::collections::HashMap;
 use std::io::Cursor;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use clap::{App, AppSettings, Arg};
-use diesel::{ExpressionMethods, QueryResult, RunQueryDsl};
+use diesel::{BoolExpressionMethods, Connection, ExpressionMethods, OptionalExtension, QueryDsl, QueryResult, RunQueryDsl, SqliteConnection};
 use rocket::{Config, Response};
-use rocket::http::{ContentType, Cookie, CookieJar, Status};
+use rocket::http::{ContentType, CookieJar, Status};
 use rocket_contrib::helmet::SpaceHelmet;
 use rocket_contrib::json::Json;
-use tracing::Level;
-use url::Url;
 
 use alias::*;
+use alias::config::AliasConfig;
 use alias::db::conn;
-use alias::model::{AliasForm, Claims, Login, LoginFailure};
+use alias::model::{AliasForm, AliasInfo, AliasSaved, Claims, Login, LoginFailure, Redirected, WebhookEvent, WeightedDestination};
 
+use crate::access::{Access, Viewer};
 use crate::cache::AliasSearchFailure;
+use crate::domains::{Domain, WriteAccess};
+use crate::forwarded::ClientInfo;
+use crate::logging::RequestSpan;
+use crate::negotiate::ResponseFormat;
+use crate::rules::RequestInfo;
 
 
 mod users;
 mod cache;
+mod snapshots;
+mod account;
+mod ui;
+mod negotiate;
+mod pages;
+mod preview;
+mod suggest;
+mod rotate;
+mod rules;
+mod access;
+mod health;
+mod webhooks;
+mod admin;
+mod metrics;
+mod probes;
+mod logging;
+mod lifecycle;
+mod tls;
+mod forwarded;
+mod domains;
+mod openapi;
 
 #[post("/login", data = "<login_form>")]
-async fn login<'a>(cookies: &'a CookieJar<'_>, login_form: Json<Login>) -> rocket::response::Response<'a> {
-    let valid = model::validate_user(
-        login_form.username.clone(),
-        (&login_form.password).as_ref(),
-    ).await;
-
-    if let Err(e) = &valid {
-        let status = match e {
-            LoginFailure::BadLogin => { rocket::http::Status::Forbidden }
-            LoginFailure::SqlError(e) => {
-                error!("{}", e);
-                rocket::http::Status::InternalServerError
-            }
-        };
+async fn login<'a>(cookies: &'a CookieJar<'_>, client: ClientInfo, login_form: Json<Login>, span: RequestSpan) -> rocket::response::Response<'a> {
+    span.run(async move {
+        let valid = model::validate_user(
+            login_form.username.clone(),
+            (&login_form.password).as_ref(),
+        ).await;
 
-        return Response::build()
-            .status(status)
-            .finalize();
-    }
+        if let Err(e) = &valid {
+            let (status, body) = match e {
+                LoginFailure::BadLogin => {
+                    ::alias::metrics::LOGINS.with_label_values(&["failure"]).inc();
+                    (rocket::http::Status::Forbidden, json!({
+                        "message": "Invalid login info."
+                    }))
+                }
+                LoginFailure::SqlError(e) => {
+                    error!("{}", e);
+                    ::alias::metrics::LOGINS.with_label_values(&["error"]).inc();
+                    (rocket::http::Status::InternalServerError, json!({
+                        "message": "An internal error occurred."
+                    }))
+                }
+            };
 
-    let token = model::jwt_generate(valid.unwrap());
-    cookies.add(Cookie::new("auth", token));
+            let body = body.to_string();
+            return Response::build()
+                .status(status)
+                .header(ContentType::JSON)
+                .sized_body(body.len(), Cursor::new(body))
+                .finalize();
+        }
 
-    Response::new()
+        ::alias::metrics::LOGINS.with_label_values(&["success"]).inc();
+        let token = model::jwt_generate(valid.unwrap());
+        cookies.add(client.cookie("auth", token.token.clone()));
+
+        let body = json!(token).to_string();
+        Response::build()
+            .header(ContentType::JSON)
+            .sized_body(body.len(), Cursor::new(body))
+            .finalize()
+    }).await
 }
 
 #[delete("/login")]
-async fn logout<'a>(cookies: &'a CookieJar<'_>) -> Response<'a> {
-    let mut resp = Response::new();
-    let login_cook = cookies.get("auth");
+async fn logout<'a>(cookies: &'a CookieJar<'_>, client: ClientInfo, span: RequestSpan) -> Response<'a> {
+    span.run(async move {
+        let mut resp = Response::new();
+        let login_cook = cookies.get("auth");
 
-    if login_cook.is_none() {
-        resp.set_status(Status::Forbidden);
-        return resp;
-    }
+        if login_cook.is_none() {
+            resp.set_status(Status::Forbidden);
+            return resp;
+        }
 
-    cookies.remove(Cookie::named("auth"));
+        cookies.remove(client.cookie("auth", String::new()));
 
-    resp
+        resp
+    }).await
 }
 
 #[post("/alias", data = "<alias_form>")]
-async fn new_or_update_alias(user: Claims, alias_form: Json<AliasForm>) -> Response<'static> {
-    let mut resp = Response::new();
-    let dest = Url::parse(&alias_form.to);
+async fn new_or_update_alias(user: Claims, alias_form: Json<AliasForm>, requested: Domain, span: RequestSpan) -> Response<'static> {
+    span.record_alias(&alias_form.from);
+    span.run(async move {
+        // The form's domain wins over the one the request was sent to.
+        let dom = alias_form.domain.as_deref().map(domains::normalize).unwrap_or(requested.0);
+        let mut resp = Response::new();
+        if let Err(e) = check_name(&alias_form.from) {
+            resp.set_status(Status::BadRequest);
+            resp.set_header(ContentType::JSON);
+            let body = json!({
+                "message": "Reserved alias name",
+                "info": e,
+            }).to_string();
+            resp.set_sized_body(body.len(), Cursor::new(body));
+            return resp;
+        }
+
+        let dest = match (&alias_form.canonical, alias_form.destinations.first()) {
+            // Aliases of another alias don't keep a destination of their own.
+            (Some(_), _) => Ok(String::new()),
+            (None, Some(first)) => model::parse_destination(&first.to).map(|d| d.to_string()),
+            (None, None) => model::parse_destination(&alias_form.to).map(|d| d.to_string()),
+        };
 
-    let dest = match dest {
-        Ok(d) => {
-            d.to_string()
+        let dest = match dest {
+            Ok(d) => d,
+            Err(e) => {
+                resp.set_status(Status::BadRequest);
+                resp.set_header(ContentType::JSON);
+                let body = json!({
+                    "message": "Invalid URL",
+                    "info": e,
+                }).to_string();
+                resp.set_sized_body(body.len(), Cursor::new(body));
+                return resp;
+            }
+        };
+
+        let redirect = alias_form.redirect.unwrap_or(model::DEFAULT_REDIRECT);
+        if !model::REDIRECT_STATUSES.contains(&redirect) {
+            resp.set_status(Status::BadRequest);
+            resp.set_header(ContentType::JSON);
+            let body = json!({
+                "message": "Invalid redirect status",
+                "info": format!("Must be one of {:?}.", model::REDIRECT_STATUSES),
+            }).to_string();
+            resp.set_sized_body(body.len(), Cursor::new(body));
+            return resp;
         }
-        Err(e) => {
+
+        if alias_form.canonical.is_some() && (!alias_form.destinations.is_empty() || !alias_form.rules.is_empty()) {
             resp.set_status(Status::BadRequest);
             resp.set_header(ContentType::JSON);
             let body = json!({
-                "message": "Invalid URL",
-                "info": format!("{}", e),
+                "message": "An alias of another alias can't have destinations or rules of its own",
             }).to_string();
             resp.set_sized_body(body.len(), Cursor::new(body));
             return resp;
         }
-    };
 
-    info!("Added alias {} to {} for user {}",
-          &alias_form.from,
-          &dest,
-          &user.user
-    );
+        let mut targets = Vec::with_capacity(alias_form.destinations.len());
+        for t in &alias_form.destinations {
+            let info = match model::parse_destination(&t.to) {
+                Ok(_) if t.weight == 0 || t.weight > model::MAX_WEIGHT => {
+                    format!("Weights must be between 1 and {}.", model::MAX_WEIGHT)
+                }
+                Ok(u) => {
+                    targets.push(WeightedDestination { to: u.to_string(), weight: t.weight });
+                    continue;
+                }
+                Err(e) => e,
+            };
 
-    let d = dest.clone();
-    let orig = alias_form.from.clone();
+            resp.set_status(Status::BadRequest);
+            resp.set_header(ContentType::JSON);
+            let body = json!({
+                "message": "Invalid destination",
+                "info": info,
+            }).to_string();
+            resp.set_sized_body(body.len(), Cursor::new(body));
+            return resp;
+        }
 
-    let result = conn()
-        .with_conn(move |c| {
-            use ::alias::schema::aliases::dsl::*;
-            diesel::replace_into(aliases)
-                .values((creator.eq(&user.user_id),
-                         alias.eq(&alias_form.from),
-                         destination.eq(dest)))
-                .execute(c)
+        let rule_list = match rules::validate(&alias_form.rules) {
+            Ok(r) => r,
+            Err(e) => {
+                resp.set_status(Status::BadRequest);
+                resp.set_header(ContentType::JSON);
+                let body = json!({
+                    "message": "Invalid rule",
+                    "info": e,
+                }).to_string();
+                resp.set_sized_body(body.len(), Cursor::new(body));
+                return resp;
+            }
+        };
+
+        let tag_list = match model::normalize_tags(&alias_form.tags) {
+            Ok(t) => model::join_tags(&t),
+            Err(e) => {
+                resp.set_status(Status::BadRequest);
+                resp.set_header(ContentType::JSON);
+                let body = json!({
+                    "message": "Invalid tags",
+                    "info": e,
+                }).to_string();
+                resp.set_sized_body(body.len(), Cursor::new(body));
+                return resp;
+            }
+        };
+
+        info!("Added alias {} to {} for user {}",
+              &alias_form.from,
+              alias_form.canonical.as_deref().unwrap_or(&dest),
+              &user.user
+        );
+
+        let d = alias_form.canonical.clone().unwrap_or_else(|| dest.clone());
+        let orig = alias_form.from.clone();
+        let orig_domain = dom.clone();
+        let now = chrono::Local::now().timestamp();
+
+        let result = conn()
+            .with_conn(move |c| {
+                use ::alias::schema::aliases::dsl::*;
+                let c: &SqliteConnection = c;
+                c.transaction::<_, diesel::result::Error, _>(|| {
+                    match domains::write_access(c, &dom, user.user_id)? {
+                        WriteAccess::Allowed => {}
+                        denied => return Ok(AliasWrite::Domain(denied)),
+                    }
+
+                    let existing: Option<(bool, i32, String)> = aliases.select((managed, creator, visibility))
+                        .filter(domain.eq(&dom))
+                        .filter(alias.eq(&alias_form.from))
+                        .get_result(c)
+                        .optional()?;
+
+                    // Anyone may fix up a public alias, but only its creator or the domain's owners may
+                    // change one that's restricted, or restrict one that isn't.
+                    if let Some((_, owner, vis)) = &existing {
+                        let restricted = vis != model::Visibility::Public.as_str() || alias_form.visibility != model::Visibility::Public;
+                        if restricted && *owner != user.user_id && !domains::is_owner(c, &dom, user.user_id)? {
+                            return Ok(AliasWrite::NotCreator);
+                        }
+                    }
+
+                    if let Some(target) = &alias_form.canonical {
+                        match cache::check_canonical(c, &dom, &alias_form.from, target) {
+                            Ok(()) => {}
+                            Err(AliasSearchFailure::Sql(e)) => return Err(e),
+                            Err(e) => return Ok(AliasWrite::BadCanonical(e)),
+                        }
+                    }
+
+                    let written = match existing.as_ref().map(|e| e.0) {
+                        Some(true) if !alias_form.managed => Ok(AliasWrite::Managed),
+                        // Updates keep the original creator, who is the only one allowed to delete the alias.
+                        Some(_) => {
+                            diesel::update(aliases.filter(domain.eq(&dom)).filter(alias.eq(&alias_form.from)))
+                                .set((destination.eq(&dest),
+                                      managed.eq(alias_form.managed),
+                                      redirect_status.eq(redirect as i32),
+                                      canonical.eq(&alias_form.canonical),
+                                      strategy.eq(alias_form.strategy.as_str()),
+                                      visibility.eq(alias_form.visibility.as_str()),
+                                      description.eq(&alias_form.description),
+                                      tags.eq(&tag_list),
+                                      updated_at.eq(now),
+                                      modified_by.eq(user.user_id)))
+                                .execute(c)
+                                .map(AliasWrite::Written)
+                        }
+                        None => {
+                            diesel::insert_into(aliases)
+                                .values((creator.eq(user.user_id),
+                                         domain.eq(&dom),
+                                         alias.eq(&alias_form.from),
+                                         destination.eq(&dest),
+                                         managed.eq(alias_form.managed),
+                                         redirect_status.eq(redirect as i32),
+                                         canonical.eq(&alias_form.canonical),
+                                         strategy.eq(alias_form.strategy.as_str()),
+                                         visibility.eq(alias_form.visibility.as_str()),
+                                         description.eq(&alias_form.description),
+                                         tags.eq(&tag_list),
+                                         created_at.eq(now),
+                                         updated_at.eq(now),
+                                         modified_by.eq(user.user_id)))
+                                .execute(c)
+                                .map(AliasWrite::Written)
+                        }
+                    }?;
+
+                    if let AliasWrite::Written(_) = written {
+                        replace_destinations(c, &dom, &alias_form.from, &targets)?;
+                        rules::replace_rules(c, &dom, &alias_form.from, &rule_list)?;
+
+                        let event = if existing.is_some() { WebhookEvent::AliasUpdated } else { WebhookEvent::AliasCreated };
+                        ::alias::webhooks::enqueue(c, event, json!({
+                            "alias": &alias_form.from,
+                            "domain": &dom,
+                            "destination": &dest,
+                            "canonical": &alias_form.canonical,
+                            "visibility": alias_form.visibility,
+                            "user": &user.user
+                        }))?;
+                    }
+                    Ok(written)
+                })
+            }).await;
+
+        resp.set_header(ContentType::JSON);
+        let body = match result {
+            Ok(AliasWrite::Managed) => {
+                resp.set_status(Status::Conflict);
+                json!({
+                    "message": "Alias is managed by a declarative config",
+                    "info": "Change it in the alias file and run `alias-client apply` instead.",
+                    "alias": orig
+                })
+            }
+            Ok(AliasWrite::NotCreator) => {
+                resp.set_status(Status::Forbidden);
+                json!({
+                    "message": "Only the alias's creator or the domain's owners can change it",
+                    "info": "Anyone can edit a public alias, but not restrict who it's visible to.",
+                    "alias": orig
+                })
+            }
+            Ok(AliasWrite::Domain(WriteAccess::NoSuchDomain)) => {
+                resp.set_status(Status::NotFound);
+                json!({
+                    "message": "No such domain",
+                    "domain": orig_domain
+                })
+            }
+            Ok(AliasWrite::Domain(_)) => {
+                resp.set_status(Status::Forbidden);
+                json!({
+                    "message": "Only the domain's owners can change its aliases",
+                    "domain": orig_domain
+                })
+            }
+            Ok(AliasWrite::BadCanonical(e)) => {
+                resp.set_status(Status::BadRequest);
+                let message = match e {
+                    AliasSearchFailure::NoSuchAlias => "Canonical alias doesn't exist".to_string(),
+                    e => e.to_string(),
+                };
+                json!({
+                    "message": message,
+                    "alias": orig
+                })
+            }
+            Ok(AliasWrite::Written(u)) if u != 1 => {
+                resp.set_status(Status::InternalServerError);
+                json!({
+                    "message": "failed to add alias"
+                })
+            }
+            Err(e) => {
+                resp.set_status(Status::InternalServerError);
+                error!("{}", e);
+                json!({
+                    "message": "failed to add alias"
+                })
+            }
+            _ => {
+                cache::evict_alias(orig_domain.clone(), orig.clone()).await;
+                resp.set_status(Status::Created);
+                json!(AliasSaved {
+                    message: "Added alias.".to_string(),
+                    domain: orig_domain,
+                    from: orig,
+                    to: d,
+                })
+            }
+        }.to_string();
+        resp.set_sized_body(body.len(), Cursor::new(body));
+
+        resp
+    }).await
+}
+
+enum AliasWrite {
+    Written(usize),
+    Managed,
+    /// Someone else's alias that's restricted, or would become so.
+    NotCreator,
+    BadCanonical(AliasSearchFailure),
+    Domain(WriteAccess),
+}
+
+fn replace_destinations(c: &SqliteConnection, dom: &str, name: &str, targets: &[WeightedDestination]) -> QueryResult<()> {
+    use ::alias::schema::alias_destinations::dsl::*;
+    diesel::delete(alias_destinations.filter(domain.eq(dom)).filter(alias.eq(name))).execute(c)?;
+    for (i, t) in targets.iter().enumerate() {
+        diesel::insert_into(alias_destinations)
+            .values((domain.eq(dom),
+                     alias.eq(name),
+                     position.eq(i as i32),
+                     destination.eq(&t.to),
+                     weight.eq(t.weight as i32)))
+            .execute(c)?;
+    }
+    Ok(())
+}
+
+enum AliasDelete {
+    Deleted(usize),
+    Managed,
+    Domain(WriteAccess),
+    /// Other aliases resolve through this one, and would be left dangling.
+    HasDependents(Vec<String>),
+}
+
+#[get("/alias")]
+async fn list_aliases(user: Claims, requested: Domain, span: RequestSpan) -> Response<'static> {
+    span.run(async move {
+        let dom = requested.0;
+        let res: QueryResult<Vec<AliasInfo>> = conn().with_conn(move |c| {
+            use ::alias::schema::{alias_destinations, alias_rules, aliases, users};
+            let usernames: HashMap<i32, String> = users::table
+                .select((users::id, users::username))
+                .load::<(i32, String)>(c)?
+                .into_iter()
+                .collect();
+
+            let mut targets: HashMap<String, Vec<WeightedDestination>> = HashMap::new();
+            for (name, to, weight) in alias_destinations::table
+                .select((alias_destinations::alias, alias_destinations::destination, alias_destinations::weight))
+                .filter(alias_destinations::domain.eq(&dom))
+                .order((alias_destinations::alias, alias_destinations::position))
+                .load::<(String, String, i32)>(c)? {
+                targets.entry(name).or_default().push(WeightedDestination { to, weight: weight as u32 });
+            }
+
+            let checks = health::load_all(c)?;
+
+            let mut rule_sets: HashMap<String, Vec<model::Rule>> = HashMap::new();
+            for (name, cond, to) in alias_rules::table
+                .select((alias_rules::alias, alias_rules::condition, alias_rules::destination))
+                .filter(alias_rules::domain.eq(&dom))
+                .order((alias_rules::alias, alias_rules::position))
+                .load::<(String, String, String)>(c)? {
+                if let Some(rule) = rules::decode(&cond, to) {
+                    rule_sets.entry(name).or_default().push(rule);
+                }
+            }
+
+            // Other users' owner-only aliases aren't theirs to see, nor are domain-only ones unless they own it.
+            let mut hidden = vec![model::Visibility::Owner.as_str()];
+            if !domains::is_owner(c, &dom, user.user_id)? {
+                hidden.push(model::Visibility::Domain.as_str());
+            }
+
+            aliases::table
+                .select((aliases::alias,
+                         aliases::destination,
+                         aliases::creator,
+                         aliases::managed,
+                         aliases::redirect_status,
+                         aliases::description,
+                         aliases::tags,
+                         aliases::canonical,
+                         aliases::strategy,
+                         aliases::visibility,
+                         aliases::created_at,
+                         aliases::updated_at,
+                         aliases::modified_by))
+                .filter(aliases::domain.eq(&dom))
+                .filter(aliases::visibility.ne_all(hidden).or(aliases::creator.eq(user.user_id)))
+                .order(aliases::alias)
+                .load::<(String, String, i32, bool, i32, String, String, Option<String>, String, String, i64, i64, Option<i32>)>(c)
+                .map(|rows| {
+                    rows.into_iter()
+                        .map(|(alias, destination, creator, managed, redirect, description, tags, canonical, strategy, visibility, created_at, updated_at, modified_by)| AliasInfo {
+                            visibility: visibility.parse().unwrap_or(model::Visibility::Owner),
+                            // Before the destinations and rules are moved out below.
+                            health: health::summarize(
+                                std::iter::once(destination.as_str())
+                                    .chain(targets.get(&alias).into_iter().flatten().map(|t| t.to.as_str()))
+                                    .chain(rule_sets.get(&alias).into_iter().flatten().map(|r| r.to.as_str())),
+                                &checks),
+                            destinations: targets.remove(&alias).unwrap_or_default(),
+                            rules: rule_sets.remove(&alias).unwrap_or_default(),
+                            strategy: strategy.parse().unwrap_or_default(),
+                            alias,
+                            domain: dom.clone(),
+                            destination,
+                            owner: usernames.get(&creator).cloned().unwrap_or_default(),
+                            managed,
+                            redirect: redirect as u16,
+                            description,
+                            tags: model::split_tags(&tags),
+                            canonical,
+                            created_at,
+                            updated_at,
+                            modified_by: modified_by.and_then(|id| usernames.get(&id).cloned()),
+                        })
+                        .collect()
+                })
         }).await;
 
-    resp.set_header(ContentType::JSON);
-    let body = match result {
-        Ok(u) if u != 1 => {
-            resp.set_status(Status::InternalServerError);
-            json!({
-                "message": "failed to add alias"
-            })
-        }
-        Err(e) => {
-            resp.set_status(Status::InternalServerError);
-            error!("{}", e);
-            json!({
-                "message": "failed to add alias"
+        let mut resp = Response::new();
+        resp.set_header(ContentType::JSON);
+
+        let body = match res {
+            Ok(list) => json!(list),
+            Err(e) => {
+                error!("{}", e);
+                resp.set_status(Status::InternalServerError);
+                json!({
+                    "message": "Internal server error"
+                })
+            }
+        }.to_string();
+        resp.set_sized_body(body.len(), Cursor::new(body));
+
+        resp
+    }).await
+}
+
+#[get("/search?<q>")]
+async fn search_aliases(user: Claims, q: String, requested: Domain, span: RequestSpan) -> Response<'static> {
+    span.run(async move {
+        let res = search::search(q, requested.0, user.user_id).await;
+
+        let mut resp = Response::new();
+        resp.set_header(ContentType::JSON);
+
+        let body = match res {
+            Ok(hits) => json!(hits),
+            Err(e) => {
+                error!("{}", e);
+                resp.set_status(Status::InternalServerError);
+                json!({
+                    "message": "Internal server error"
+                })
+            }
+        }.to_string();
+        resp.set_sized_body(body.len(), Cursor::new(body));
+
+        resp
+    }).await
+}
+
+#[delete("/<alias>?<managed>")]
+async fn delete_alias(user: Claims, alias: String, managed: Option<bool>, requested: Domain, span: RequestSpan) -> Response<'static> {
+    span.record_alias(&alias);
+    span.run(async move {
+        let a = Arc::new(alias);
+        let qa = a.clone();
+        let dom = requested.0;
+        let qd = dom.clone();
+        let force = managed.unwrap_or(false);
+        let res: QueryResult<AliasDelete> = conn().with_conn(move |c| {
+            use ::alias::schema::aliases::dsl::*;
+            let c: &SqliteConnection = c;
+            c.transaction(|| {
+                match domains::write_access(c, &qd, user.user_id)? {
+                    WriteAccess::Allowed => {}
+                    denied => return Ok(AliasDelete::Domain(denied)),
+                }
+
+                let is_managed: Option<bool> = aliases.select(managed)
+                    .filter(creator.eq(user.user_id))
+                    .filter(domain.eq(&qd))
+                    .filter(alias.eq(&*qa))
+                    .get_result(c)
+                    .optional()?;
+
+                if is_managed == Some(true) && !force {
+                    return Ok(AliasDelete::Managed);
+                }
+
+                if is_managed.is_some() {
+                    let deps = cache::direct_dependents(c, &qd, &qa)?;
+                    if !deps.is_empty() {
+                        return Ok(AliasDelete::HasDependents(deps));
+                    }
+                    // Foreign keys aren't enforced on this connection, so the cascade has to be done by hand.
+                    replace_destinations(c, &qd, &qa, &[])?;
+                    rules::replace_rules(c, &qd, &qa, &[])?;
+                }
+
+                let deleted = diesel::delete(aliases)
+                    .filter(creator.eq(user.user_id))
+                    .filter(domain.eq(&qd))
+                    .filter(alias.eq(&*qa))
+                    .execute(c)?;
+                if deleted == 1 {
+                    ::alias::webhooks::enqueue(c, WebhookEvent::AliasDeleted, json!({
+                        "alias": &*qa,
+                        "domain": &qd,
+                        "user": &user.user
+                    }))?;
+                }
+                Ok(AliasDelete::Deleted(deleted))
             })
+        }).await;
+
+        let mut resp = Response::new();
+
+        resp.set_header(ContentType::JSON);
+
+        match res {
+            Ok(AliasDelete::Domain(WriteAccess::NoSuchDomain)) => {
+                resp.set_status(Status::NotFound);
+                let body = json!({
+                    "message": "No such domain",
+                    "domain": &dom
+                }).to_string();
+                resp.set_sized_body(body.len(), Cursor::new(body));
+            }
+            Ok(AliasDelete::Domain(_)) => {
+                resp.set_status(Status::Forbidden);
+                let body = json!({
+                    "message": "Only the domain's owners can change its aliases",
+                    "domain": &dom
+                }).to_string();
+                resp.set_sized_body(body.len(), Cursor::new(body));
+            }
+            Ok(AliasDelete::HasDependents(deps)) => {
+                resp.set_status(Status::Conflict);
+                let body = json!({
+                    "message": "Other aliases resolve through this one",
+                    "info": format!("Repoint or delete {} first.", deps.join(", ")),
+                    "alias": a.as_str(),
+                    "dependents": deps
+                }).to_string();
+                resp.set_sized_body(body.len(), Cursor::new(body));
+            }
+            Ok(AliasDelete::Managed) => {
+                resp.set_status(Status::Conflict);
+                let body = json!({
+                    "message": "Alias is managed by a declarative config",
+                    "info": "Remove it from the alias file and run `alias-client apply --prune` instead.",
+                    "alias": a.as_str()
+                }).to_string();
+                resp.set_sized_body(body.len(), Cursor::new(body));
+            }
+            Ok(AliasDelete::Deleted(s)) if s != 1 => {
+                resp.set_status(Status::NotFound);
+                let body = json!({
+                    "message": "No such alias"
+                }).to_string();
+                resp.set_sized_body(body.len(), Cursor::new(body));
+            }
+            Err(e) => {
+                error!("{}", e);
+                resp.set_status(Status::InternalServerError);
+                let body = json!({
+                    "message": "Internal server error"
+                }).to_string();
+                resp.set_sized_body(body.len(), Cursor::new(body));
+            }
+            _ => {
+                cache::evict_alias(dom.clone(), a.to_string()).await;
+                let body = json!({
+                    "message": "Successfully deleted alias",
+                    "alias": a.as_str()
+                }).to_string();
+                resp.set_sized_body(body.len(), Cursor::new(body));
+            }
+        };
+
+        resp
+    }).await
+}
+
+#[get("/<alias>")]
+async fn get_alias(alias: String,
+                   format: ResponseFormat,
+                   req: RequestInfo,
+                   viewer: Viewer,
+                   requested: Domain,
+                   span: RequestSpan,
+                   client: ClientInfo,
+                   cookies: &CookieJar<'_>) -> Response<'static> {
+    span.record_alias(&alias);
+    span.run(async move {
+        if let Some(name) = alias.strip_suffix('+') {
+            return preview_alias(requested.0, name.to_string(), format, viewer).await;
         }
-        _ => {
-            resp.set_status(Status::Created);
-            json!({
-                "message": "Added alias.",
-                "from": orig,
-                "to": d
-            })
+
+        let res_dest = cache::get_alias(requested.0.clone(), alias.clone()).await;
+
+        if let Ok(d) = &res_dest {
+            if let Some(resp) = deny(&alias, format, d, &viewer).await {
+                return resp;
+            }
         }
-    }.to_string();
-    resp.set_sized_body(body.len(), Cursor::new(body));
 
-    resp
-}
+        let mut resp = Response::new();
+        resp.set_header(ContentType::JSON);
 
-#[delete("/<alias>")]
-async fn delete_alias(user: Claims, alias: String) -> Response<'static> {
-    let a = Arc::new(alias);
-    let qa = a.clone();
-    let res: QueryResult<usize> = conn().with_conn(move |c| {
-        use ::alias::schema::aliases::dsl::*;
-        diesel::delete(aliases)
-            .filter(creator.eq(user.user_id))
-            .filter(alias.eq(&*qa))
-            .execute(c)
-    }).await;
+        match res_dest {
+            Err(AliasSearchFailure::NoSuchAlias) if format == ResponseFormat::Html => {
+                let suggestions = suggest::similar(requested.0.clone(), alias.clone()).await
+                    .unwrap_or_else(|e| {
+                        error!("{}", e);
+                        Vec::new()
+                    });
 
-    let mut resp = Response::new();
+                resp.set_status(Status::NotFound);
+                resp.set_header(ContentType::HTML);
+                let body = pages::not_found(&alias, &suggestions);
+                resp.set_sized_body(body.len(), Cursor::new(body));
+            }
+            Err(e) => {
+                let
+                    body = match e {
+                    AliasSearchFailure::NoSuchAlias => {
+                        resp.set_status(Status::NotFound);
+                        json!({
+                        "message": "No such alias",
+                        "alias": alias
+                    })
+                    }
+                    AliasSearchFailure::Sql(e) => {
+                        error!("{}", e);
+                        resp.set_status(Status::InternalServerError);
+                        json!({
+                        "message": "An internal error occurred."
+                    })
+                    }
+                    e @ AliasSearchFailure::Cycle(_) | e @ AliasSearchFailure::TooDeep(_) => {
+                        error!("{}", e);
+                        resp.set_status(Status::new(508, "Loop Detected"));
+                        json!({
+                        "message": e.to_string(),
+                        "alias": alias
+                    })
+                    }
+                }.to_string();
 
-    resp.set_header(ContentType::JSON);
+                resp.set_sized_body(body.len(), Cursor::new(body));
+            },
+            Ok(d) => {
+                let to = match rules::evaluate(&d.rules, &req) {
+                    Some(to) => to.to_string(),
+                    None => {
+                        let bucket = match d.strategy {
+                            model::Strategy::Sticky if !d.targets.is_empty() => Some(sticky_bucket(cookies, &client)),
+                            _ => None,
+                        };
+                        rotate::choose(&d, bucket).to_string()
+                    }
+                };
 
-    match res {
-        Ok(s) if s != 1 => {
-            resp.set_status(Status::NotFound);
-            let body = json!({
-                "message": "No such alias"
-            }).to_string();
-            resp.set_sized_body(body.len(), Cursor::new(body));
+                resp.set_status(Status::from_code(d.redirect).unwrap_or(Status::Found));
+                if !d.restrictions.is_empty() {
+                    // A shared cache would hand this to the next visitor without asking who they are.
+                    resp.set_raw_header("Cache-Control", "private, no-store");
+                } else if !d.targets.is_empty() || !d.rules.is_empty() {
+                    // Each request may go somewhere else, so don't let anything remember this one.
+                    resp.set_raw_header("Cache-Control", "no-store");
+                }
+                let body = match format {
+                    ResponseFormat::Html => {
+                        resp.set_header(ContentType::HTML);
+                        pages::redirect(&to)
+                    }
+                    ResponseFormat::Json => json!(Redirected {
+                        message: "redirected".to_string(),
+                        to: to.clone(),
+                    }).to_string(),
+                };
+                resp.set_raw_header("Location", to);
+                resp.set_sized_body(body.len(), Cursor::new(body));
+            }
         }
+
+        resp
+    }).await
+}
+
+/// Builds the response for a request that isn't allowed to follow `alias`, or `None` if it is.
+///
+/// Browsers that aren't logged in are sent to the login page, which brings them back here afterwards.
+async fn deny(alias: &str, format: ResponseFormat, found: &cache::CachedAlias, viewer: &Viewer) -> Option<Response<'static>> {
+    let access = match access::check(&found.domain, &found.restrictions, viewer).await {
+        Ok(a) => a,
         Err(e) => {
             error!("{}", e);
-            resp.set_status(Status::InternalServerError);
-            let body = json!({
-                "message": "Internal server error"
-            }).to_string();
-            resp.set_sized_body(body.len(), Cursor::new(body));
+            Access::Forbidden
         }
-        _ => {
-            cache::evict_alias(a.to_string()).await;
-            let body = json!({
-                "message": "Successfully deleted alias",
-                "alias": a.as_str()
-            }).to_string();
-            resp.set_sized_body(body.len(), Cursor::new(body));
+    };
+
+    let mut resp = Response::new();
+    let body = match (access, format) {
+        (Access::Allowed, _) => return None,
+        (Access::LoginRequired, ResponseFormat::Html) => {
+            let login = format!("ui#/login?next={}", pages::encode(&format!("./{}", alias)));
+            resp.set_status(Status::SeeOther);
+            resp.set_header(ContentType::HTML);
+            resp.set_raw_header("Location", login.clone());
+            pages::redirect(&login)
+        }
+        (Access::LoginRequired, ResponseFormat::Json) => {
+            resp.set_status(Status::Unauthorized);
+            resp.set_header(ContentType::JSON);
+            json!({
+                "message": "Log in to use this alias",
+                "alias": alias
+            }).to_string()
+        }
+        (Access::Forbidden, ResponseFormat::Html) => {
+            resp.set_status(Status::Forbidden);
+            resp.set_header(ContentType::HTML);
+            pages::error("You don't have access to this alias.")
+        }
+        (Access::Forbidden, ResponseFormat::Json) => {
+            resp.set_status(Status::Forbidden);
+            resp.set_header(ContentType::JSON);
+            json!({
+                "message": "You don't have access to this alias",
+                "alias": alias
+            }).to_string()
         }
     };
+    resp.set_sized_body(body.len(), Cursor::new(body));
+    Some(resp)
+}
 
-    resp
+/// Reads the browser's sticky bucket, handing out a new one if it doesn't have one yet.
+fn sticky_bucket(cookies: &CookieJar<'_>, client: &ClientInfo) -> u64 {
+    if let Some(b) = cookies.get(rotate::STICKY_COOKIE).and_then(|c| c.value().parse().ok()) {
+        return b;
+    }
+
+    let b = rotate::new_bucket();
+    let mut cookie = client.cookie(rotate::STICKY_COOKIE, b.to_string());
+    cookie.make_permanent();
+    cookies.add(cookie);
+    b
 }
 
-#[get("/<alias>")]
-async fn get_alias(alias: String) -> Response<'static> {
-    let res_dest = cache::get_alias(alias.clone()).await;
+async fn preview_alias(domain: String, name: String, format: ResponseFormat, viewer: Viewer) -> Response<'static> {
+    // Previews show where the alias goes, so they're restricted the same way following it is.
+    let mut restricted = false;
+    if let Ok(d) = cache::get_alias(domain.clone(), name.clone()).await {
+        if let Some(resp) = deny(&name, format, &d, &viewer).await {
+            return resp;
+        }
+        restricted = !d.restrictions.is_empty();
+    }
+
+    let res = preview::preview(domain, name.clone()).await;
     let mut resp = Response::new();
-    resp.set_header(ContentType::JSON);
+    if restricted {
+        resp.set_raw_header("Cache-Control", "private, no-store");
+    }
 
-    match res_dest {
-        Err(e) => {
-            let
-                body = match e {
-                AliasSearchFailure::NoSuchAlias => {
-                    resp.set_status(Status::NotFound);
+    let body = match (res, format) {
+        (Ok(p), ResponseFormat::Html) => {
+            resp.set_header(ContentType::HTML);
+            pages::preview(&p)
+        }
+        (Ok(p), ResponseFormat::Json) => {
+            resp.set_header(ContentType::JSON);
+            json!(p).to_string()
+        }
+        (Err(AliasSearchFailure::NoSuchAlias), ResponseFormat::Html) => {
+            resp.set_status(Status::NotFound);
+            resp.set_header(ContentType::HTML);
+            pages::not_found(&name, &[])
+        }
+        (Err(AliasSearchFailure::NoSuchAlias), ResponseFormat::Json) => {
+            resp.set_status(Status::NotFound);
+            resp.set_header(ContentType::JSON);
+            json!({
+                "message": "No such alias",
+                "alias": name
+            }).to_string()
+        }
+        (Err(e @ AliasSearchFailure::Cycle(_)), format) | (Err(e @ AliasSearchFailure::TooDeep(_)), format) => {
+            resp.set_status(Status::new(508, "Loop Detected"));
+            match format {
+                ResponseFormat::Html => {
+                    resp.set_header(ContentType::HTML);
+                    pages::error(&e.to_string())
+                }
+                ResponseFormat::Json => {
+                    resp.set_header(ContentType::JSON);
                     json!({
-                    "message": "No such alias",
-                    "alias": alias
-                })
+                        "message": e.to_string(),
+                        "alias": name
+                    }).to_string()
                 }
-                AliasSearchFailure::Sql(e) => {
-                    error!("{}", e);
-                    resp.set_status(Status::InternalServerError);
+            }
+        }
+        (Err(AliasSearchFailure::Sql(e)), format) => {
+            error!("{}", e);
+            resp.set_status(Status::InternalServerError);
+            match format {
+                ResponseFormat::Html => {
+                    resp.set_header(ContentType::HTML);
+                    pages::error("An internal error occurred.")
+                }
+                ResponseFormat::Json => {
+                    resp.set_header(ContentType::JSON);
                     json!({
-                    "message": "An internal error occurred."
-                })
+                        "message": "An internal error occurred."
+                    }).to_string()
                 }
-            }.to_string();
-
-            resp.set_sized_body(body.len(), Cursor::new(body));
-        },
-        Ok(d) => {
-            resp.set_status(Status::Found);
-            let body = json!({
-                "message": "redirected",
-                "to": &d
-            }).to_string();
-            resp.set_raw_header("Location", d.clone());
-            resp.set_sized_body(body.len(), Cursor::new(body));
+            }
         }
-    }
+    };
+    resp.set_sized_body(body.len(), Cursor::new(body));
 
     resp
 }
 
-
 embed_migrations!();
 
 #[tokio::main]
@@ -245,6 +954,12 @@ async fn main() -> anyhow::Result<()> {
             .takes_value(true)
             .default_value(".env")
             .about("Allows for alternate locations for a dotenv file"))
+        .arg(Arg::new("config")
+            .short('f')
+            .long("config")
+            .takes_value(true)
+            .default_value("aliasd.toml")
+            .about("The TOML config file. Environment variables override anything set in it."))
         .arg(Arg::new("verbose")
             .short('v')
             .multiple_occurrences(true)
@@ -253,6 +968,41 @@ async fn main() -> anyhow::Result<()> {
         .subcommand(App::new("run")
             .about("Starts the server.")
         )
+        .subcommand(App::new("backup")
+            .about("Writes a consistent copy of the database. Safe to use while the server is running.")
+            .arg(Arg::new("path")
+                .required(true)
+                .takes_value(true)
+                .index(1))
+        )
+        .subcommand(App::new("restore")
+            .about("Replaces the database with a backup. Stop the server first.")
+            .arg(Arg::new("path")
+                .required(true)
+                .takes_value(true)
+                .index(1))
+        )
+        .subcommand(App::new("check")
+            .about("Runs the same checks as /readyz without starting the server, plus a check that /openapi.json describes every route, and fails if any don't pass.")
+        )
+        .subcommand(App::new("config")
+            .about("Commands related to the configuration.")
+            .subcommand(
+                App::new("check")
+                    .about("Reads and validates the configuration, pointing at the setting that's wrong if it isn't.")
+            )
+            .subcommand(
+                App::new("show")
+                    .about("Prints the config file, with secrets redacted.")
+                    .arg(Arg::new("effective")
+                        .long("effective")
+                        .about("Prints every setting after defaults and environment overrides are applied instead."))
+            )
+            .setting(AppSettings::SubcommandRequired)
+        )
+        .subcommand(App::new("check-links")
+            .about("Checks every alias destination once and records the results, like the background checker.")
+        )
         .subcommand(App::new("user")
             .about("Commands related to users.")
             .subcommand(
@@ -269,6 +1019,14 @@ async fn main() -> anyhow::Result<()> {
                 App::new("list")
                     .about("Prints a list of registered users.")
             )
+            .subcommand(
+                App::new("admin")
+                    .about("Lets a user manage webhooks through the admin API.")
+                    .arg(uname.clone())
+                    .arg(Arg::new("revoke")
+                        .long("revoke")
+                        .about("Takes admin rights away instead."))
+            )
             .setting(AppSettings::SubcommandRequired)
         )
         .setting(AppSettings::SubcommandRequired)
@@ -279,33 +1037,107 @@ async fn main() -> anyhow::Result<()> {
 
     dotenv::from_filename(env_file).ok();
 
-    let verbose = matches.occurrences_of("verbose");
-    let log_level = log_level(verbose);
-    let rocket_level = translate_level(log_level);
+    let config_file = PathBuf::from(matches.value_of_os("config").unwrap());
+    if let ("config", m) = matches.subcommand().unwrap() {
+        return config_command(&config_file, m);
+    }
 
-    let sub = tracing_subscriber::FmtSubscriber::builder()
-        .with_max_level(log_level)
-        .finish();
+    let cfg = AliasConfig::load(Some(&config_file))?;
+    config::init(cfg.clone());
 
-    tracing::subscriber::set_global_default(sub).unwrap();
+    let verbose = matches.occurrences_of("verbose");
+    let logs = logging::init(&cfg.logging, log_level(verbose))?;
 
-    let db_path = db::db_path();
-    let conn = db::establish_connection(db_path);
-    db::init_service(conn);
+    // `restore` replaces the database file, so it mustn't create, migrate or hold open the one it replaces.
+    if matches.subcommand_name() != Some("restore") {
+        let db_path = db::db_path();
+        let conn = db::establish_connection(db_path);
+        db::init_service(conn);
 
-    db::conn().with_conn(|c| {
-        embedded_migrations::run(c)
-    }).await?;
+        // `check` reports unapplied migrations rather than quietly applying them.
+        if matches.subcommand_name() != Some("check") {
+            db::conn().with_conn(|c| {
+                embedded_migrations::run(c)
+            }).await?;
+        }
+    }
 
     match matches.subcommand().unwrap() {
         ("run", _) => {
-            let mut cfg = Config::from(Config::figment());
-            cfg.log_level = rocket_level;
-            rocket::custom(cfg)
+            let signals = lifecycle::Signals::new()?;
+            let _in_use = snapshots::hold_database()?;
+
+            if let Some(schedule) = snapshots::SnapshotSchedule::from_config(&cfg.backup) {
+                tokio::spawn(schedule.run());
+            }
+
+            let checker = health::HealthChecker::from_config(&cfg.health);
+            if let Some(interval) = checker.interval {
+                tokio::spawn(checker.run(interval));
+            }
+
+            tokio::spawn(webhooks::run(cfg.webhooks.retention_days));
+
+            if let Some(server) = metrics::MetricsServer::from_config(&cfg.metrics) {
+                tokio::spawn(server.run());
+            }
+
+            let mut rocket_cfg = Config::from(Config::figment());
+            if cfg.tls.enabled() {
+                let backend = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), cfg.tls.backend_port);
+                let listener = tls::TlsListener::new(cfg.tls.clone(), backend, cfg.server.trusted_proxies.clone())?;
+                let public = SocketAddr::new(cfg.server.address, cfg.server.port);
+                tokio::spawn(async move {
+                    if let Err(e) = listener.run(public).await {
+                        error!("HTTPS listener stopped: {}", e);
+                    }
+                });
+                rocket_cfg.address = backend.ip();
+                rocket_cfg.port = backend.port();
+            } else {
+                rocket_cfg.address = cfg.server.address;
+                rocket_cfg.port = cfg.server.port;
+            }
+            // Rocket's records already reach our subscriber through the `log` bridge, so it mustn't install its own logger.
+            rocket_cfg.log_level = rocket::logger::LogLevel::Off;
+            // Shutdown is started from `lifecycle`, which also handles SIGTERM and SIGHUP.
+            rocket_cfg.ctrlc = false;
+            let rocket = rocket::custom(rocket_cfg)
                 .attach(SpaceHelmet::default())
-                .mount("/", routes![get_alias, delete_alias, new_or_update_alias, logout, login])
-                .launch()
-                .await?;
+                .attach(logging::RequestTracing)
+                .attach(metrics::RequestMetrics)
+                .mount("/", all_routes());
+
+            let handle = rocket.shutdown();
+            tokio::spawn(signals.watch(config_file.clone(), logs.reloader(), move || handle.shutdown()));
+
+            rocket.launch().await?;
+
+            // Requests are done, but some of their writes may still be queued for the database thread.
+            db::conn().flush().await;
+            info!("Shut down cleanly");
+        }
+        ("backup", m) => {
+            let path = m.value_of("path").unwrap();
+            backup::backup(path).await?;
+            println!("Wrote backup to {}", path);
+        }
+        ("restore", m) => {
+            snapshots::restore(m.value_of("path").unwrap().into()).await?;
+        }
+        ("check", _) => {
+            let mut checks = probes::run_checks().await;
+            checks.push(probes::Check::new("openapi", openapi::check(&all_routes())));
+            for c in &checks {
+                println!("{:<12} {:<4} {}", c.name, if c.ok { "ok" } else { "FAIL" }, c.detail);
+            }
+            if checks.iter().any(|c| !c.ok) {
+                std::process::exit(1);
+            }
+        }
+        ("check-links", _) => {
+            let summary = health::HealthChecker::from_config(&cfg.health).check_all().await?;
+            println!("Checked {} destinations, {} dead", summary.checked, summary.dead);
         }
         ("user", m) => {
             match m.subcommand().unwrap() {
@@ -319,6 +1151,11 @@ async fn main() -> anyhow::Result<()> {
                         "del" => {
                             users::del_user(user).await?;
                         }
+                        "admin" => {
+                            let grant = !m.is_present("revoke");
+                            admin::set_admin(user.to_string(), grant).await?;
+                            println!("{} {} an admin.", user, if grant { "is now" } else { "is no longer" });
+                        }
                         _ => unreachable!()
                     }
                 }
@@ -331,6 +1168,69 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Names taken by [`all_routes`]. An alias by one of these could be saved but never followed,
+/// since the route would always win.
+const RESERVED_NAMES: &[&str] = &["ui", "account", "search", "login", "alias", "healthz", "readyz", "openapi.json", "admin"];
+
+/// Rejects names that would be shadowed by a route or by [`get_alias`]'s `+` preview suffix.
+fn check_name(name: &str) -> Result<(), String> {
+    if RESERVED_NAMES.contains(&name) {
+        Err(format!("{} is one of aliasd's own pages.", name))
+    } else if let Some(previewed) = name.strip_suffix('+') {
+        Err(format!("{} would show the preview of {:?} instead.", name, previewed))
+    } else {
+        Ok(())
+    }
+}
+
+/// Every route Rocket serves. `aliasd check` makes sure `/openapi.json` describes each of them.
+fn all_routes() -> Vec<rocket::Route> {
+    let mut all = routes![get_alias, delete_alias, list_aliases, search_aliases, new_or_update_alias, logout, login];
+    all.extend(routes![account::account, account::change_password]);
+    all.extend(routes![ui::root, ui::index, ui::asset]);
+    all.extend(routes![probes::healthz, probes::readyz, openapi::document]);
+    all.extend(routes![admin::list_webhooks, admin::add_webhook, admin::delete_webhook, admin::list_deliveries]);
+    all.extend(routes![admin::list_domains, admin::put_domain, admin::delete_domain]);
+    all
+}
+
+fn config_command(file: &Path, m: &clap::ArgMatches) -> anyhow::Result<()> {
+    match m.subcommand().unwrap() {
+        ("check", _) => {
+            if let Err(e) = AliasConfig::load(Some(file)) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            println!("Configuration OK");
+        }
+        ("show", m) if m.is_present("effective") => {
+            let cfg = AliasConfig::load(Some(file))?;
+            print!("{}", toml::to_string(&cfg.redacted())?);
+        }
+        ("show", _) => {
+            let text = match std::fs::read_to_string(file) {
+                Ok(t) => t,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    eprintln!("{} doesn't exist, so everything comes from defaults and the environment.", file.display());
+                    return Ok(());
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let mut value: toml::Value = toml::from_str(&text)
+                .map_err(|e| anyhow::anyhow!("{}: {}", file.display(), e))?;
+            let secret = value.get_mut("auth")
+                .and_then(|a| a.as_table_mut())
+                .and_then(|a| a.get_mut("secret_key"));
+            if let Some(s) = secret {
+                *s = toml::Value::String(config::REDACTED.to_string());
+            }
+            print!("{}", toml::to_string(&value)?);
+        }
+        _ => unreachable!()
+    }
+    Ok(())
+}
+
 fn log_level(i: u64) -> tracing::Level {
     match i {
         0 => tracing::Level::INFO,
@@ -339,10 +1239,23 @@ fn log_level(i: u64) -> tracing::Level {
     }
 }
 
-fn translate_level(tl: tracing::Level) -> rocket::logger::LogLevel {
-    match tl {
-        Level::DEBUG | Level::TRACE => rocket::logger::LogLevel::Debug,
-        Level::INFO | Level::WARN => rocket::logger::LogLevel::Normal,
-        Level::ERROR => rocket::logger::LogLevel::Critical
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn openapi_describes_every_route() {
+        assert_eq!(openapi::check(&all_routes()).map(|_| ()), Ok(()));
+    }
+
+    #[test]
+    fn rejects_names_that_cant_be_followed() {
+        assert!(check_name("admin").is_err());
+        assert!(check_name("openapi.json").is_err());
+        assert!(check_name("docs+").is_err());
+        assert!(check_name("+").is_err());
+        assert_eq!(check_name("logout"), Ok(()));
+        assert_eq!(check_name("c++x"), Ok(()));
+        assert_eq!(check_name("docs"), Ok(()));
     }
-}
\ No newline at end of file
+}
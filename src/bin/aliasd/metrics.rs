@@ -0,0 +1,82 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::time::Instant;
+
+use hyper::{Body, Method, Request, Response, StatusCode};
+use hyper::header::CONTENT_TYPE;
+use hyper::service::{make_service_fn, service_fn};
+use rocket::Data;
+use rocket::fairing::{Fairing, Info, Kind};
+
+use alias::config::MetricsConfig;
+use alias::metrics;
+
+/// Serves `/metrics` on its own address, so it can stay off the public listener.
+pub struct MetricsServer {
+    pub addr: SocketAddr,
+}
+
+impl MetricsServer {
+    /// Metrics aren't served unless `metrics.address` is set.
+    pub fn from_config(cfg: &MetricsConfig) -> Option<MetricsServer> {
+        cfg.address.map(|addr| MetricsServer { addr })
+    }
+
+    pub async fn run(self) {
+        metrics::init();
+        let make_svc = make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(serve)) });
+
+        info!("Serving metrics on http://{}/metrics", self.addr);
+        if let Err(e) = hyper::Server::bind(&self.addr).serve(make_svc).await {
+            error!("Metrics server stopped: {}", e);
+        }
+    }
+}
+
+async fn serve(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let resp = match (req.method(), req.uri().path()) {
+        (&Method::GET, "/metrics") => Response::builder()
+            .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+            .body(Body::from(metrics::render())),
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty()),
+    };
+    Ok(resp.unwrap())
+}
+
+/// Times every request by the handler it was routed to, and counts the statuses of alias lookups.
+pub struct RequestMetrics;
+
+/// When the request came in, kept in the request-local cache.
+struct Started(Instant);
+
+#[rocket::async_trait]
+impl Fairing for RequestMetrics {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request metrics",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut rocket::Request<'_>, _: &Data) {
+        request.local_cache(|| Started(Instant::now()));
+    }
+
+    async fn on_response<'r>(&self, request: &'r rocket::Request<'_>, response: &mut rocket::Response<'r>) {
+        let started = request.local_cache(|| Started(Instant::now()));
+        // Requests no route matched are lumped together, so scanners can't blow up the label count.
+        let route = request.route().and_then(|r| r.name).unwrap_or("unmatched");
+
+        metrics::HTTP_REQUEST_SECONDS
+            .with_label_values(&[route])
+            .observe(started.0.elapsed().as_secs_f64());
+
+        if route == "get_alias" {
+            metrics::REDIRECTS
+                .with_label_values(&[&response.status().code.to_string()])
+                .inc();
+        }
+    }
+}
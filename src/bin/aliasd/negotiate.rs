@@ -0,0 +1,25 @@
+use rocket::Request;
+use rocket::http::MediaType;
+use rocket::request::{FromRequest, Outcome};
+
+/// Which kind of body the client would rather get back.
+///
+/// Browsers prefer `text/html`; anything else, including clients that send no `Accept` header, gets JSON.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseFormat {
+    Html,
+    Json,
+}
+
+#[rocket::async_trait]
+impl<'a, 'r> FromRequest<'a, 'r> for ResponseFormat {
+    type Error = ();
+
+    async fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        let html = request.accept()
+            .map(|a| a.preferred().media_type() == &MediaType::HTML)
+            .unwrap_or(false);
+
+        Outcome::Success(if html { ResponseFormat::Html } else { ResponseFormat::Json })
+    }
+}
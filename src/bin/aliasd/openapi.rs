@@ -0,0 +1,72 @@
+use std::collections::BTreeSet;
+
+use once_cell::sync::Lazy;
+use rocket::Route;
+use rocket::http::ContentType;
+use rocket::response::content::Content;
+
+use alias::api;
+
+static DOCUMENT: Lazy<String> = Lazy::new(|| api::openapi().to_string());
+
+#[get("/openapi.json")]
+pub fn document() -> Content<&'static str> {
+    Content(ContentType::JSON, DOCUMENT.as_str())
+}
+
+/// Compares the document with the routes Rocket serves, so neither gains a route the other is missing.
+pub fn check(mounted: &[Route]) -> Result<String, String> {
+    let served = mounted.iter()
+        .map(|r| (r.method.to_string(), template(&r.uri.path().to_string())))
+        .collect::<BTreeSet<_>>();
+    let documented = api::routes().into_iter()
+        .map(|(m, p)| (m.as_str().to_string(), p.to_string()))
+        .collect::<BTreeSet<_>>();
+
+    let describe = |routes: Vec<&(String, String)>| routes.iter()
+        .map(|(m, p)| format!("{} {}", m, p))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let undocumented = served.difference(&documented).collect::<Vec<_>>();
+    let missing = documented.difference(&served).collect::<Vec<_>>();
+    match (undocumented.is_empty(), missing.is_empty()) {
+        (true, true) => Ok(format!("Describes all {} routes", served.len())),
+        (false, true) => Err(format!("Not in /openapi.json: {}", describe(undocumented))),
+        (true, false) => Err(format!("Described but not served: {}", describe(missing))),
+        (false, false) => Err(format!("Not in /openapi.json: {}; described but not served: {}",
+                                      describe(undocumented), describe(missing))),
+    }
+}
+
+/// Rewrites a Rocket path like `/admin/webhooks/<hook>` or `/ui/<path..>` in OpenAPI's form.
+fn template(path: &str) -> String {
+    path.split('/')
+        .map(|s| match s.strip_prefix('<').and_then(|s| s.strip_suffix('>')) {
+            Some(name) => format!("{{{}}}", name.trim_end_matches("..")),
+            None => s.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn template_rewrites_parameters() {
+        assert_eq!(template("/admin/webhooks/<hook>"), "/admin/webhooks/{hook}");
+        assert_eq!(template("/admin/webhooks/<hook>/deliveries"), "/admin/webhooks/{hook}/deliveries");
+    }
+
+    #[test]
+    fn template_drops_the_dots_of_segments_parameters() {
+        assert_eq!(template("/ui/<path..>"), "/ui/{path}");
+    }
+
+    #[test]
+    fn template_keeps_plain_paths() {
+        assert_eq!(template("/"), "/");
+        assert_eq!(template("/openapi.json"), "/openapi.json");
+    }
+}
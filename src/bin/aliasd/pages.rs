@@ -0,0 +1,150 @@
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+use alias::model::{self, Condition, Platform};
+
+use crate::preview::Preview;
+
+pub fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Everything but the characters RFC 3986 leaves unreserved. Unlike form encoding this turns a
+/// space into `%20`, not `+`, which would read as the preview suffix.
+const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+
+/// Percent-encodes a value for use as a path segment in a link.
+pub fn encode(s: &str) -> String {
+    utf8_percent_encode(s, SEGMENT).to_string()
+}
+
+/// A link to a destination, or just its text if it isn't http or https, like one saved before those
+/// were the only kinds accepted.
+fn link(url: &str) -> String {
+    if model::parse_destination(url).is_ok() {
+        format!("<a href=\"{0}\">{0}</a>", escape(url))
+    } else {
+        escape(url)
+    }
+}
+
+fn page(title: &str, body: &str) -> String {
+    format!(r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="utf-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+    <title>{title}</title>
+    <link rel="stylesheet" href="ui/app.css">
+</head>
+<body>
+<header><a class="brand" href="ui">alias</a></header>
+<main><div class="card">
+{body}
+</div></main>
+</body>
+</html>
+"#, title = escape(title), body = body)
+}
+
+pub fn not_found(alias: &str, suggestions: &[String]) -> String {
+    let mut body = format!("<h2>There's no alias named <code>{}</code></h2>\n", escape(alias));
+
+    if !suggestions.is_empty() {
+        body.push_str("<p>Did you mean one of these?</p>\n<ul>\n");
+        for s in suggestions {
+            body.push_str(&format!("<li><a href=\"{}\">{}</a></li>\n", encode(s), escape(s)));
+        }
+        body.push_str("</ul>\n");
+    }
+
+    body.push_str(&format!(
+        "<p><a href=\"ui#/new?alias={}\"><button>Create {}</button></a></p>\n",
+        encode(alias),
+        escape(alias)
+    ));
+
+    page(&format!("No alias {}", alias), &body)
+}
+
+pub fn error(message: &str) -> String {
+    page("Error", &format!("<h2>{}</h2>\n", escape(message)))
+}
+
+pub fn redirect(destination: &str) -> String {
+    page("Redirecting", &format!(
+        "<p>Redirecting to {}</p>\n",
+        link(destination)
+    ))
+}
+
+fn destinations(p: &Preview) -> String {
+    if p.destinations.is_empty() {
+        return link(&p.destination);
+    }
+
+    let mut out = format!("<p class=\"muted\">Chosen per request: {}</p>\n<ul>\n", p.strategy);
+    for d in &p.destinations {
+        out.push_str(&format!("<li>{} <span class=\"muted\">weight {}</span></li>\n", link(&d.to), d.weight));
+    }
+    out.push_str("</ul>");
+    out
+}
+
+fn describe(c: &Condition) -> String {
+    match c {
+        Condition::Language { languages } => format!("language is {}", languages.join(" or ")),
+        Condition::Platform { platform: Platform::Mobile } => "on a phone or tablet".to_string(),
+        Condition::Platform { platform: Platform::Desktop } => "on a desktop".to_string(),
+        Condition::Network { cidrs } => format!("from {}", cidrs.join(" or ")),
+        Condition::Schedule { days, from, until } if days.is_empty() => format!("between {} and {} server time", from, until),
+        Condition::Schedule { days, from, until } => format!("{} between {} and {} server time", days.join(", "), from, until),
+    }
+}
+
+fn rules(p: &Preview) -> String {
+    if p.rules.is_empty() {
+        return String::new();
+    }
+
+    let mut out = "<tr><th>Rules</th><td><ol>\n".to_string();
+    for r in &p.rules {
+        out.push_str(&format!("<li>{}: {}</li>\n", escape(&describe(&r.condition)), link(&r.to)));
+    }
+    out.push_str("</ol></td></tr>\n");
+    out
+}
+
+pub fn preview(p: &Preview) -> String {
+    let body = format!(r#"<h2><code>{alias}</code></h2>
+<table>
+{rules}<tr><th>Destination</th><td class="destination">{destinations}</td></tr>
+<tr><th>Description</th><td>{description}</td></tr>
+<tr><th>Tags</th><td>{tags}</td></tr>
+<tr><th>Owner</th><td>{owner}</td></tr>
+<tr><th>Redirect</th><td>{redirect}</td></tr>
+{canonical}</table>
+"#,
+                       alias = escape(&p.alias),
+                       rules = rules(p),
+                       destinations = destinations(p),
+                       description = escape(&p.description),
+                       tags = escape(&p.tags.join(", ")),
+                       owner = escape(&p.owner),
+                       redirect = p.redirect,
+                       canonical = p.canonical.as_ref()
+                           .map(|c| format!("<tr><th>Alias of</th><td><a href=\"{0}+\">{1}</a></td></tr>\n", encode(c), escape(c)))
+                           .unwrap_or_default());
+
+    page(&p.alias, &body)
+}
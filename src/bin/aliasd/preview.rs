@@ -0,0 +1,65 @@
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use serde::Serialize;
+
+use alias::db::conn;
+use alias::model::{split_tags, Rule, Strategy, WeightedDestination};
+
+use crate::cache::{self, AliasSearchFailure};
+use crate::rules;
+
+/// What `/<alias>+` shows instead of redirecting.
+#[derive(Debug, Clone, Serialize)]
+pub struct Preview {
+    pub alias: String,
+    pub destination: String,
+    pub owner: String,
+    pub redirect: u16,
+    pub description: String,
+    pub tags: Vec<String>,
+    /// The alias this one resolves through, if any; `destination` and `redirect` are the resolved ones.
+    pub canonical: Option<String>,
+    pub destinations: Vec<WeightedDestination>,
+    pub strategy: Strategy,
+    pub rules: Vec<Rule>,
+}
+
+pub async fn preview(domain: impl Into<String>, name: impl Into<String>) -> Result<Preview, AliasSearchFailure> {
+    let domain = domain.into();
+    let name = name.into();
+    conn().with_conn(move |c| {
+        use ::alias::schema::{aliases, users};
+        aliases::table.inner_join(users::table)
+            .select((aliases::alias,
+                     aliases::destination,
+                     users::username,
+                     aliases::redirect_status,
+                     aliases::description,
+                     aliases::tags,
+                     aliases::canonical))
+            .filter(aliases::domain.eq(&domain))
+            .filter(aliases::alias.eq(&name))
+            .first::<(String, String, String, i32, String, String, Option<String>)>(c)
+            .map_err(AliasSearchFailure::from)
+            .and_then(|(alias, destination, owner, redirect, description, tags, canonical)| {
+                let mut p = Preview {
+                    alias,
+                    destination,
+                    owner,
+                    redirect: redirect as u16,
+                    description,
+                    tags: split_tags(&tags),
+                    canonical,
+                    destinations: Vec::new(),
+                    strategy: Strategy::default(),
+                    rules: Vec::new(),
+                };
+                let (_, resolved) = cache::resolve(c, &domain, &p.alias)?;
+                p.destination = resolved.destination;
+                p.redirect = resolved.redirect;
+                p.destinations = resolved.targets;
+                p.strategy = resolved.strategy;
+                p.rules = rules::rules_of(c, &domain, &resolved.name)?;
+                Ok(p)
+            })
+    }).await
+}
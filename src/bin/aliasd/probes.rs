@@ -0,0 +1,84 @@
+use std::io::Cursor;
+
+use once_cell::sync::OnceCell;
+use rocket::Response;
+use rocket::http::{ContentType, Status};
+use serde::Serialize;
+
+use alias::db::{self, conn};
+
+use crate::snapshots;
+
+#[derive(Debug, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub ok: bool,
+    /// Only printed by `aliasd check`, since it can name paths and settings.
+    #[serde(skip)]
+    pub detail: String,
+}
+
+impl Check {
+    pub fn new(name: &'static str, res: Result<String, String>) -> Check {
+        match res {
+            Ok(detail) => Check { name, ok: true, detail },
+            Err(detail) => Check { name, ok: false, detail },
+        }
+    }
+}
+
+/// The process is up and Rocket is answering. Says nothing about the database.
+#[get("/healthz")]
+pub fn healthz() -> &'static str {
+    "ok"
+}
+
+/// Whether this instance can actually serve aliases; 503 with the failing checks otherwise. Only
+/// names which checks failed; `aliasd check` says why.
+#[get("/readyz")]
+pub async fn readyz() -> Response<'static> {
+    let checks = run_checks().await;
+    let ready = checks.iter().all(|c| c.ok);
+
+    let mut resp = Response::new();
+    resp.set_status(if ready { Status::Ok } else { Status::ServiceUnavailable });
+    resp.set_header(ContentType::JSON);
+    let body = json!({
+        "ready": ready,
+        "checks": checks
+    }).to_string();
+    resp.set_sized_body(body.len(), Cursor::new(body));
+    resp
+}
+
+/// The checks behind `/readyz` and `aliasd check`.
+pub async fn run_checks() -> Vec<Check> {
+    let applied = conn().with_conn(|c| db::applied_migrations(c)).await;
+
+    let database = Check::new("database", applied.as_ref()
+        .map(|_| format!("Reachable at {}", db::db_path()))
+        .map_err(|e| e.to_string()));
+
+    let migrations = Check::new("migrations", match (&applied, embedded_versions()) {
+        (Ok(applied), Ok(known)) => match known.iter().find(|v| !applied.contains(v)) {
+            Some(v) => Err(format!("Migration {} hasn't been applied", v)),
+            None => Ok(format!("All {} migrations applied", known.len())),
+        },
+        (Err(_), _) => Err("Couldn't read the database".to_string()),
+        (_, Err(e)) => Err(e.clone()),
+    });
+
+    let secret = Check::new("secret", match &alias::config::get().auth.secret_key {
+        Some(_) => Ok("auth.secret_key is set".to_string()),
+        None => Err("auth.secret_key isn't set".to_string()),
+    });
+
+    vec![database, migrations, secret]
+}
+
+/// Only computed once, since it means running every migration against an in-memory database.
+fn embedded_versions() -> Result<&'static Vec<String>, &'static String> {
+    static VERSIONS: OnceCell<Result<Vec<String>, String>> = OnceCell::new();
+    VERSIONS.get_or_init(|| snapshots::embedded_versions().map_err(|e| e.to_string()))
+        .as_ref()
+}
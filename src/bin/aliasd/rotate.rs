@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+use rand::Rng;
+
+use alias::model::{Strategy, WeightedDestination};
+
+use crate::cache::CachedAlias;
+
+/// Holds a random number per browser, which `Sticky` aliases hash to pick a destination.
+pub const STICKY_COOKIE: &str = "alias_bucket";
+
+static ROUND_ROBIN: Lazy<Mutex<HashMap<(String, String), u64>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Picks where this request should go. `bucket` is only used by `Sticky` aliases.
+pub fn choose<'a>(a: &'a CachedAlias, bucket: Option<u64>) -> &'a str {
+    let total: u64 = a.targets.iter().map(|t| t.weight as u64).sum();
+    if total == 0 {
+        return &a.destination;
+    }
+
+    let point = match (a.strategy, bucket) {
+        (Strategy::RoundRobin, _) => {
+            let mut counters = ROUND_ROBIN.lock().unwrap();
+            let n = counters.entry((a.domain.clone(), a.name.clone())).or_insert(0);
+            let point = *n % total;
+            *n = n.wrapping_add(1);
+            point
+        }
+        (Strategy::Sticky, Some(bucket)) => {
+            sticky_hash(bucket, &a.domain, &a.name) % total
+        }
+        (Strategy::Weighted, _) | (Strategy::Sticky, None) => rand::thread_rng().gen_range(0, total),
+    };
+
+    pick(&a.targets, point)
+}
+
+/// Drops an alias's round-robin counter, so deleted aliases don't keep one forever.
+pub fn forget(domain: &str, name: &str) {
+    ROUND_ROBIN.lock().unwrap().remove(&(domain.to_string(), name.to_string()));
+}
+
+/// FNV-1a over the bucket and the alias, which unlike `DefaultHasher` is the same in every build,
+/// so a browser keeps its destination across restarts and upgrades.
+fn sticky_hash(bucket: u64, domain: &str, name: &str) -> u64 {
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    // 0xff never appears in UTF-8, so it keeps the domain and name apart.
+    for b in bucket.to_le_bytes().iter().chain(domain.as_bytes()).chain(&[0xff]).chain(name.as_bytes()) {
+        h ^= *b as u64;
+        h = h.wrapping_mul(0x0100_0000_01b3);
+    }
+    h
+}
+
+/// Finds the destination whose share of the total weight covers `point`.
+fn pick(targets: &[WeightedDestination], mut point: u64) -> &str {
+    for t in targets {
+        if point < t.weight as u64 {
+            return &t.to;
+        }
+        point -= t.weight as u64;
+    }
+    &targets[targets.len() - 1].to
+}
+
+pub fn new_bucket() -> u64 {
+    rand::thread_rng().gen()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn targets(weights: &[u32]) -> Vec<WeightedDestination> {
+        weights.iter()
+            .enumerate()
+            .map(|(i, w)| WeightedDestination { to: format!("https://{}.example/", i), weight: *w })
+            .collect()
+    }
+
+    fn alias(name: &str, strategy: Strategy, weights: &[u32]) -> CachedAlias {
+        CachedAlias {
+            domain: String::new(),
+            name: name.to_string(),
+            destination: "https://fallback.example/".to_string(),
+            redirect: 302,
+            strategy,
+            targets: targets(weights),
+            rules: Vec::new(),
+            restrictions: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn picks_by_weight() {
+        let t = targets(&[1, 2, 0, 1]);
+        let picked: Vec<&str> = (0..4).map(|p| pick(&t, p)).collect();
+        assert_eq!(picked, [&t[0].to, &t[1].to, &t[1].to, &t[3].to]);
+        assert_eq!(pick(&t, 100), t[3].to);
+    }
+
+    #[test]
+    fn falls_back_without_weight() {
+        let a = alias("none", Strategy::Weighted, &[0, 0]);
+        assert_eq!(choose(&a, None), a.destination);
+    }
+
+    #[test]
+    fn round_robin_takes_turns_until_forgotten() {
+        let a = alias("round-robin-test", Strategy::RoundRobin, &[1, 2]);
+        let picked: Vec<&str> = (0..6).map(|_| choose(&a, None)).collect();
+        assert_eq!(picked, [&a.targets[0].to, &a.targets[1].to, &a.targets[1].to,
+                            &a.targets[0].to, &a.targets[1].to, &a.targets[1].to]);
+
+        choose(&a, None);
+        forget(&a.domain, &a.name);
+        assert_eq!(choose(&a, None), a.targets[0].to);
+        forget(&a.domain, &a.name);
+        assert!(!ROUND_ROBIN.lock().unwrap().contains_key(&(a.domain.clone(), a.name.clone())));
+    }
+
+    #[test]
+    fn sticky_buckets_keep_their_destination() {
+        let a = alias("sticky", Strategy::Sticky, &[1, 1, 1]);
+        for bucket in 0..32 {
+            let first = choose(&a, Some(bucket));
+            assert!((0..8).all(|_| choose(&a, Some(bucket)) == first));
+        }
+        let seen: std::collections::HashSet<&str> = (0..64).map(|b| choose(&a, Some(b))).collect();
+        assert_eq!(seen.len(), 3);
+    }
+
+    #[test]
+    fn sticky_hash_is_fixed() {
+        // Changing this moves every sticky visitor to a different destination.
+        assert_eq!(sticky_hash(0, "", ""), 0xe603_f73a_248f_3d8e);
+        assert_ne!(sticky_hash(1, "a", "bc"), sticky_hash(1, "ab", "c"));
+    }
+}
@@ -0,0 +1,329 @@
+use std::net::IpAddr;
+
+use chrono::{Datelike, Timelike};
+use diesel::{ExpressionMethods, QueryDsl, QueryResult, RunQueryDsl, SqliteConnection};
+use rocket::Request;
+use rocket::request::{FromRequest, Outcome};
+
+use alias::model::{self, Condition, Platform, Rule};
+use alias::net::Cidr;
+
+use crate::forwarded::ClientInfo;
+
+const DAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+/// A [`Rule`] parsed into something that can be checked without allocating.
+#[derive(Debug, Clone)]
+pub struct CompiledRule {
+    condition: Compiled,
+    pub to: String,
+}
+
+#[derive(Debug, Clone)]
+enum Compiled {
+    Language(Vec<String>),
+    Platform(Platform),
+    Network(Vec<Cidr>),
+    /// Days as a bitmask with Monday as bit 0, and minutes since midnight in the server's timezone.
+    Schedule { days: u8, from: u32, until: u32 },
+}
+
+fn parse_time(s: &str) -> Result<u32, String> {
+    let mut parts = s.splitn(2, ':');
+    let h = parts.next().and_then(|h| h.parse::<u32>().ok()).filter(|h| *h < 24);
+    let m = parts.next().and_then(|m| m.parse::<u32>().ok()).filter(|m| *m < 60);
+    match (h, m) {
+        (Some(h), Some(m)) => Ok(h * 60 + m),
+        _ => Err(format!("Invalid time {:?}, expected HH:MM", s)),
+    }
+}
+
+/// Checks a rule and normalizes its destination the same way plain destinations are.
+pub fn compile(rule: &Rule) -> Result<CompiledRule, String> {
+    let to = model::parse_destination(&rule.to)?.to_string();
+
+    let condition = match &rule.condition {
+        Condition::Language { languages } => {
+            if languages.is_empty() {
+                return Err("A language rule needs at least one language.".to_string());
+            }
+            Compiled::Language(languages.iter().map(|l| l.trim().to_lowercase()).collect())
+        }
+        Condition::Platform { platform } => Compiled::Platform(*platform),
+        Condition::Network { cidrs } => {
+            if cidrs.is_empty() {
+                return Err("A network rule needs at least one CIDR block.".to_string());
+            }
+            Compiled::Network(cidrs.iter().map(|c| Cidr::parse(c)).collect::<Result<_, _>>()?)
+        }
+        Condition::Schedule { days, from, until } => {
+            let mut mask = 0u8;
+            for d in days {
+                let i = DAYS.iter().position(|x| d.eq_ignore_ascii_case(x))
+                    .ok_or_else(|| format!("Invalid day {:?}, expected one of {:?}", d, DAYS))?;
+                mask |= 1 << i;
+            }
+            let (from, until) = (parse_time(from)?, parse_time(until)?);
+            // Would either never match or always match, depending on how it's read.
+            if from == until {
+                return Err("A schedule has to end at a different time than it starts.".to_string());
+            }
+            Compiled::Schedule {
+                days: if mask == 0 { 0x7f } else { mask },
+                from,
+                until,
+            }
+        }
+    };
+
+    Ok(CompiledRule { condition, to })
+}
+
+/// Compiles every rule, returning the rule with its destination normalized, or the first problem.
+pub fn validate(rules: &[Rule]) -> Result<Vec<Rule>, String> {
+    rules.iter()
+        .enumerate()
+        .map(|(i, r)| compile(r)
+            .map(|c| Rule { condition: r.condition.clone(), to: c.to })
+            .map_err(|e| format!("Rule {}: {}", i + 1, e)))
+        .collect()
+}
+
+/// The parts of a request that rules can look at.
+#[derive(Debug, Clone, Default)]
+pub struct RequestInfo {
+    language: Option<String>,
+    mobile: bool,
+    ip: Option<IpAddr>,
+}
+
+#[rocket::async_trait]
+impl<'a, 'r> FromRequest<'a, 'r> for RequestInfo {
+    type Error = ();
+
+    async fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        let headers = request.headers();
+        let ua = headers.get_one("User-Agent").unwrap_or_default();
+
+        Outcome::Success(RequestInfo {
+            language: headers.get_one("Accept-Language").and_then(preferred_language),
+            mobile: ["Mobi", "Android", "iPhone", "iPad"].iter().any(|m| ua.contains(m)),
+            ip: ClientInfo::of(request).ip,
+        })
+    }
+}
+
+/// The highest-weighted tag in an `Accept-Language` header, lowercased.
+fn preferred_language(header: &str) -> Option<String> {
+    header.split(',')
+        .filter_map(|part| {
+            let mut fields = part.split(';');
+            let tag = fields.next()?.trim();
+            let q = fields
+                .find_map(|f| f.trim().strip_prefix("q="))
+                .map(|q| q.parse::<f32>().unwrap_or(0.0))
+                .unwrap_or(1.0);
+            if tag.is_empty() || tag == "*" || q <= 0.0 {
+                None
+            } else {
+                Some((q, tag.to_lowercase()))
+            }
+        })
+        // Ties go to whichever came first.
+        .fold(None, |best: Option<(f32, String)>, (q, tag)| match best {
+            Some((bq, _)) if bq >= q => best,
+            _ => Some((q, tag)),
+        })
+        .map(|(_, tag)| tag)
+}
+
+impl CompiledRule {
+    pub fn matches(&self, req: &RequestInfo) -> bool {
+        match &self.condition {
+            Compiled::Language(langs) => match &req.language {
+                Some(l) => langs.iter().any(|want| {
+                    l == want || (l.starts_with(want.as_str()) && l[want.len()..].starts_with('-'))
+                }),
+                None => false,
+            },
+            Compiled::Platform(Platform::Mobile) => req.mobile,
+            Compiled::Platform(Platform::Desktop) => !req.mobile,
+            Compiled::Network(cidrs) => match req.ip {
+                Some(ip) => cidrs.iter().any(|c| c.contains(ip)),
+                None => false,
+            },
+            Compiled::Schedule { days, from, until } => {
+                let now = chrono::Local::now();
+                let minute = now.hour() * 60 + now.minute();
+                in_schedule(*days, *from, *until, now.weekday().num_days_from_monday(), minute)
+            }
+        }
+    }
+}
+
+/// Whether `minute` past midnight on `today` (Monday is 0) falls in a compiled schedule.
+fn in_schedule(days: u8, from: u32, until: u32, today: u32, minute: u32) -> bool {
+    if from <= until {
+        days & (1 << today) != 0 && from <= minute && minute < until
+    } else {
+        // Wraps past midnight, so the early part belongs to the day it started on.
+        let yesterday = (today + 6) % 7;
+        (days & (1 << today) != 0 && minute >= from)
+            || (days & (1 << yesterday) != 0 && minute < until)
+    }
+}
+
+/// The first matching rule's destination, if any.
+pub fn evaluate<'a>(rules: &'a [CompiledRule], req: &RequestInfo) -> Option<&'a str> {
+    rules.iter().find(|r| r.matches(req)).map(|r| r.to.as_str())
+}
+
+pub fn rules_of(c: &SqliteConnection, dom: &str, name: &str) -> QueryResult<Vec<Rule>> {
+    use ::alias::schema::alias_rules::dsl::*;
+    alias_rules.select((condition, destination))
+        .filter(domain.eq(dom))
+        .filter(alias.eq(name))
+        .order(position)
+        .load::<(String, String)>(c)
+        .map(|rows| rows.into_iter().filter_map(|(cond, to)| decode(&cond, to)).collect())
+}
+
+/// Turns a stored row back into a rule, skipping any that no longer parse.
+pub fn decode(cond: &str, to: String) -> Option<Rule> {
+    match serde_json::from_str(cond) {
+        Ok(condition) => Some(Rule { condition, to }),
+        Err(e) => {
+            error!("Skipping unreadable rule condition {:?}: {}", cond, e);
+            None
+        }
+    }
+}
+
+pub fn replace_rules(c: &SqliteConnection, dom: &str, name: &str, rules: &[Rule]) -> QueryResult<()> {
+    use ::alias::schema::alias_rules::dsl::*;
+    diesel::delete(alias_rules.filter(domain.eq(dom)).filter(alias.eq(name))).execute(c)?;
+    for (i, r) in rules.iter().enumerate() {
+        let cond = serde_json::to_string(&r.condition)
+            .map_err(|e| diesel::result::Error::SerializationError(Box::new(e)))?;
+        diesel::insert_into(alias_rules)
+            .values((domain.eq(dom),
+                     alias.eq(name),
+                     position.eq(i as i32),
+                     condition.eq(cond),
+                     destination.eq(&r.to)))
+            .execute(c)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(condition: Condition) -> Rule {
+        Rule { condition, to: "https://example.com".to_string() }
+    }
+
+    fn schedule(days: &[&str], from: &str, until: &str) -> Rule {
+        rule(Condition::Schedule {
+            days: days.iter().map(|d| d.to_string()).collect(),
+            from: from.to_string(),
+            until: until.to_string(),
+        })
+    }
+
+    #[test]
+    fn parses_times() {
+        assert_eq!(parse_time("00:00"), Ok(0));
+        assert_eq!(parse_time("9:05"), Ok(9 * 60 + 5));
+        assert_eq!(parse_time("23:59"), Ok(23 * 60 + 59));
+        assert!(parse_time("24:00").is_err());
+        assert!(parse_time("12:60").is_err());
+        assert!(parse_time("12").is_err());
+        assert!(parse_time("noon").is_err());
+        assert!(parse_time("-1:00").is_err());
+    }
+
+    #[test]
+    fn schedules_within_a_day() {
+        let weekdays = 0b001_1111;
+        let (from, until) = (9 * 60, 17 * 60);
+        assert!(in_schedule(weekdays, from, until, 0, 9 * 60));
+        assert!(in_schedule(weekdays, from, until, 4, 17 * 60 - 1));
+        assert!(!in_schedule(weekdays, from, until, 0, 17 * 60));
+        assert!(!in_schedule(weekdays, from, until, 0, 8 * 60 + 59));
+        assert!(!in_schedule(weekdays, from, until, 5, 12 * 60));
+    }
+
+    #[test]
+    fn schedules_across_midnight_belong_to_the_day_they_start() {
+        let friday = 1 << 4;
+        let (from, until) = (22 * 60, 6 * 60);
+        assert!(in_schedule(friday, from, until, 4, 23 * 60));
+        // Early Saturday is still Friday night.
+        assert!(in_schedule(friday, from, until, 5, 5 * 60));
+        assert!(!in_schedule(friday, from, until, 5, 23 * 60));
+        // Early Friday is Thursday night, which isn't in it.
+        assert!(!in_schedule(friday, from, until, 4, 5 * 60));
+        assert!(!in_schedule(friday, from, until, 5, 6 * 60));
+    }
+
+    #[test]
+    fn sunday_night_runs_into_monday() {
+        let sunday = 1 << 6;
+        assert!(in_schedule(sunday, 22 * 60, 2 * 60, 0, 60));
+    }
+
+    #[test]
+    fn picks_the_preferred_language() {
+        assert_eq!(preferred_language("de-AT"), Some("de-at".to_string()));
+        assert_eq!(preferred_language("en;q=0.5, de;q=0.9, fr;q=0.7"), Some("de".to_string()));
+        assert_eq!(preferred_language("fr, de"), Some("fr".to_string()));
+        assert_eq!(preferred_language("en;q=0.8, de;q=0.8"), Some("en".to_string()));
+        assert_eq!(preferred_language("*, de;q=0.1"), Some("de".to_string()));
+        assert_eq!(preferred_language("en;q=0, de;q=bogus"), None);
+        assert_eq!(preferred_language(""), None);
+    }
+
+    #[test]
+    fn matches_language_prefixes() {
+        let r = compile(&rule(Condition::Language { languages: vec!["DE".to_string()] })).unwrap();
+        let req = |l: &str| RequestInfo { language: Some(l.to_string()), ..RequestInfo::default() };
+        assert!(r.matches(&req("de")));
+        assert!(r.matches(&req("de-at")));
+        assert!(!r.matches(&req("den")));
+        assert!(!r.matches(&RequestInfo::default()));
+    }
+
+    #[test]
+    fn compiles_schedules() {
+        assert!(compile(&schedule(&["mon", "Fri"], "09:00", "17:00")).is_ok());
+        assert!(compile(&schedule(&[], "22:00", "06:00")).is_ok());
+        assert!(compile(&schedule(&["someday"], "09:00", "17:00")).is_err());
+        assert!(compile(&schedule(&[], "9am", "17:00")).is_err());
+    }
+
+    #[test]
+    fn rejects_schedules_that_end_when_they_start() {
+        assert!(compile(&schedule(&[], "09:00", "09:00")).is_err());
+        assert!(compile(&schedule(&[], "9:00", "09:00")).is_err());
+    }
+
+    #[test]
+    fn rejects_empty_lists_and_bad_destinations() {
+        assert!(compile(&rule(Condition::Language { languages: vec![] })).is_err());
+        assert!(compile(&rule(Condition::Network { cidrs: vec![] })).is_err());
+        assert!(compile(&rule(Condition::Network { cidrs: vec!["10.0.0.0/33".to_string()] })).is_err());
+        let js = Rule { to: "javascript:alert(1)".to_string(), ..rule(Condition::Platform { platform: Platform::Mobile }) };
+        assert!(compile(&js).is_err());
+    }
+
+    #[test]
+    fn validate_normalizes_and_numbers_errors() {
+        let ok = Rule { to: "HTTPS://Example.com".to_string(), ..rule(Condition::Platform { platform: Platform::Desktop }) };
+        assert_eq!(validate(&[ok.clone()]).unwrap()[0].to, "https://example.com/");
+
+        let err = validate(&[ok, schedule(&[], "09:00", "09:00")]).unwrap_err();
+        assert!(err.starts_with("Rule 2: "), "{}", err);
+    }
+}
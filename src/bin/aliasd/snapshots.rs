@@ -0,0 +1,199 @@
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use diesel::{Connection, SqliteConnection};
+use fs2::FileExt;
+
+use alias::backup::{self, backup_file};
+use alias::config::BackupConfig;
+use alias::db;
+
+const SNAPSHOT_PREFIX: &str = "alias-";
+const SNAPSHOT_SUFFIX: &str = ".sqlite";
+
+pub struct SnapshotSchedule {
+    pub dir: PathBuf,
+    pub interval: Duration,
+    pub keep: usize,
+}
+
+impl SnapshotSchedule {
+    /// Snapshots are disabled unless `backup.dir` is set.
+    pub fn from_config(cfg: &BackupConfig) -> Option<SnapshotSchedule> {
+        Some(SnapshotSchedule {
+            dir: cfg.dir.clone()?,
+            interval: Duration::from_secs(cfg.interval_mins * 60),
+            keep: cfg.keep,
+        })
+    }
+
+    pub async fn run(self) {
+        let mut interval = tokio::time::interval(self.interval);
+        // The first tick is immediate; restarts shouldn't each leave a snapshot behind.
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.snapshot().await {
+                error!("Scheduled backup failed: {}", e);
+            }
+        }
+    }
+
+    async fn snapshot(&self) -> anyhow::Result<()> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+
+        let name = format!("{}{}{}",
+                           SNAPSHOT_PREFIX,
+                           chrono::Local::now().format("%Y%m%d-%H%M%S"),
+                           SNAPSHOT_SUFFIX);
+        let dest = self.dir.join(name);
+
+        backup::backup(dest.clone()).await?;
+        info!("Wrote database snapshot to {}", dest.display());
+
+        self.prune()
+    }
+
+    fn prune(&self) -> anyhow::Result<()> {
+        let mut snapshots = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let name = entry?.file_name();
+            let name = name.to_string_lossy();
+            if name.starts_with(SNAPSHOT_PREFIX) && name.ends_with(SNAPSHOT_SUFFIX) {
+                snapshots.push(name.into_owned());
+            }
+        }
+
+        // The timestamp format sorts lexically, so the oldest snapshots come first.
+        snapshots.sort();
+        let excess = snapshots.len().saturating_sub(self.keep);
+        for name in snapshots.into_iter().take(excess) {
+            debug!("Removing old snapshot {}", &name);
+            std::fs::remove_file(self.dir.join(name))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The migration versions compiled into this binary.
+pub fn embedded_versions() -> anyhow::Result<Vec<String>> {
+    let c = SqliteConnection::establish(":memory:")?;
+    crate::embedded_migrations::run(&c)?;
+    Ok(db::applied_migrations(&c)?)
+}
+
+fn validate(path: &Path) -> anyhow::Result<()> {
+    let c = SqliteConnection::establish(path.to_str().unwrap())?;
+
+    if !db::integrity_check(&c)? {
+        anyhow::bail!("The backup failed SQLite's integrity check.");
+    }
+
+    let applied = db::applied_migrations(&c)
+        .map_err(|_| anyhow::anyhow!("The backup doesn't look like an aliasd database."))?;
+    let known = embedded_versions()?;
+
+    if let Some(v) = applied.iter().find(|v| !known.contains(v)) {
+        anyhow::bail!("The backup has migration {}, which this version of aliasd doesn't know about.", v);
+    }
+
+    // Older backups are brought up to the current schema before they go live.
+    crate::embedded_migrations::run(&c)?;
+
+    Ok(())
+}
+
+fn lock_path() -> PathBuf {
+    PathBuf::from(format!("{}.lock", db::db_path()))
+}
+
+/// Marks the database as in use until the file is dropped. `aliasd run` holds this while it serves.
+///
+/// It's a shared lock, so more than one process can hold it; only [`restore`] needs the database to itself.
+pub fn hold_database() -> anyhow::Result<File> {
+    let path = lock_path();
+    let file = OpenOptions::new().create(true).write(true).open(&path)?;
+    file.try_lock_shared()
+        .map_err(|_| anyhow::anyhow!("{} is locked by a restore in progress.", path.display()))?;
+    Ok(file)
+}
+
+/// Replaces the database with a validated copy of the backup at `from`.
+///
+/// Refuses while aliasd is running, or while SQLite has a journal to recover, since either would
+/// end up applied to the wrong file. The previous database is kept alongside it with a
+/// `.pre-restore` extension.
+pub async fn restore(from: PathBuf) -> anyhow::Result<()> {
+    let db_path = PathBuf::from(db::db_path().as_ref());
+    let src = from.to_str()
+        .ok_or_else(|| anyhow::anyhow!("Backup path must be valid UTF-8."))?
+        .to_string();
+
+    let lock = OpenOptions::new().create(true).write(true).open(lock_path())?;
+    if lock.try_lock_exclusive().is_err() {
+        anyhow::bail!("The database is in use. Stop aliasd before restoring.");
+    }
+    for suffix in &["-journal", "-wal"] {
+        let journal = PathBuf::from(format!("{}{}", db_path.display(), suffix));
+        if journal.exists() {
+            anyhow::bail!("{} exists, so the database is in use or wasn't closed cleanly. \
+                           Stop aliasd, or start and stop it once so SQLite can recover, then try again.",
+                          journal.display());
+        }
+    }
+
+    tokio::task::spawn_blocking(move || {
+        // Held until the new database is in place.
+        let _lock = lock;
+        let staging = db_path.with_extension("restore");
+        backup_file(&src, &staging)?;
+
+        if let Err(e) = validate(&staging) {
+            std::fs::remove_file(&staging).ok();
+            return Err(e);
+        }
+
+        if db_path.exists() {
+            std::fs::rename(&db_path, db_path.with_extension("pre-restore"))?;
+        }
+        std::fs::rename(&staging, &db_path)?;
+
+        info!("Restored {} from {}", db_path.display(), src);
+        Ok(())
+    }).await?
+}
+
+#[cfg(test)]
+mod tests {
+    use alias::config::{self, AliasConfig};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn restores_where_there_is_no_database() {
+        let dir = std::env::temp_dir().join(format!("aliasd-restore-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let backup = dir.join("backup.sqlite");
+        let live = dir.join("live").join("alias.db");
+        std::fs::create_dir_all(live.parent().unwrap()).unwrap();
+        {
+            let c = SqliteConnection::establish(backup.to_str().unwrap()).unwrap();
+            crate::embedded_migrations::run(&c).unwrap();
+        }
+
+        let mut cfg = AliasConfig::default();
+        cfg.database.path = live.to_str().unwrap().to_string();
+        config::replace(cfg);
+
+        restore(backup.clone()).await.unwrap();
+
+        let c = SqliteConnection::establish(live.to_str().unwrap()).unwrap();
+        assert_eq!(db::applied_migrations(&c).unwrap(), embedded_versions().unwrap());
+        assert!(!live.with_extension("pre-restore").exists());
+        assert!(!live.with_extension("restore").exists());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
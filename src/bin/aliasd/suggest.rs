@@ -0,0 +1,64 @@
+use diesel::{QueryDsl, QueryResult, RunQueryDsl};
+
+use alias::db::conn;
+
+const MAX_SUGGESTIONS: usize = 5;
+
+/// Finds existing aliases that look like `name`, for when it doesn't exist.
+///
+/// Aliases sharing a prefix with `name` come first, then ones within a small edit distance.
+/// Only public aliases in the same domain are suggested, since the 404 page is shown to anyone.
+pub async fn similar(dom: impl Into<String>, name: impl Into<String>) -> QueryResult<Vec<String>> {
+    let dom = dom.into();
+    let name = name.into().to_lowercase();
+
+    let names: Vec<String> = conn().with_conn(move |c| {
+        use ::alias::schema::aliases::dsl::*;
+        aliases.select(alias)
+            .filter(domain.eq(dom))
+            .filter(visibility.eq(::alias::model::Visibility::Public.as_str()))
+            .load(c)
+    }).await?;
+
+    let max_distance = (name.chars().count() / 3).max(2);
+
+    let mut scored: Vec<(bool, usize, String)> = names.into_iter()
+        .filter_map(|candidate| {
+            let lower = candidate.to_lowercase();
+            let prefix = !name.is_empty() && (lower.starts_with(&name) || name.starts_with(&lower));
+            let distance = levenshtein(&name, &lower);
+
+            if prefix || distance <= max_distance {
+                Some((!prefix, distance, candidate))
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    scored.sort();
+
+    Ok(scored.into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, _, s)| s)
+        .collect())
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut cur = vec![0; b.len() + 1];
+
+    for (i, ca) in a.chars().enumerate() {
+        cur[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            cur[j + 1] = (prev[j] + cost)
+                .min(prev[j + 1] + 1)
+                .min(cur[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+
+    prev[b.len()]
+}
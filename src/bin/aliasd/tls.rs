@@ -0,0 +1,271 @@
+use std::convert::Infallible;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use hyper::{Body, Request, Response, StatusCode};
+use hyper::client::HttpConnector;
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use once_cell::sync::Lazy;
+use rustls::{AllowAnyAnonymousOrAuthenticatedClient, NoClientAuth, ProtocolVersion, RootCertStore, ServerConfig, Session};
+use rustls::internal::pemfile;
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+
+use alias::config::{TlsConfig, TlsVersion};
+use alias::net::Cidr;
+
+use crate::webhooks;
+
+/// How often the certificate files are checked for changes.
+const RELOAD_CHECK: Duration = Duration::from_secs(30);
+
+/// Headers that only apply to one connection, so they aren't passed through.
+const HOP_BY_HOP: &[&str] = &["connection", "keep-alive", "proxy-connection", "te", "trailer", "upgrade"];
+
+/// Set on every request the listener forwards, so Rocket can tell them from ones a local process sent
+/// straight to the backend port.
+pub const LISTENER_HEADER: &str = "x-aliasd-listener";
+
+/// Set on requests from clients with a certificate `tls.client_ca` issued, and dropped from all others.
+pub const CLIENT_CERT_HEADER: &str = "x-client-cert";
+
+/// The value of [`LISTENER_HEADER`] and [`CLIENT_CERT_HEADER`]. Made up at startup, so that neither
+/// clients nor other local processes can claim to be the listener or to have a certificate.
+static MARKER: Lazy<String> = Lazy::new(webhooks::new_secret);
+
+fn marked(request: &rocket::Request<'_>, header: &str) -> bool {
+    request.headers().get_one(header) == Some(MARKER.as_str())
+}
+
+/// Whether this request came through the TLS listener, whose forwarding headers can be believed.
+pub fn from_listener(request: &rocket::Request<'_>) -> bool {
+    marked(request, LISTENER_HEADER)
+}
+
+/// Whether the TLS listener forwarded this request for a client with a verified certificate.
+pub fn has_client_cert(request: &rocket::Request<'_>) -> bool {
+    marked(request, CLIENT_CERT_HEADER)
+}
+
+/// Headers describing the client, which are dropped unless they came from a trusted proxy.
+const FORWARDING: &[&str] = &[
+    "forwarded", "x-forwarded-for", "x-forwarded-proto", "x-forwarded-host", "x-forwarded-prefix", "x-real-ip",
+];
+
+/// Terminates TLS on the public address and hands each request to Rocket over loopback.
+///
+/// The peer is added to both `X-Forwarded-For` and `Forwarded`, one of which Rocket believes depending
+/// on `server.forwarded_header`, so network rules and logging see the real client rather than
+/// 127.0.0.1. Rocket only believes them on requests carrying [`LISTENER_HEADER`].
+pub struct TlsListener {
+    cfg: TlsConfig,
+    current: Arc<RwLock<Arc<ServerConfig>>>,
+    backend: SocketAddr,
+    trusted_proxies: Arc<Vec<Cidr>>,
+}
+
+impl TlsListener {
+    pub fn new(cfg: TlsConfig, backend: SocketAddr, trusted_proxies: Vec<Cidr>) -> anyhow::Result<TlsListener> {
+        let server = load(&cfg)?;
+        Ok(TlsListener {
+            cfg,
+            current: Arc::new(RwLock::new(Arc::new(server))),
+            backend,
+            trusted_proxies: Arc::new(trusted_proxies),
+        })
+    }
+
+    pub async fn run(self, addr: SocketAddr) -> anyhow::Result<()> {
+        tokio::spawn(watch(self.cfg.clone(), self.current.clone()));
+
+        let client = hyper::Client::new();
+        let mut listener = TcpListener::bind(addr).await?;
+        info!("Serving HTTPS on {}", addr);
+
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(s) => s,
+                Err(e) => {
+                    error!("Couldn't accept a connection: {}", e);
+                    continue;
+                }
+            };
+            let acceptor = TlsAcceptor::from(self.current.read().unwrap().clone());
+            let client = client.clone();
+            let backend = self.backend;
+            let trusted_peer = self.trusted_proxies.iter().any(|c| c.contains(peer.ip()));
+
+            tokio::spawn(async move {
+                let stream = match acceptor.accept(stream).await {
+                    Ok(s) => s,
+                    Err(e) => {
+                        debug!("TLS handshake with {} failed: {}", peer, e);
+                        return;
+                    }
+                };
+                // rustls has already checked any certificate against tls.client_ca.
+                let has_cert = stream.get_ref().1.get_peer_certificates().map_or(false, |c| !c.is_empty());
+
+                let svc = service_fn(move |req| forward(client.clone(), backend, peer, trusted_peer, has_cert, req));
+                if let Err(e) = Http::new().serve_connection(stream, svc).await {
+                    debug!("Connection from {} ended: {}", peer, e);
+                }
+            });
+        }
+    }
+}
+
+async fn forward(client: hyper::Client<HttpConnector>,
+                 backend: SocketAddr,
+                 peer: SocketAddr,
+                 trusted_peer: bool,
+                 has_cert: bool,
+                 mut req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let path = req.uri().path_and_query().map(|p| p.as_str()).unwrap_or("/");
+    *req.uri_mut() = match format!("http://{}{}", backend, path).parse() {
+        Ok(u) => u,
+        Err(_) => return Ok(status(StatusCode::BAD_REQUEST, "Bad request path.\n")),
+    };
+
+    let headers = req.headers_mut();
+    strip_hop_by_hop(headers);
+    if !trusted_peer {
+        // Only this listener knows who connected, so whatever the client claimed is dropped.
+        for h in FORWARDING {
+            headers.remove(*h);
+        }
+    }
+    append_forwarding(headers, peer.ip());
+    let marker = HeaderValue::from_str(&MARKER).unwrap();
+    headers.insert(HeaderName::from_static(LISTENER_HEADER), marker.clone());
+    // Rocket's admin guard checks this, rather than the listener matching paths it may not route the same way.
+    headers.remove(CLIENT_CERT_HEADER);
+    if has_cert {
+        headers.insert(HeaderName::from_static(CLIENT_CERT_HEADER), marker);
+    }
+
+    match client.request(req).await {
+        Ok(mut resp) => {
+            strip_hop_by_hop(resp.headers_mut());
+            Ok(resp)
+        }
+        Err(e) => {
+            error!("Couldn't reach Rocket on {}: {}", backend, e);
+            Ok(status(StatusCode::BAD_GATEWAY, "The server isn't ready.\n"))
+        }
+    }
+}
+
+/// Records the peer the way a proxy would, keeping what a trusted proxy in front already added.
+fn append_forwarding(headers: &mut hyper::HeaderMap, peer: IpAddr) {
+    let join = |existing: Option<&HeaderValue>, add: String| match existing.and_then(|v| v.to_str().ok()) {
+        Some(v) => format!("{}, {}", v, add),
+        None => add,
+    };
+
+    let node = match peer {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("\"[{}]\"", ip),
+    };
+    let value = join(headers.get("forwarded"), format!("for={};proto=https", node));
+    headers.insert(HeaderName::from_static("forwarded"), HeaderValue::from_str(&value).unwrap());
+    let value = join(headers.get("x-forwarded-for"), peer.to_string());
+    headers.insert(HeaderName::from_static("x-forwarded-for"), HeaderValue::from_str(&value).unwrap());
+    if !headers.contains_key("x-forwarded-proto") {
+        headers.insert(HeaderName::from_static("x-forwarded-proto"), HeaderValue::from_static("https"));
+    }
+    headers.insert(HeaderName::from_static("x-real-ip"), HeaderValue::from_str(&peer.to_string()).unwrap());
+}
+
+fn strip_hop_by_hop(headers: &mut hyper::HeaderMap) {
+    for h in HOP_BY_HOP {
+        headers.remove(*h);
+    }
+}
+
+fn status(code: StatusCode, body: &'static str) -> Response<Body> {
+    let mut resp = Response::new(Body::from(body));
+    *resp.status_mut() = code;
+    resp
+}
+
+fn load(cfg: &TlsConfig) -> anyhow::Result<ServerConfig> {
+    let certs_path = cfg.certs.as_ref().ok_or_else(|| anyhow::anyhow!("tls.certs isn't set"))?;
+    let key_path = cfg.key.as_ref().ok_or_else(|| anyhow::anyhow!("tls.key isn't set"))?;
+
+    let certs = pemfile::certs(&mut BufReader::new(File::open(certs_path)?))
+        .map_err(|_| anyhow::anyhow!("{} isn't a PEM certificate file", certs_path.display()))?;
+    if certs.is_empty() {
+        anyhow::bail!("{} has no certificates in it", certs_path.display());
+    }
+    let key = private_key(key_path)?;
+
+    let verifier = match &cfg.client_ca {
+        Some(ca) => {
+            let mut roots = RootCertStore::empty();
+            let (added, _) = roots.add_pem_file(&mut BufReader::new(File::open(ca)?))
+                .map_err(|_| anyhow::anyhow!("{} isn't a PEM certificate file", ca.display()))?;
+            if added == 0 {
+                anyhow::bail!("{} has no usable CA certificates in it", ca.display());
+            }
+            // Certificates are optional for the connection, and only demanded for admin routes.
+            AllowAnyAnonymousOrAuthenticatedClient::new(roots)
+        }
+        None => NoClientAuth::new(),
+    };
+
+    let mut server = ServerConfig::new(verifier);
+    server.set_single_cert(certs, key)?;
+    server.versions = match cfg.min_version {
+        TlsVersion::Tls12 => vec![ProtocolVersion::TLSv1_3, ProtocolVersion::TLSv1_2],
+        TlsVersion::Tls13 => vec![ProtocolVersion::TLSv1_3],
+    };
+    server.set_protocols(&[b"http/1.1".to_vec()]);
+    Ok(server)
+}
+
+/// The first PKCS#8 or RSA key in the file.
+fn private_key(path: &Path) -> anyhow::Result<rustls::PrivateKey> {
+    let bad = || anyhow::anyhow!("{} isn't a PEM private key file", path.display());
+
+    let mut keys = pemfile::pkcs8_private_keys(&mut BufReader::new(File::open(path)?)).map_err(|_| bad())?;
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut BufReader::new(File::open(path)?)).map_err(|_| bad())?;
+    }
+    keys.into_iter().next().ok_or_else(|| anyhow::anyhow!("{} has no private key in it", path.display()))
+}
+
+fn modified(cfg: &TlsConfig) -> Vec<Option<SystemTime>> {
+    [&cfg.certs, &cfg.key, &cfg.client_ca].iter()
+        .map(|p| p.as_ref().and_then(|p| std::fs::metadata(p).and_then(|m| m.modified()).ok()))
+        .collect()
+}
+
+/// Swaps in new certificates when the files change. New connections use them; open ones keep the old ones.
+async fn watch(cfg: TlsConfig, current: Arc<RwLock<Arc<ServerConfig>>>) {
+    let mut seen = modified(&cfg);
+    let mut interval = tokio::time::interval(RELOAD_CHECK);
+    loop {
+        interval.tick().await;
+        let now = modified(&cfg);
+        if now == seen {
+            continue;
+        }
+
+        // A renewal may still be writing the files, so anything unreadable is retried on the next check.
+        match load(&cfg) {
+            Ok(server) => {
+                *current.write().unwrap() = Arc::new(server);
+                seen = now;
+                info!("Reloaded TLS certificates");
+            }
+            Err(e) => warn!("Not reloading TLS certificates yet: {}", e),
+        }
+    }
+}
@@ -0,0 +1,29 @@
+use rocket::http::ContentType;
+use rocket::response::Redirect;
+use rocket::response::content::Content;
+
+use crate::forwarded::ClientInfo;
+
+// The UI is compiled into the binary so the package doesn't need to ship an asset directory.
+const INDEX_HTML: &str = include_str!("ui/index.html");
+const APP_JS: &str = include_str!("ui/app.js");
+const APP_CSS: &str = include_str!("ui/app.css");
+
+#[get("/")]
+pub fn root(client: ClientInfo) -> Redirect {
+    Redirect::to(client.url("/ui"))
+}
+
+#[get("/ui")]
+pub fn index() -> Content<&'static str> {
+    Content(ContentType::HTML, INDEX_HTML)
+}
+
+#[get("/ui/<file>")]
+pub fn asset(file: String) -> Option<Content<&'static str>> {
+    match file.as_str() {
+        "app.js" => Some(Content(ContentType::JavaScript, APP_JS)),
+        "app.css" => Some(Content(ContentType::CSS, APP_CSS)),
+        _ => None,
+    }
+}
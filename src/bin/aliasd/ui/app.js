@@ -0,0 +1,367 @@
+'use strict';
+
+// Everything is resolved relative to the page so the UI keeps working when aliasd is mounted under a prefix.
+const base = new URL('.', window.location.href);
+const main = document.getElementById('main');
+const nav = document.getElementById('nav');
+
+const state = {
+    account: null,
+    aliases: [],
+    query: '',
+};
+
+async function api(method, path, body) {
+    const opts = { method, credentials: 'same-origin', headers: { 'Accept': 'application/json' } };
+    if (body !== undefined) {
+        opts.headers['Content-Type'] = 'application/json';
+        opts.body = JSON.stringify(body);
+    }
+
+    const resp = await fetch(new URL(path, base), opts);
+    const text = await resp.text();
+    let data = null;
+    try {
+        data = text ? JSON.parse(text) : null;
+    } catch (e) {
+        data = { message: text };
+    }
+
+    if (!resp.ok) {
+        const err = new Error((data && data.message) || resp.statusText);
+        err.status = resp.status;
+        err.info = data && data.info;
+        throw err;
+    }
+    return data;
+}
+
+function el(tag, attrs, ...children) {
+    const node = document.createElement(tag);
+    for (const [k, v] of Object.entries(attrs || {})) {
+        if (k.startsWith('on')) {
+            node.addEventListener(k.slice(2), v);
+        } else if (v !== false && v !== null && v !== undefined) {
+            node.setAttribute(k, v === true ? '' : v);
+        }
+    }
+    for (const child of children.flat()) {
+        node.append(child instanceof Node ? child : document.createTextNode(String(child)));
+    }
+    return node;
+}
+
+function render(...nodes) {
+    main.replaceChildren(...nodes);
+}
+
+let toastTimer = null;
+
+function toast(message, isError) {
+    const t = document.getElementById('toast');
+    t.textContent = message;
+    t.className = isError ? 'error' : '';
+    t.hidden = false;
+    clearTimeout(toastTimer);
+    toastTimer = setTimeout(() => { t.hidden = true; }, 4000);
+}
+
+function showError(err) {
+    toast(err.info ? `${err.message} (${err.info})` : err.message, true);
+}
+
+function go(hash) {
+    if (window.location.hash === hash) {
+        route();
+    } else {
+        window.location.hash = hash;
+    }
+}
+
+async function loadAccount() {
+    try {
+        state.account = await api('GET', 'account');
+    } catch (err) {
+        if (err.status !== 403 && err.status !== 401) {
+            throw err;
+        }
+        state.account = null;
+    }
+    nav.hidden = !state.account;
+}
+
+function loginView(next) {
+    const username = el('input', { name: 'username', autocomplete: 'username', required: true });
+    const password = el('input', { name: 'password', type: 'password', autocomplete: 'current-password', required: true });
+
+    const form = el('form', {
+        class: 'card',
+        onsubmit: async (e) => {
+            e.preventDefault();
+            try {
+                await api('POST', 'login', { username: username.value, password: password.value });
+                await loadAccount();
+                const target = next && new URL(next, base);
+                // Only ever bounce back somewhere on this server.
+                if (target && target.origin === base.origin) {
+                    window.location.href = target.href;
+                } else {
+                    go('#/');
+                }
+            } catch (err) {
+                showError(err);
+            }
+        },
+    },
+    el('h2', {}, 'Log in'),
+    el('label', {}, 'Username', username),
+    el('label', {}, 'Password', password),
+    el('button', { type: 'submit' }, 'Log in'));
+
+    render(form);
+    username.focus();
+}
+
+function matches(a, query) {
+    const q = query.toLowerCase();
+    return a.alias.toLowerCase().includes(q)
+        || a.destination.toLowerCase().includes(q)
+        || a.owner.toLowerCase().includes(q)
+        || a.description.toLowerCase().includes(q)
+        || a.tags.some((t) => t.includes(q));
+}
+
+function aliasRows(tbody) {
+    const rows = state.aliases
+        .filter((a) => !state.query || matches(a, state.query))
+        .map((a) => {
+            const mine = state.account && a.owner === state.account.user;
+            const actions = el('td', { class: 'actions' });
+            if (a.managed) {
+                actions.append(el('span', { class: 'muted', title: 'Managed by a declarative alias file' }, 'managed'));
+            } else {
+                // Anyone can edit a public alias; restricted ones only by their owner.
+                if (mine || a.visibility === 'public') {
+                    actions.append(el('button', { class: 'secondary', onclick: () => go(`#/edit/${encodeURIComponent(a.alias)}`) }, 'Edit'));
+                }
+                if (mine) {
+                    actions.append(el('button', { class: 'danger', onclick: () => deleteAlias(a.alias) }, 'Delete'));
+                }
+            }
+            return el('tr', {},
+                el('td', {}, el('a', { href: new URL(encodeURIComponent(a.alias), base).href }, a.alias)),
+                el('td', { class: 'destination' },
+                    a.canonical ? el('span', { class: 'muted' }, `alias of ${a.canonical}`) : a.destination,
+                    a.destinations.length > 1 ? el('span', { class: 'muted' }, ` and ${a.destinations.length - 1} more, ${a.strategy.replace('_', ' ')}`) : [],
+                    a.health && a.health.dead ? el('div', { class: 'dead', title: a.health.error || `HTTP ${a.health.status}` }, 'Dead link') : [],
+                    a.visibility !== 'public' ? el('div', { class: 'muted' }, { owner: 'Only visible to its owner', domain: "Only visible to its owner and the domain's owners" }[a.visibility] || 'Requires login') : [],
+                    a.rules.length ? el('div', { class: 'muted' }, `${a.rules.length} conditional ${a.rules.length === 1 ? 'rule' : 'rules'}`) : [],
+                    a.description ? el('div', { class: 'muted' }, a.description) : [],
+                    a.tags.length ? el('div', { class: 'tags' }, a.tags.map((t) => el('span', { class: 'tag' }, t))) : []),
+                el('td', { class: 'muted' }, a.owner),
+                actions);
+        });
+
+    tbody.replaceChildren(...rows);
+    if (rows.length === 0) {
+        tbody.append(el('tr', {}, el('td', { colspan: 4, class: 'muted' }, 'No aliases found.')));
+    }
+}
+
+async function listView() {
+    state.aliases = await api('GET', 'alias');
+
+    const tbody = el('tbody');
+    const search = el('input', {
+        type: 'search',
+        placeholder: 'Search aliases, destinations, descriptions and tags',
+        value: state.query,
+        oninput: (e) => {
+            state.query = e.target.value;
+            aliasRows(tbody);
+        },
+    });
+
+    render(el('div', { class: 'card' },
+        el('div', { class: 'toolbar' }, search, el('button', { onclick: () => go('#/new') }, 'New alias')),
+        el('table', {},
+            el('thead', {}, el('tr', {}, el('th', {}, 'Alias'), el('th', {}, 'Destination'), el('th', {}, 'Owner'), el('th'))),
+            tbody)));
+
+    aliasRows(tbody);
+    search.focus();
+}
+
+async function deleteAlias(alias) {
+    if (!window.confirm(`Delete ${alias}?`)) {
+        return;
+    }
+    try {
+        await api('DELETE', encodeURIComponent(alias));
+        toast(`Deleted ${alias}.`);
+        route();
+    } catch (err) {
+        showError(err);
+    }
+}
+
+function editView(existing, prefill) {
+    const from = el('input', { name: 'from', required: true, value: existing ? existing.alias : (prefill || ''), readonly: !!existing });
+    const to = el('input', { name: 'to', type: 'url', value: existing ? existing.destination : '' });
+    const destinations = el('textarea', { name: 'destinations', rows: 3, placeholder: 'To split requests, one URL per line, each optionally followed by a weight' },
+        existing ? existing.destinations.map((d) => `${d.to} ${d.weight}`).join('\n') : '');
+    const currentStrategy = existing ? existing.strategy : 'weighted';
+    const strategy = el('select', { name: 'strategy' },
+        [['weighted', 'Weighted random'], ['round_robin', 'Round robin'], ['sticky', 'Sticky per browser']]
+            .map(([value, name]) => el('option', { value, selected: value === currentStrategy }, name)));
+    const currentVisibility = existing ? existing.visibility : 'public';
+    const mine = !existing || (state.account && existing.owner === state.account.user);
+    const visibility = el('select', { name: 'visibility', disabled: !mine, title: mine ? null : 'Only the owner can restrict an alias' },
+        [['public', 'Anyone'], ['authenticated', 'Logged-in users'], ['domain', "Me and the domain's owners"], ['owner', 'Only me']]
+            .map(([value, name]) => el('option', { value, selected: value === currentVisibility }, name)));
+    const canonical = el('input', { name: 'canonical', placeholder: 'Another alias to resolve through instead', value: existing && existing.canonical ? existing.canonical : '' });
+    const current = existing ? existing.redirect : 302;
+    const redirect = el('select', { name: 'redirect' },
+        [[302, 'Found'], [301, 'Moved Permanently'], [307, 'Temporary Redirect'], [308, 'Permanent Redirect']]
+            .map(([code, name]) => el('option', { value: code, selected: code === current }, `${code} ${name}`)));
+    const description = el('input', { name: 'description', value: existing ? existing.description : '' });
+    const tags = el('input', { name: 'tags', placeholder: 'Separated by spaces or commas', value: existing ? existing.tags.join(' ') : '' });
+
+    const form = el('form', {
+        class: 'card',
+        onsubmit: async (e) => {
+            e.preventDefault();
+            try {
+                const split = destinations.value.split('\n')
+                    .map((line) => line.trim().split(/\s+/))
+                    .filter(([url]) => url)
+                    .map(([url, weight]) => ({ to: url, weight: weight ? Number(weight) : 1 }));
+                await api('POST', 'alias', {
+                    from: from.value,
+                    to: canonical.value ? '' : to.value,
+                    canonical: canonical.value || null,
+                    destinations: canonical.value ? [] : split,
+                    strategy: strategy.value,
+                    visibility: visibility.value,
+                    // Rules aren't editable here yet; keep whatever the alias already has.
+                    rules: canonical.value || !existing ? [] : existing.rules,
+                    redirect: Number(redirect.value),
+                    description: description.value,
+                    tags: tags.value.split(/[\s,]+/).filter((t) => t),
+                });
+                toast(`Saved ${from.value}.`);
+                go('#/');
+            } catch (err) {
+                showError(err);
+            }
+        },
+    },
+    el('h2', {}, existing ? `Edit ${existing.alias}` : 'New alias'),
+    el('label', {}, 'Alias', from),
+    el('label', {}, 'Destination', to),
+    el('label', {}, 'Split between', destinations),
+    el('label', {}, 'Strategy', strategy),
+    el('label', {}, 'Alias of', canonical),
+    el('label', {}, 'Who can use it', visibility),
+    el('label', {}, 'Redirect', redirect),
+    el('label', {}, 'Description', description),
+    el('label', {}, 'Tags', tags),
+    el('div', { class: 'toolbar' },
+        el('button', { type: 'submit' }, 'Save'),
+        el('button', { type: 'button', class: 'secondary', onclick: () => go('#/') }, 'Cancel')));
+
+    render(form);
+    (existing || prefill ? to : from).focus();
+}
+
+async function editExisting(alias) {
+    state.aliases = await api('GET', 'alias');
+    const existing = state.aliases.find((a) => a.alias === alias);
+    if (!existing) {
+        toast(`No alias named ${alias}.`, true);
+        go('#/');
+        return;
+    }
+    editView(existing);
+}
+
+function accountView() {
+    const current = el('input', { type: 'password', autocomplete: 'current-password', required: true });
+    const next = el('input', { type: 'password', autocomplete: 'new-password', required: true, minlength: 8 });
+
+    const passwordForm = el('form', {
+        onsubmit: async (e) => {
+            e.preventDefault();
+            try {
+                await api('POST', 'account/password', { old_password: current.value, new_password: next.value });
+                toast('Password changed.');
+                current.value = '';
+                next.value = '';
+            } catch (err) {
+                showError(err);
+            }
+        },
+    },
+    el('h3', {}, 'Change password'),
+    el('label', {}, 'Current password', current),
+    el('label', {}, 'New password', next),
+    el('button', { type: 'submit' }, 'Change password'));
+
+    const logout = el('button', {
+        class: 'secondary',
+        onclick: async () => {
+            try {
+                await api('DELETE', 'login');
+            } catch (err) {
+                // Already logged out.
+            }
+            await loadAccount();
+            go('#/login');
+        },
+    }, 'Log out');
+
+    render(el('div', { class: 'card' },
+        el('h2', {}, state.account.user),
+        el('p', { class: 'muted' }, `Session expires ${new Date(state.account.expires * 1000).toLocaleString()}.`),
+        passwordForm,
+        el('hr'),
+        logout));
+}
+
+async function route() {
+    const hash = window.location.hash.replace(/^#/, '') || '/';
+    const [path, query] = hash.split('?');
+    const params = new URLSearchParams(query || '');
+
+    try {
+        if (path === '/login') {
+            loginView(params.get('next'));
+            return;
+        }
+
+        if (!state.account) {
+            await loadAccount();
+        }
+        if (!state.account) {
+            // Come back to wherever we were headed, e.g. creating an alias from a 404 page.
+            const next = params.get('next') || `ui${window.location.hash}`;
+            go(`#/login?next=${encodeURIComponent(next)}`);
+            return;
+        }
+
+        if (path === '/account') {
+            accountView();
+        } else if (path === '/new') {
+            editView(null, params.get('alias'));
+        } else if (path.startsWith('/edit/')) {
+            await editExisting(decodeURIComponent(path.slice('/edit/'.length)));
+        } else {
+            await listView();
+        }
+    } catch (err) {
+        showError(err);
+    }
+}
+
+window.addEventListener('hashchange', route);
+route();
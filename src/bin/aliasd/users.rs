@@ -1,7 +1,7 @@
-use alias::model::{UserCreateError};
+use alias::model::{UserCreateError, WebhookEvent};
 use alias::db::conn;
 use alias::pass::create_pass_hash;
-use diesel::{RunQueryDsl, ExpressionMethods};
+use diesel::{RunQueryDsl, ExpressionMethods, QueryDsl};
 
 pub async fn add_user_interactive(user: impl AsRef<str>) -> Result<(), UserCreateError> {
     let pass = tokio::task::spawn_blocking(|| {
@@ -48,6 +48,14 @@ pub async fn del_user(user: impl Into<String>) -> anyhow::Result<()> {
             std::process::exit(1);
         }
 
+        diesel::delete(::alias::schema::admins::table)
+            .filter(::alias::schema::admins::user_id.ne_all(users.select(id)))
+            .execute(c)?;
+        diesel::delete(::alias::schema::domain_owners::table)
+            .filter(::alias::schema::domain_owners::user_id.ne_all(users.select(id)))
+            .execute(c)?;
+        ::alias::webhooks::enqueue(c, WebhookEvent::UserDeleted, serde_json::json!({"user": &us}))?;
+
         Result::<_, diesel::result::Error>::Ok(())
 
     }).await?;
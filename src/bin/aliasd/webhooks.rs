@@ -0,0 +1,299 @@
+use std::time::{Duration, Instant};
+
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha2::Sha256;
+use diesel::{Connection, ExpressionMethods, QueryDsl, QueryResult, RunQueryDsl, SqliteConnection};
+use futures::StreamExt;
+use hyper::{Body, Method, Request};
+use hyper::client::HttpConnector;
+use hyper::header::{CONTENT_TYPE, USER_AGENT};
+use hyper_rustls::HttpsConnector;
+use rand::Rng;
+
+use alias::db::conn;
+use alias::webhooks::OUTBOX_READY;
+
+/// Deliveries are given up on after this many failed attempts, which with the backoff below is about a day.
+pub const MAX_ATTEMPTS: i32 = 12;
+const BATCH_SIZE: i64 = 50;
+const CONCURRENCY: usize = 8;
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Even with nothing due, the outbox is looked at this often in case a delivery was queued by another process.
+const IDLE_WAIT: Duration = Duration::from_secs(60);
+/// How often finished deliveries older than the retention period are deleted.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+type HttpClient = hyper::Client<HttpsConnector<HttpConnector>>;
+
+struct Due {
+    id: i32,
+    event: String,
+    payload: String,
+    attempts: i32,
+    url: String,
+    secret: String,
+}
+
+struct Outcome {
+    id: i32,
+    attempts: i32,
+    status: Option<u16>,
+    error: Option<String>,
+    duration_ms: i32,
+}
+
+impl Outcome {
+    fn delivered(&self) -> bool {
+        matches!(self.status, Some(s) if (200..300).contains(&s))
+    }
+}
+
+/// Sends queued deliveries until the server stops, deleting finished ones after `retention_days`.
+pub async fn run(retention_days: u32) {
+    let client: HttpClient = hyper::Client::builder().build(HttpsConnector::with_native_roots());
+    let retention = i64::from(retention_days) * 24 * 60 * 60;
+    let mut swept: Option<Instant> = None;
+    loop {
+        if swept.map_or(true, |at| at.elapsed() >= SWEEP_INTERVAL) {
+            let cutoff = chrono::Local::now().timestamp() - retention;
+            match conn().with_conn(move |c| sweep(c, cutoff)).await {
+                Ok(0) => {}
+                Ok(n) => info!("Deleted {} finished webhook deliveries", n),
+                Err(e) => error!("Couldn't delete old webhook deliveries: {}", e),
+            }
+            swept = Some(Instant::now());
+        }
+
+        let wait = match deliver_due(&client).await {
+            Ok(w) => w,
+            Err(e) => {
+                error!("Couldn't deliver webhooks: {}", e);
+                IDLE_WAIT
+            }
+        };
+        // Timing out just means it's time to look again.
+        let _ = tokio::time::timeout(wait, OUTBOX_READY.notified()).await;
+    }
+}
+
+/// Attempts everything that's due, returning how long until the next delivery will be.
+async fn deliver_due(client: &HttpClient) -> QueryResult<Duration> {
+    let now = chrono::Local::now().timestamp();
+    let due = conn().with_conn(move |c| load_due(c, now)).await?;
+
+    let outcomes: Vec<Outcome> = futures::stream::iter(due.into_iter().map(|d| attempt(client, d)))
+        .buffer_unordered(CONCURRENCY)
+        .collect()
+        .await;
+
+    let next = conn().with_conn(move |c| {
+        let now = chrono::Local::now().timestamp();
+        for o in &outcomes {
+            record(c, o, now)?;
+        }
+        next_due(c)
+    }).await?;
+
+    let now = chrono::Local::now().timestamp();
+    Ok(match next {
+        Some(at) if at <= now => Duration::from_secs(0),
+        Some(at) => Duration::from_secs((at - now) as u64).min(IDLE_WAIT),
+        None => IDLE_WAIT,
+    })
+}
+
+fn load_due(c: &SqliteConnection, now: i64) -> QueryResult<Vec<Due>> {
+    use ::alias::schema::{webhook_deliveries as d, webhook_endpoints as e};
+
+    Ok(d::table.inner_join(e::table)
+        .select((d::id, d::event, d::payload, d::attempts, e::url, e::secret))
+        .filter(d::delivered_at.is_null())
+        .filter(d::failed.eq(false))
+        .filter(d::next_attempt_at.le(now))
+        .order(d::id)
+        .limit(BATCH_SIZE)
+        .load::<(i32, String, String, i32, String, String)>(c)?
+        .into_iter()
+        .map(|(id, event, payload, attempts, url, secret)| Due { id, event, payload, attempts, url, secret })
+        .collect())
+}
+
+fn next_due(c: &SqliteConnection) -> QueryResult<Option<i64>> {
+    use ::alias::schema::webhook_deliveries::dsl::*;
+    webhook_deliveries.select(diesel::dsl::min(next_attempt_at))
+        .filter(delivered_at.is_null())
+        .filter(failed.eq(false))
+        .get_result(c)
+}
+
+/// Deletes deliveries that went through before `cutoff`, or were given up on and created before it,
+/// along with their attempts. Returns how many deliveries went.
+fn sweep(c: &SqliteConnection, cutoff: i64) -> QueryResult<usize> {
+    use ::alias::schema::{webhook_attempts as a, webhook_deliveries as d};
+
+    c.transaction(|| {
+        let delivered = || d::table.filter(d::delivered_at.lt(cutoff));
+        // Given-up deliveries have no time of their own, but their last attempt is within a day of creation.
+        let given_up = || d::table.filter(d::failed.eq(true)).filter(d::created_at.lt(cutoff));
+
+        diesel::delete(a::table.filter(a::delivery.eq_any(delivered().select(d::id)))).execute(c)?;
+        diesel::delete(a::table.filter(a::delivery.eq_any(given_up().select(d::id)))).execute(c)?;
+        Ok(diesel::delete(delivered()).execute(c)? + diesel::delete(given_up()).execute(c)?)
+    })
+}
+
+/// The hex HMAC-SHA256 of `timestamp.body`, sent as `X-Alias-Signature: sha256=...`.
+///
+/// Including the timestamp lets receivers reject old deliveries being replayed at them.
+pub fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac = Hmac::new(Sha256::new(), secret.as_bytes());
+    mac.input(timestamp.to_string().as_bytes());
+    mac.input(b".");
+    mac.input(body.as_bytes());
+    mac.result().code().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn new_secret() -> String {
+    rand::thread_rng()
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(32)
+        .collect()
+}
+
+async fn attempt(client: &HttpClient, d: Due) -> Outcome {
+    let start = Instant::now();
+    let timestamp = chrono::Local::now().timestamp();
+    let signature = sign(&d.secret, timestamp, &d.payload);
+
+    let req = Request::builder()
+        .method(Method::POST)
+        .uri(&d.url)
+        .header(CONTENT_TYPE, "application/json")
+        .header(USER_AGENT, concat!("aliasd/", env!("CARGO_PKG_VERSION"), " (webhooks)"))
+        .header("X-Alias-Event", d.event.as_str())
+        .header("X-Alias-Delivery", d.id.to_string())
+        .header("X-Alias-Timestamp", timestamp.to_string())
+        .header("X-Alias-Signature", format!("sha256={}", signature))
+        .body(Body::from(d.payload));
+
+    let res = match req {
+        Ok(req) => match tokio::time::timeout(TIMEOUT, client.request(req)).await {
+            Ok(Ok(resp)) => Ok(resp.status().as_u16()),
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(_) => Err(format!("Timed out after {}s", TIMEOUT.as_secs())),
+        },
+        Err(e) => Err(e.to_string()),
+    };
+
+    let (status, error) = match res {
+        Ok(s) => (Some(s), None),
+        Err(e) => (None, Some(e)),
+    };
+    debug!("Webhook delivery {} to {}: {:?} {:?}", d.id, d.url, status, error);
+
+    Outcome {
+        id: d.id,
+        attempts: d.attempts + 1,
+        status,
+        error,
+        duration_ms: start.elapsed().as_millis() as i32,
+    }
+}
+
+/// Waits twice as long after each failure, starting at 30 seconds and topping out at six hours.
+fn backoff(attempts: i32) -> i64 {
+    (30i64 << (attempts.max(1) - 1).min(10) as u32).min(6 * 60 * 60)
+}
+
+fn record(c: &SqliteConnection, o: &Outcome, now: i64) -> QueryResult<()> {
+    use ::alias::schema::{webhook_attempts as a, webhook_deliveries as d};
+
+    diesel::insert_into(a::table)
+        .values((a::delivery.eq(o.id),
+                 a::attempted_at.eq(now),
+                 a::status.eq(o.status.map(i32::from)),
+                 a::error.eq(&o.error),
+                 a::duration_ms.eq(o.duration_ms)))
+        .execute(c)?;
+
+    let target = d::table.filter(d::id.eq(o.id));
+    if o.delivered() {
+        diesel::update(target)
+            .set((d::attempts.eq(o.attempts), d::delivered_at.eq(now)))
+            .execute(c)?;
+    } else if o.attempts >= MAX_ATTEMPTS {
+        warn!("Giving up on webhook delivery {} after {} attempts", o.id, o.attempts);
+        diesel::update(target)
+            .set((d::attempts.eq(o.attempts), d::failed.eq(true)))
+            .execute(c)?;
+    } else {
+        diesel::update(target)
+            .set((d::attempts.eq(o.attempts), d::next_attempt_at.eq(now + backoff(o.attempts))))
+            .execute(c)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn database() -> SqliteConnection {
+        let c = SqliteConnection::establish(":memory:").unwrap();
+        crate::embedded_migrations::run(&c).unwrap();
+        c
+    }
+
+    /// Queues a delivery created at `created` with one attempt, returning its id.
+    fn delivery(c: &SqliteConnection, created: i64, delivered: Option<i64>, failed: bool) -> i32 {
+        use ::alias::schema::{webhook_attempts as a, webhook_deliveries as d};
+
+        diesel::insert_into(d::table)
+            .values((d::endpoint.eq(1),
+                     d::event.eq("alias.created"),
+                     d::payload.eq("{}"),
+                     d::created_at.eq(created),
+                     d::attempts.eq(1),
+                     d::next_attempt_at.eq(created),
+                     d::delivered_at.eq(delivered),
+                     d::failed.eq(failed)))
+            .execute(c)
+            .unwrap();
+        let id = d::table.select(diesel::dsl::max(d::id)).get_result::<Option<i32>>(c).unwrap().unwrap();
+        diesel::insert_into(a::table)
+            .values((a::delivery.eq(id), a::attempted_at.eq(created), a::duration_ms.eq(1)))
+            .execute(c)
+            .unwrap();
+        id
+    }
+
+    #[test]
+    fn sweeps_only_finished_deliveries() {
+        use ::alias::schema::{webhook_attempts as a, webhook_deliveries as d, webhook_endpoints as e};
+
+        let c = database();
+        diesel::insert_into(e::table)
+            .values((e::url.eq("https://hooks.example/"), e::secret.eq("s"), e::created_at.eq(0)))
+            .execute(&c)
+            .unwrap();
+
+        let cutoff = 1000;
+        delivery(&c, 100, Some(200), false);
+        delivery(&c, 100, None, true);
+        let pending = delivery(&c, 100, None, false);
+        let recent = delivery(&c, 900, Some(cutoff + 1), false);
+        let recently_given_up = delivery(&c, cutoff + 1, None, true);
+
+        assert_eq!(sweep(&c, cutoff), Ok(2));
+
+        let mut left: Vec<i32> = d::table.select(d::id).load(&c).unwrap();
+        left.sort();
+        assert_eq!(left, [pending, recent, recently_given_up]);
+        let mut attempted: Vec<i32> = a::table.select(a::delivery).load(&c).unwrap();
+        attempted.sort();
+        assert_eq!(attempted, left);
+
+        assert_eq!(sweep(&c, cutoff), Ok(0));
+    }
+}
@@ -0,0 +1,307 @@
+//! An async client for `aliasd`, for tools that would otherwise shell out to `alias-client`.
+//!
+//! Make an [`AliasClient`] with the server's URL, then either [`AliasClient::login`] or hand it a
+//! token saved from an earlier login with [`AliasClient::with_token`]. Endpoints without a method of
+//! their own can be called with [`AliasClient::call`] and the types in [`crate::api`].
+//!
+//! Only built with the `client` feature, so `aliasd` can be built without reqwest.
+
+use reqwest::{header, redirect, Client, Method, RequestBuilder, Response, StatusCode};
+use url::Url;
+
+use crate::api::{self, Endpoint};
+use crate::model::{AliasForm, AliasInfo, AliasSaved, ErrorBody, Login, LoginToken, SearchHit};
+
+/// An error response from `aliasd`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiError {
+    pub status: u16,
+    #[serde(flatten)]
+    pub body: ErrorBody,
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.status, self.body)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Not logged in, the token expired, or the user isn't allowed to do that.
+    #[error("Authentication failed: {0}")]
+    Auth(ApiError),
+    #[error("Not found: {0}")]
+    NotFound(ApiError),
+    /// Any other 4xx, like an invalid destination or a change to a managed alias.
+    #[error("Rejected by the server: {0}")]
+    Validation(ApiError),
+    #[error("The server had an error: {0}")]
+    Server(ApiError),
+    #[error("Couldn't reach the server: {0}")]
+    Network(#[from] reqwest::Error),
+    #[error("Invalid URL: {0}")]
+    Url(#[from] url::ParseError),
+    /// A response `aliasd` shouldn't send, like a redirect without a `Location`.
+    #[error("Unexpected response: {0}")]
+    Unexpected(String),
+}
+
+impl Error {
+    /// Turns an unsuccessful response into an error, using the server's JSON body when it sent one.
+    pub async fn from_response(resp: Response) -> Error {
+        let status = resp.status();
+        let text = match resp.text().await {
+            Ok(t) => t,
+            Err(e) => return e.into(),
+        };
+
+        let body = serde_json::from_str::<ErrorBody>(&text)
+            .unwrap_or_else(|_| ErrorBody {
+                message: if text.is_empty() {
+                    status.canonical_reason().unwrap_or("Unknown error").to_string()
+                } else {
+                    text
+                },
+                info: None,
+            });
+
+        let err = ApiError { status: status.as_u16(), body };
+        match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Error::Auth(err),
+            StatusCode::NOT_FOUND => Error::NotFound(err),
+            s if s.is_server_error() => Error::Server(err),
+            _ => Error::Validation(err),
+        }
+    }
+
+    /// What the server said, if the error came from it.
+    pub fn api(&self) -> Option<&ApiError> {
+        match self {
+            Error::Auth(e) | Error::NotFound(e) | Error::Validation(e) | Error::Server(e) => Some(e),
+            Error::Network(_) | Error::Url(_) | Error::Unexpected(_) => None,
+        }
+    }
+}
+
+/// A connection to one `aliasd` server.
+///
+/// Cheap to clone; clones share connections, and each keeps its own token.
+#[derive(Debug, Clone)]
+pub struct AliasClient {
+    http: Client,
+    /// Stops at redirects so [`AliasClient::resolve`] can read where they go.
+    no_redirects: Client,
+    server_url: Url,
+    token: Option<String>,
+    /// Sent as `?domain=` with every request, instead of letting the server go by its hostname.
+    domain: Option<String>,
+}
+
+impl AliasClient {
+    /// `server_url` may have a path, like `https://example.com/links` for a server behind a proxy there.
+    pub fn new(server_url: &str) -> Result<AliasClient, Error> {
+        let mut url = server_url.parse::<Url>()?;
+        // Without the slash, joining paths onto it would replace the last segment.
+        if !url.path().ends_with('/') {
+            let path = format!("{}/", url.path());
+            url.set_path(&path);
+        }
+
+        Ok(AliasClient {
+            http: Client::builder().build()?,
+            no_redirects: Client::builder().redirect(redirect::Policy::none()).build()?,
+            server_url: url,
+            token: None,
+            domain: None,
+        })
+    }
+
+    /// Uses a token from an earlier login instead of logging in.
+    pub fn with_token(mut self, token: impl Into<String>) -> AliasClient {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Works in `domain` instead of the one the server URL's host is for. Empty for the default domain.
+    pub fn with_domain(mut self, domain: impl Into<String>) -> AliasClient {
+        self.domain = Some(domain.into());
+        self
+    }
+
+    pub fn server_url(&self) -> &Url {
+        &self.server_url
+    }
+
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+
+    pub fn domain(&self) -> Option<&str> {
+        self.domain.as_deref()
+    }
+
+    /// Logs in, keeping the token for the calls that follow.
+    pub async fn login(&mut self, username: &str, password: &str) -> Result<LoginToken, Error> {
+        let login = api::LoginRequest(Login { username: username.to_string(), password: password.to_string() });
+        let token = self.call(&login).await?;
+        self.token = Some(token.token.clone());
+        Ok(token)
+    }
+
+    /// Calls one of the endpoints in [`crate::api`], decoding its response.
+    pub async fn call<E: Endpoint>(&self, endpoint: &E) -> Result<E::Response, Error> {
+        let resp = self.request(&self.http, endpoint)?.send().await?;
+        if !resp.status().is_success() {
+            return Err(Error::from_response(resp).await);
+        }
+        Ok(resp.json().await?)
+    }
+
+    /// Where `alias` redirects to right now. Aliases with several destinations or rules may answer
+    /// differently each time.
+    pub async fn resolve(&self, alias: &str) -> Result<String, Error> {
+        let resp = self.request(&self.no_redirects, &api::ResolveAlias { alias: alias.to_string() })?
+            .header(header::ACCEPT, "application/json")
+            .send()
+            .await?;
+        if !resp.status().is_redirection() {
+            return Err(Error::from_response(resp).await);
+        }
+
+        resp.headers()
+            .get(header::LOCATION)
+            .ok_or_else(|| Error::Unexpected("Got a redirect but no location.".to_string()))?
+            .to_str()
+            .map(String::from)
+            .map_err(|_| Error::Unexpected("Got a location, but it wasn't valid UTF-8.".to_string()))
+    }
+
+    /// The aliases in the domain that the user can see.
+    pub async fn list(&self) -> Result<Vec<AliasInfo>, Error> {
+        self.call(&api::ListAliases).await
+    }
+
+    /// One alias from [`AliasClient::list`].
+    pub async fn get(&self, alias: &str) -> Result<AliasInfo, Error> {
+        self.list().await?
+            .into_iter()
+            .find(|a| a.alias == alias)
+            .ok_or_else(|| Error::NotFound(ApiError {
+                status: StatusCode::NOT_FOUND.as_u16(),
+                body: ErrorBody { message: "No such alias".to_string(), info: Some(alias.to_string()) },
+            }))
+    }
+
+    pub async fn search(&self, q: &str) -> Result<Vec<SearchHit>, Error> {
+        self.call(&api::SearchAliases { q: q.to_string() }).await
+    }
+
+    /// Creates an alias. `aliasd` replaces the user's own alias by that name rather than refusing.
+    pub async fn create(&self, form: AliasForm) -> Result<AliasSaved, Error> {
+        self.call(&api::SaveAlias(form)).await
+    }
+
+    /// Changes an existing alias, keeping whatever `edit` leaves alone.
+    pub async fn update(&self, alias: &str, edit: impl FnOnce(&mut AliasForm)) -> Result<AliasSaved, Error> {
+        let info = self.get(alias).await?;
+        let mut form = AliasForm {
+            from: info.alias,
+            domain: Some(info.domain),
+            to: info.destination,
+            canonical: info.canonical,
+            destinations: info.destinations,
+            strategy: info.strategy,
+            rules: info.rules,
+            visibility: info.visibility,
+            // So the server still refuses to change aliases that `alias-client apply` manages.
+            managed: false,
+            redirect: Some(info.redirect),
+            description: info.description,
+            tags: info.tags,
+        };
+        edit(&mut form);
+        self.create(form).await
+    }
+
+    pub async fn delete(&self, alias: &str) -> Result<(), Error> {
+        self.call(&api::DeleteAlias { alias: alias.to_string(), managed: false }).await?;
+        Ok(())
+    }
+
+    /// Where `endpoint` is on this server. Names are encoded as single path segments rather than
+    /// joined on, so one like `//other.example/x` can't send the token to another host.
+    fn url<E: Endpoint>(&self, endpoint: &E) -> Result<Url, Error> {
+        let mut url = self.server_url.clone();
+        url.path_segments_mut()
+            .map_err(|_| Error::Unexpected(format!("{} can't have a path", self.server_url)))?
+            .pop_if_empty()
+            .extend(endpoint.segments());
+        Ok(url)
+    }
+
+    fn request<E: Endpoint>(&self, http: &Client, endpoint: &E) -> Result<RequestBuilder, Error> {
+        let method = match E::METHOD {
+            api::Method::Get => Method::GET,
+            api::Method::Post => Method::POST,
+            api::Method::Put => Method::PUT,
+            api::Method::Delete => Method::DELETE,
+        };
+        let mut req = http.request(method, self.url(endpoint)?);
+
+        let mut query = endpoint.query();
+        if let Some(d) = &self.domain {
+            query.push(("domain", d.clone()));
+        }
+        if !query.is_empty() {
+            req = req.query(&query);
+        }
+        if let Some(body) = endpoint.body() {
+            req = req.json(body);
+        }
+        if let Some(token) = &self.token {
+            req = req.bearer_auth(token);
+        }
+        Ok(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve_url(server: &str, alias: &str) -> Url {
+        AliasClient::new(server).unwrap()
+            .url(&api::ResolveAlias { alias: alias.to_string() })
+            .unwrap()
+    }
+
+    #[test]
+    fn names_stay_on_the_server() {
+        let url = resolve_url("https://go.example/", "//evil.example/x");
+        assert_eq!(url.host_str(), Some("go.example"));
+        assert_eq!(url.path(), "/%2F%2Fevil.example%2Fx");
+    }
+
+    #[test]
+    fn names_are_one_segment() {
+        assert_eq!(resolve_url("https://go.example/", "c:foo").as_str(), "https://go.example/c:foo");
+        assert_eq!(resolve_url("https://go.example/", "a?b").as_str(), "https://go.example/a%3Fb");
+        assert_eq!(resolve_url("https://go.example/", "a#b").as_str(), "https://go.example/a%23b");
+        assert_eq!(resolve_url("https://go.example/", "50%").as_str(), "https://go.example/50%25");
+    }
+
+    #[test]
+    fn names_keep_the_server_path() {
+        assert_eq!(resolve_url("https://go.example/links", "docs").as_str(), "https://go.example/links/docs");
+        assert_eq!(resolve_url("https://go.example/links/", "a/b").as_str(), "https://go.example/links/a%2Fb");
+    }
+
+    #[test]
+    fn fixed_paths_and_domains() {
+        let client = AliasClient::new("https://go.example/links").unwrap();
+        assert_eq!(client.url(&api::ListAliases).unwrap().as_str(), "https://go.example/links/alias");
+        let domain = api::DeleteDomain { name: "a/b".to_string() };
+        assert_eq!(client.url(&domain).unwrap().as_str(), "https://go.example/links/admin/domains/a%2Fb");
+    }
+}
@@ -0,0 +1,397 @@
+//! aliasd's configuration: built-in defaults, then a TOML file, then environment variables.
+//!
+//! Any key can be set from the environment as `ALIAS_<SECTION>__<KEY>`, e.g. `ALIAS_CACHE__SIZE=8192`.
+//! The variables aliasd read before this existed, like `DATABASE_URL` and `ALIAS_SECRET_KEY`, still work.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use once_cell::sync::OnceCell;
+use rocket::figment::Figment;
+use rocket::figment::providers::{Env, Format, Serialized, Toml};
+use serde::{Deserialize, Serialize};
+
+use crate::net::Cidr;
+
+/// Shown in place of secrets by `aliasd config show`.
+pub const REDACTED: &str = "<redacted>";
+
+/// Environment variables from before the config file, and the keys they set.
+const LEGACY_ENV: &[(&str, &str)] = &[
+    ("ROCKET_ADDRESS", "server.address"),
+    ("ROCKET_PORT", "server.port"),
+    ("DATABASE_URL", "database.path"),
+    ("ALIAS_SECRET_KEY", "auth.secret_key"),
+    ("ALIAS_BACKUP_DIR", "backup.dir"),
+    ("ALIAS_BACKUP_INTERVAL_MINS", "backup.interval_mins"),
+    ("ALIAS_BACKUP_KEEP", "backup.keep"),
+    ("ALIAS_HEALTH_INTERVAL_MINS", "health.interval_mins"),
+    ("ALIAS_HEALTH_CONCURRENCY", "health.concurrency"),
+    ("ALIAS_HEALTH_TIMEOUT_SECS", "health.timeout_secs"),
+    ("ALIAS_HEALTH_HOST_DELAY_MS", "health.host_delay_ms"),
+    ("ALIAS_HEALTH_NOTIFY_CMD", "health.notify_cmd"),
+    ("ALIAS_METRICS_ADDR", "metrics.address"),
+    ("ALIAS_LOG_FORMAT", "logging.format"),
+    ("RUST_LOG", "logging.filter"),
+    ("ALIAS_OTLP_ENDPOINT", "logging.otlp_endpoint"),
+];
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AliasConfig {
+    pub server: ServerConfig,
+    pub tls: TlsConfig,
+    pub database: DatabaseConfig,
+    pub cache: CacheConfig,
+    pub auth: AuthConfig,
+    pub backup: BackupConfig,
+    pub health: HealthConfig,
+    pub webhooks: WebhooksConfig,
+    pub metrics: MetricsConfig,
+    pub logging: LoggingConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub address: IpAddr,
+    pub port: u16,
+    /// Proxies whose `Forwarded` and `X-Forwarded-*` headers are believed. Nobody's are by default.
+    pub trusted_proxies: Vec<Cidr>,
+    /// Which headers the trusted proxies use. The other kind is ignored, since they'd pass it on
+    /// from the client untouched.
+    pub forwarded_header: ForwardedHeader,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            address: Ipv4Addr::LOCALHOST.into(),
+            port: 8000,
+            trusted_proxies: Vec::new(),
+            forwarded_header: ForwardedHeader::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ForwardedHeader {
+    /// `X-Forwarded-For`, or `X-Real-IP` without it, along with `X-Forwarded-Proto` and `X-Forwarded-Host`.
+    #[serde(rename = "x-forwarded-for")]
+    XForwardedFor,
+    /// RFC 7239's `Forwarded`, with its `for=`, `proto=` and `host=`.
+    #[serde(rename = "forwarded")]
+    Forwarded,
+}
+
+impl Default for ForwardedHeader {
+    fn default() -> Self {
+        ForwardedHeader::XForwardedFor
+    }
+}
+
+/// HTTPS on `server.address:server.port`, enabled when `certs` and `key` are set.
+///
+/// Rocket then listens for plain HTTP on `127.0.0.1:backend_port`, which only the TLS listener talks to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    /// A PEM file with the certificate chain, leaf first. Reloaded when it changes on disk.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub certs: Option<PathBuf>,
+    /// A PEM file with the certificate's private key. Reloaded when it changes on disk.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<PathBuf>,
+    pub min_version: TlsVersion,
+    /// PEM CA certificates. When set, `/admin` routes only answer clients presenting a certificate it issued.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_ca: Option<PathBuf>,
+    pub backend_port: u16,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        TlsConfig { certs: None, key: None, min_version: TlsVersion::default(), client_ca: None, backend_port: 8001 }
+    }
+}
+
+impl TlsConfig {
+    pub fn enabled(&self) -> bool {
+        self.certs.is_some() && self.key.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TlsVersion {
+    #[serde(rename = "1.2")]
+    Tls12,
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+impl Default for TlsVersion {
+    fn default() -> Self {
+        TlsVersion::Tls12
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub path: String,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        let path = dirs::data_local_dir()
+            .map(|p| p.join("aliasd").join("alias.sqlite"))
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        DatabaseConfig { path }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// How many resolved aliases are kept in memory.
+    pub size: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig { size: 4096 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Used for password hashing and signing login tokens. Set it to `""` to use no secret on purpose.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret_key: Option<String>,
+    pub token_lifetime_hours: u32,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig { secret_key: None, token_lifetime_hours: 7 * 24 }
+    }
+}
+
+/// Periodic database snapshots, taken only when `dir` is set.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackupConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dir: Option<PathBuf>,
+    pub interval_mins: u64,
+    pub keep: usize,
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        BackupConfig { dir: None, interval_mins: 24 * 60, keep: 7 }
+    }
+}
+
+/// The dead link checker, which only runs in the background when `interval_mins` is set.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interval_mins: Option<u64>,
+    pub concurrency: usize,
+    pub timeout_secs: u64,
+    pub host_delay_ms: u64,
+    /// Run for each alias whose destination stops working.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify_cmd: Option<String>,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        HealthConfig { interval_mins: None, concurrency: 8, timeout_secs: 10, host_delay_ms: 1000, notify_cmd: None }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhooksConfig {
+    /// How long delivered and given-up-on deliveries are kept, along with their attempts.
+    pub retention_days: u32,
+}
+
+impl Default for WebhooksConfig {
+    fn default() -> Self {
+        WebhooksConfig { retention_days: 7 }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Where `/metrics` is served; off unless set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<SocketAddr>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl Default for LogFormat {
+    fn default() -> Self {
+        LogFormat::Text
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    pub format: LogFormat,
+    /// `EnvFilter` directives like `info,hyper=warn`. Overrides `-v` when set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub otlp_endpoint: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("{0}")]
+    Parse(#[from] rocket::figment::Error),
+    #[error("{key} (from {origin}): {message}")]
+    Invalid { key: &'static str, origin: String, message: String },
+}
+
+impl AliasConfig {
+    /// Where each setting comes from, lowest priority first.
+    pub fn figment(file: Option<&Path>) -> Figment {
+        let mut fig = Figment::from(Serialized::defaults(AliasConfig::default()));
+        if let Some(f) = file {
+            // A missing file is fine; everything has a default.
+            fig = fig.merge(Toml::file(f));
+        }
+
+        let legacy = Env::raw()
+            .only(&LEGACY_ENV.iter().map(|(var, _)| *var).collect::<Vec<_>>())
+            .map(|var| LEGACY_ENV.iter()
+                .find(|(v, _)| var.as_str().eq_ignore_ascii_case(v))
+                .map(|(_, key)| (*key).into())
+                .unwrap_or_else(|| var.into()))
+            .split(".");
+
+        fig.merge(legacy)
+            .merge(Env::prefixed("ALIAS_").filter(|k| k.as_str().contains("__")).split("__"))
+    }
+
+    /// Reads and validates the configuration.
+    pub fn load(file: Option<&Path>) -> Result<AliasConfig, ConfigError> {
+        let fig = AliasConfig::figment(file);
+        let cfg: AliasConfig = fig.extract()?;
+        cfg.validate(&fig)?;
+        Ok(cfg)
+    }
+
+    fn validate(&self, fig: &Figment) -> Result<(), ConfigError> {
+        let invalid = |key: &'static str, message: &str| ConfigError::Invalid {
+            key,
+            origin: fig.find_metadata(key)
+                .map(|m| m.name.to_string())
+                .unwrap_or_else(|| "defaults".to_string()),
+            message: message.to_string(),
+        };
+
+        if self.database.path.is_empty() {
+            return Err(invalid("database.path", "must not be empty"));
+        }
+        if self.cache.size == 0 {
+            return Err(invalid("cache.size", "must be at least 1"));
+        }
+        if self.auth.token_lifetime_hours == 0 {
+            return Err(invalid("auth.token_lifetime_hours", "must be at least 1"));
+        }
+        if self.backup.interval_mins == 0 {
+            return Err(invalid("backup.interval_mins", "must be at least 1"));
+        }
+        if self.backup.keep == 0 {
+            return Err(invalid("backup.keep", "must be at least 1"));
+        }
+        if self.health.interval_mins == Some(0) {
+            return Err(invalid("health.interval_mins", "must be at least 1"));
+        }
+        if self.health.concurrency == 0 {
+            return Err(invalid("health.concurrency", "must be at least 1"));
+        }
+        if self.webhooks.retention_days == 0 {
+            return Err(invalid("webhooks.retention_days", "must be at least 1"));
+        }
+        match (&self.tls.certs, &self.tls.key) {
+            (Some(_), None) => return Err(invalid("tls.certs", "tls.key must be set too")),
+            (None, Some(_)) => return Err(invalid("tls.key", "tls.certs must be set too")),
+            (Some(certs), Some(key)) => {
+                if !certs.is_file() {
+                    return Err(invalid("tls.certs", &format!("{} isn't a readable file", certs.display())));
+                }
+                if !key.is_file() {
+                    return Err(invalid("tls.key", &format!("{} isn't a readable file", key.display())));
+                }
+            }
+            (None, None) => {}
+        }
+        if let Some(ca) = &self.tls.client_ca {
+            if !self.tls.enabled() {
+                return Err(invalid("tls.client_ca", "needs tls.certs and tls.key to be set"));
+            }
+            if !ca.is_file() {
+                return Err(invalid("tls.client_ca", &format!("{} isn't a readable file", ca.display())));
+            }
+        }
+        if self.tls.enabled() && self.tls.backend_port == self.server.port {
+            return Err(invalid("tls.backend_port", "must differ from server.port"));
+        }
+        if let Some(filter) = &self.logging.filter {
+            if filter.trim().is_empty() {
+                return Err(invalid("logging.filter", "must not be empty when set"));
+            }
+        }
+        Ok(())
+    }
+
+    /// A copy that's safe to print.
+    pub fn redacted(&self) -> AliasConfig {
+        let mut cfg = self.clone();
+        if let Some(s) = &mut cfg.auth.secret_key {
+            *s = REDACTED.to_string();
+        }
+        cfg
+    }
+}
+
+static CONFIG: OnceCell<RwLock<Arc<AliasConfig>>> = OnceCell::new();
+
+fn current() -> &'static RwLock<Arc<AliasConfig>> {
+    CONFIG.get_or_init(|| RwLock::new(Arc::new(AliasConfig::figment(None).extract().unwrap_or_default())))
+}
+
+/// Makes `cfg` the configuration everything else reads. Only the first call has any effect.
+pub fn init(cfg: AliasConfig) {
+    CONFIG.get_or_init(|| RwLock::new(Arc::new(cfg)));
+}
+
+/// Swaps in a reloaded configuration. Code that already called [`get`] keeps the old one until it calls it again.
+pub fn replace(cfg: AliasConfig) {
+    *current().write().unwrap() = Arc::new(cfg);
+}
+
+/// The configuration passed to [`init`] or [`replace`], or one read from the environment alone if there wasn't one.
+pub fn get() -> Arc<AliasConfig> {
+    current().read().unwrap().clone()
+}
@@ -1,12 +1,16 @@
 use diesel::SqliteConnection;
 use diesel::prelude::*;
+use diesel::sql_types::Text;
 use crossbeam::channel;
 use std::thread::{JoinHandle};
 use futures::channel::oneshot;
-use once_cell::sync::{Lazy, OnceCell};
+use once_cell::sync::OnceCell;
 use std::borrow::Cow;
 use diesel::result::DatabaseErrorKind;
 use std::path::Path;
+use std::time::Instant;
+
+use crate::metrics;
 
 type ConnFunPtr = Box<dyn FnOnce(&mut SqliteConnection) + Send + 'static>;
 
@@ -22,7 +26,10 @@ impl From<SqliteConnection> for DBService {
         let runner = std::thread::spawn(move || {
             let mut c = conn;
             recv.into_iter()
-                .for_each(|f| f(&mut c))
+                .for_each(|f| {
+                    crate::metrics::DB_QUEUE_DEPTH.dec();
+                    f(&mut c)
+                })
         });
         DBService { task_pool: send, tl_pool: thread_local::ThreadLocal::new(), runner: Some(runner) }
     }
@@ -39,15 +46,27 @@ impl DBService {
         where F: FnOnce(&mut SqliteConnection) -> T + Send + 'static,
               T: Send + 'static {
         let (send, recv) = oneshot::channel();
+        let queued = Instant::now();
+        metrics::DB_QUEUE_DEPTH.inc();
         self.local_pool().await.send(
-            Box::new(|c| {
-                send.send(f(c)).ok().unwrap()
+            Box::new(move |c| {
+                metrics::DB_JOB_WAIT_SECONDS.observe(queued.elapsed().as_secs_f64());
+                let res = {
+                    let _timer = metrics::DB_JOB_SECONDS.start_timer();
+                    f(c)
+                };
+                send.send(res).ok().unwrap()
             })
         ).unwrap();
 
         recv.await.unwrap()
     }
 
+    /// Waits for every job queued before this one to finish, since they run in order on one thread.
+    pub async fn flush(&self) {
+        self.with_conn(|_| ()).await
+    }
+
     async fn local_pool(&self) -> &channel::Sender<ConnFunPtr> {
         self.tl_pool.get_or(|| self.task_pool.clone())
     }
@@ -61,18 +80,8 @@ pub fn establish_connection(path: impl AsRef<str>) -> SqliteConnection {
     SqliteConnection::establish(path.as_ref()).unwrap()
 }
 
-static DEFAULT_DB_PATH: Lazy<String> = Lazy::new(
-    || {
-        dirs::data_local_dir().map(|p| p.join("aliasd").join("alias.sqlite"))
-            .unwrap()
-            .to_str()
-            .unwrap()
-            .to_string()
-    }
-);
-
 pub fn db_path() -> Cow<'static, str> {
-    std::env::var("DATABASE_URL").map(|s| Cow::Owned(s)).unwrap_or(Cow::from(DEFAULT_DB_PATH.as_str()))
+    Cow::Owned(crate::config::get().database.path.clone())
 }
 
 pub static DB_SERVICE: OnceCell<DBService> = OnceCell::new();
@@ -98,3 +107,28 @@ impl DBError for diesel::result::Error {
         }
     }
 }
+
+#[derive(QueryableByName)]
+struct MigrationVersion {
+    #[sql_type = "Text"]
+    version: String,
+}
+
+/// Lists the migration versions that have been run against a database, oldest first.
+pub fn applied_migrations(c: &SqliteConnection) -> QueryResult<Vec<String>> {
+    diesel::sql_query("select version from __diesel_schema_migrations order by version")
+        .load::<MigrationVersion>(c)
+        .map(|v| v.into_iter().map(|m| m.version).collect())
+}
+
+#[derive(QueryableByName)]
+struct IntegrityCheck {
+    #[sql_type = "Text"]
+    integrity_check: String,
+}
+
+pub fn integrity_check(c: &SqliteConnection) -> QueryResult<bool> {
+    diesel::sql_query("pragma integrity_check")
+        .load::<IntegrityCheck>(c)
+        .map(|rows| rows.len() == 1 && rows[0].integrity_check == "ok")
+}
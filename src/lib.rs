@@ -11,6 +11,15 @@ extern crate rocket;
 extern crate tracing;
 
 pub mod schema;
+pub mod config;
 pub mod db;
 pub mod model;
 pub mod pass;
+pub mod backup;
+pub mod search;
+pub mod webhooks;
+pub mod metrics;
+pub mod net;
+pub mod api;
+#[cfg(feature = "client")]
+pub mod client;
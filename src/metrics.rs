@@ -0,0 +1,119 @@
+//! Prometheus metrics, registered in the default registry and served by aliasd on its own address.
+
+use once_cell::sync::Lazy;
+use prometheus::{Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts};
+
+/// Buckets for the database thread, which should answer in well under a millisecond when it isn't backed up.
+const DB_BUCKETS: &[f64] = &[0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5];
+
+/// Argon2 is slow on purpose, so these start where the default buckets stop being useful.
+const HASH_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+
+fn counter(name: &str, help: &str) -> IntCounter {
+    let c = IntCounter::new(name, help).unwrap();
+    prometheus::register(Box::new(c.clone())).unwrap();
+    c
+}
+
+fn counter_vec(name: &str, help: &str, labels: &[&str]) -> IntCounterVec {
+    let c = IntCounterVec::new(Opts::new(name, help), labels).unwrap();
+    prometheus::register(Box::new(c.clone())).unwrap();
+    c
+}
+
+fn histogram(opts: HistogramOpts) -> Histogram {
+    let h = Histogram::with_opts(opts).unwrap();
+    prometheus::register(Box::new(h.clone())).unwrap();
+    h
+}
+
+fn histogram_vec(opts: HistogramOpts, labels: &[&str]) -> HistogramVec {
+    let h = HistogramVec::new(opts, labels).unwrap();
+    prometheus::register(Box::new(h.clone())).unwrap();
+    h
+}
+
+pub static HTTP_REQUEST_SECONDS: Lazy<HistogramVec> = Lazy::new(|| histogram_vec(
+    HistogramOpts::new("aliasd_http_request_duration_seconds", "Time spent handling a request, by route."),
+    &["route"],
+));
+
+pub static REDIRECTS: Lazy<IntCounterVec> = Lazy::new(|| counter_vec(
+    "aliasd_redirects_total",
+    "Responses to alias lookups, by HTTP status.",
+    &["status"],
+));
+
+pub static CACHE_HITS: Lazy<IntCounter> = Lazy::new(|| counter(
+    "aliasd_alias_cache_hits_total",
+    "Alias lookups answered from the cache.",
+));
+
+pub static CACHE_MISSES: Lazy<IntCounter> = Lazy::new(|| counter(
+    "aliasd_alias_cache_misses_total",
+    "Alias lookups that had to go to the database.",
+));
+
+pub static CACHE_EVICTIONS: Lazy<IntCounter> = Lazy::new(|| counter(
+    "aliasd_alias_cache_evictions_total",
+    "Aliases dropped from the cache to make room.",
+));
+
+pub static CACHE_INVALIDATIONS: Lazy<IntCounter> = Lazy::new(|| counter(
+    "aliasd_alias_cache_invalidations_total",
+    "Aliases dropped from the cache because they, or a canonical they went through, changed.",
+));
+
+pub static DB_QUEUE_DEPTH: Lazy<IntGauge> = Lazy::new(|| {
+    let g = IntGauge::new("aliasd_db_queue_depth", "Jobs waiting for the database thread.").unwrap();
+    prometheus::register(Box::new(g.clone())).unwrap();
+    g
+});
+
+pub static DB_JOB_WAIT_SECONDS: Lazy<Histogram> = Lazy::new(|| histogram(
+    HistogramOpts::new("aliasd_db_job_wait_seconds", "Time a job spent queued before the database thread picked it up.")
+        .buckets(DB_BUCKETS.to_vec())
+));
+
+pub static DB_JOB_SECONDS: Lazy<Histogram> = Lazy::new(|| histogram(
+    HistogramOpts::new("aliasd_db_job_duration_seconds", "Time the database thread spent running a job.")
+        .buckets(DB_BUCKETS.to_vec())
+));
+
+pub static LOGINS: Lazy<IntCounterVec> = Lazy::new(|| counter_vec(
+    "aliasd_logins_total",
+    "Login attempts, by result: success, failure or error.",
+    &["result"],
+));
+
+pub static PASSWORD_HASH_SECONDS: Lazy<HistogramVec> = Lazy::new(|| histogram_vec(
+    HistogramOpts::new("aliasd_password_hash_duration_seconds", "Time spent in argon2, by operation: hash or verify.")
+        .buckets(HASH_BUCKETS.to_vec()),
+    &["op"],
+));
+
+/// Registers every metric up front, so they're exported as zero rather than missing until first used.
+pub fn init() {
+    Lazy::force(&HTTP_REQUEST_SECONDS);
+    Lazy::force(&REDIRECTS);
+    Lazy::force(&CACHE_HITS);
+    Lazy::force(&CACHE_MISSES);
+    Lazy::force(&CACHE_EVICTIONS);
+    Lazy::force(&CACHE_INVALIDATIONS);
+    Lazy::force(&DB_QUEUE_DEPTH);
+    Lazy::force(&DB_JOB_WAIT_SECONDS);
+    Lazy::force(&DB_JOB_SECONDS);
+    Lazy::force(&LOGINS);
+    Lazy::force(&PASSWORD_HASH_SECONDS);
+}
+
+/// Everything in the default registry in the Prometheus text format.
+pub fn render() -> String {
+    use prometheus::Encoder;
+
+    let mut buf = Vec::new();
+    prometheus::TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buf)
+        .unwrap();
+    String::from_utf8(buf).unwrap()
+}
@@ -8,6 +8,7 @@ use rocket::request::{FromRequest, Outcome};
 use rocket::Request;
 use rocket::http::Status;
 use diesel::expression::exists::exists;
+use schemars::JsonSchema;
 
 #[derive(Insertable)]
 #[table_name = "users"]
@@ -47,10 +48,20 @@ pub async fn create_user(u: String, h: String) -> Result<User, UserCreateError>
             })?;
 
         let user: User = users.filter(username.eq(u)).first(c)?;
+        crate::webhooks::enqueue(c, WebhookEvent::UserCreated, serde_json::json!({ "user": &user.username }))?;
         Ok(user)
     }).await
 }
 
+pub async fn set_password(user_id: i32, h: String) -> QueryResult<usize> {
+    conn().with_conn(move |c| {
+        use crate::schema::users::dsl::*;
+        diesel::update(users.filter(id.eq(user_id)))
+            .set(hash.eq(h))
+            .execute(c)
+    }).await
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum LoginFailure {
     #[error("Invalid login info.")]
@@ -74,6 +85,7 @@ pub async fn validate_user(u: String, pass: &[u8]) -> Result<User, LoginFailure>
         Result::<_, LoginFailure>::Ok(user)
     }).await?;
 
+    let _timer = crate::metrics::PASSWORD_HASH_SECONDS.with_label_values(&["verify"]).start_timer();
     argon2::verify_encoded_ext(&user.hash, pass, (&*crate::pass::SECRET_KEY).as_ref(), &[])
         .map_err(|_| BadLogin)
         .and_then(|b| if b { Ok(user) } else { Err(BadLogin) })
@@ -95,23 +107,31 @@ pub struct Claims {
     pub exp: i64,
 }
 
+impl Claims {
+    /// Decodes the bearer token or `auth` cookie without checking that the user still exists.
+    pub fn unverified(request: &Request<'_>) -> Option<Claims> {
+        let bearer = request.headers()
+            .get_one("Authorization")
+            .and_then(|h| h.strip_prefix("Bearer "));
+        let cookie = request.cookies().get("auth").map(|c| c.value().to_string());
+        let token = bearer.map(String::from).or(cookie)?;
+
+        jsonwebtoken::decode::<Claims>(&token, &DecodingKey::from_secret(SECRET_KEY.as_bytes()), &Validation::default())
+            .ok()
+            .map(|t| t.claims)
+    }
+}
+
 #[rocket::async_trait]
 impl<'a, 'r> FromRequest<'a, 'r> for Claims {
     type Error = ();
 
     async fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
-        let cookies = request.cookies();
-        let jwt = cookies.get("auth")
-            .map(|c| c.value())
-            .ok_or(())
-            .and_then(|s| jsonwebtoken::decode::<Claims>(s, &DecodingKey::from_secret(SECRET_KEY.as_bytes()), &Validation::default()).map_err(|_| ()))
-            ;
-
-        if let Err(_) = jwt {
-            return Outcome::Failure((Status::Forbidden, ()));
-        }
+        let token = match Claims::unverified(request) {
+            Some(t) => t,
+            None => return Outcome::Failure((Status::Unauthorized, ())),
+        };
 
-        let token = jwt.unwrap().claims;
         let user_exists = user_exists(token.user.clone()).await;
 
         if let Err(e) = &user_exists {
@@ -123,34 +143,476 @@ impl<'a, 'r> FromRequest<'a, 'r> for Claims {
         if e {
             Outcome::Success(token)
         } else {
-            Outcome::Failure((Status::Forbidden, ()))
+            Outcome::Failure((Status::Unauthorized, ()))
         }
     }
 }
 
-pub fn jwt_generate(user: User) -> String {
+/// Returned by a successful login so clients that don't keep cookies can send the token as a bearer token.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct LoginToken {
+    pub token: String,
+    pub expires: i64,
+}
+
+pub fn jwt_generate(user: User) -> LoginToken {
     let now = chrono::Local::now();
-    let now_plus_week = now + chrono::Duration::weeks(1);
+    let expires = now + chrono::Duration::hours(crate::config::get().auth.token_lifetime_hours.into());
 
     let payload = Claims {
         user: user.username,
         user_id: user.id,
         iat: now.timestamp(),
-        exp: now_plus_week.timestamp(),
+        exp: expires.timestamp(),
     };
 
-    jsonwebtoken::encode(&Header::default(), &payload, &EncodingKey::from_secret(SECRET_KEY.as_bytes())).unwrap()
+    let token = jsonwebtoken::encode(&Header::default(), &payload, &EncodingKey::from_secret(SECRET_KEY.as_bytes())).unwrap();
+    LoginToken { token, expires: payload.exp }
 }
 
-#[derive(FromForm, Serialize, Deserialize)]
+#[derive(FromForm, Serialize, Deserialize, JsonSchema)]
 pub struct Login {
     pub username: String,
     pub password: String,
 }
 
-#[derive(FromForm, Serialize, Deserialize)]
+#[derive(FromForm, Serialize, Deserialize, JsonSchema)]
+pub struct PasswordChange {
+    pub old_password: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Account {
+    pub user: String,
+    pub user_id: i32,
+    pub expires: i64,
+}
+
+/// The redirect statuses an alias can be configured to respond with.
+pub const REDIRECT_STATUSES: [u16; 4] = [301, 302, 307, 308];
+pub const DEFAULT_REDIRECT: u16 = 302;
+
+/// How a request chooses among an alias's destinations when it has more than one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Strategy {
+    /// Picks at random, in proportion to each destination's weight.
+    Weighted,
+    /// Cycles through the destinations, repeating each one `weight` times.
+    RoundRobin,
+    /// Like `Weighted`, but a browser keeps getting the same destination.
+    Sticky,
+}
+
+impl Strategy {
+    pub const ALL: [&'static str; 3] = ["weighted", "round_robin", "sticky"];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Strategy::Weighted => "weighted",
+            Strategy::RoundRobin => "round_robin",
+            Strategy::Sticky => "sticky",
+        }
+    }
+}
+
+impl Default for Strategy {
+    fn default() -> Self {
+        Strategy::Weighted
+    }
+}
+
+impl std::str::FromStr for Strategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "weighted" => Ok(Strategy::Weighted),
+            "round_robin" => Ok(Strategy::RoundRobin),
+            "sticky" => Ok(Strategy::Sticky),
+            _ => Err(format!("Unknown strategy {:?}, expected one of {:?}", s, Strategy::ALL)),
+        }
+    }
+}
+
+impl std::fmt::Display for Strategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+pub const MAX_WEIGHT: u32 = 10_000;
+
+/// Parses a destination, which has to be an http or https URL. Others, like `javascript:`, would
+/// run on aliasd's origin when linked from a page.
+pub fn parse_destination(s: &str) -> Result<url::Url, String> {
+    let url = url::Url::parse(s).map_err(|e| format!("{}: {}", s, e))?;
+    match url.scheme() {
+        "http" | "https" => Ok(url),
+        scheme => Err(format!("{}: destinations have to be http or https, not {}", s, scheme)),
+    }
+}
+
+/// Who an alias resolves for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Visibility {
+    Public,
+    /// Any logged-in user.
+    Authenticated,
+    /// The user who created the alias and the owners of its domain. Domains without owners,
+    /// like the default one, leave just the creator.
+    Domain,
+    /// Only the user who created the alias.
+    Owner,
+}
+
+impl Visibility {
+    pub const ALL: [&'static str; 4] = ["public", "authenticated", "domain", "owner"];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Visibility::Public => "public",
+            Visibility::Authenticated => "authenticated",
+            Visibility::Domain => "domain",
+            Visibility::Owner => "owner",
+        }
+    }
+}
+
+impl Default for Visibility {
+    fn default() -> Self {
+        Visibility::Public
+    }
+}
+
+impl std::str::FromStr for Visibility {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "public" => Ok(Visibility::Public),
+            "authenticated" => Ok(Visibility::Authenticated),
+            "domain" => Ok(Visibility::Domain),
+            "owner" => Ok(Visibility::Owner),
+            _ => Err(format!("Unknown visibility {:?}, expected one of {:?}", s, Visibility::ALL)),
+        }
+    }
+}
+
+impl std::fmt::Display for Visibility {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct WeightedDestination {
+    pub to: String,
+    #[serde(default = "WeightedDestination::default_weight")]
+    pub weight: u32,
+}
+
+impl WeightedDestination {
+    fn default_weight() -> u32 {
+        1
+    }
+}
+
+/// A request property a [`Rule`] checks before sending the request to its own destination.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "when", rename_all = "snake_case")]
+pub enum Condition {
+    /// The browser's preferred language is one of these, e.g. `de` matches `de-AT`.
+    Language { languages: Vec<String> },
+    /// Guessed from the user agent.
+    Platform { platform: Platform },
+    /// The client address is in one of these CIDR blocks, e.g. `10.0.0.0/8`.
+    Network { cidrs: Vec<String> },
+    /// The server's local time is between `from` and `until` (`HH:MM`), on one of `days` if any are given.
+    /// Times are in the server's timezone, not the visitor's. `until` may be earlier than `from` to
+    /// run past midnight, but not the same.
+    Schedule {
+        #[serde(default)]
+        days: Vec<String>,
+        from: String,
+        until: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Platform {
+    Mobile,
+    Desktop,
+}
+
+/// Sends requests matching `condition` to `to`. An alias's rules are tried in order before its destinations.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Rule {
+    #[serde(flatten)]
+    pub condition: Condition,
+    pub to: String,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct AliasForm {
     pub from: String,
+    /// The domain the alias goes in. When not given, the one the request was sent to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domain: Option<String>,
+    /// Left empty when `canonical` is set.
+    #[serde(default)]
+    pub to: String,
+    /// Another alias this one resolves through instead of having its own destination.
+    #[serde(default)]
+    pub canonical: Option<String>,
+    /// When not empty, these replace `to` and one is picked per request using `strategy`.
+    #[serde(default)]
+    pub destinations: Vec<WeightedDestination>,
+    #[serde(default)]
+    pub strategy: Strategy,
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    #[serde(default)]
+    pub visibility: Visibility,
+    /// Set by `alias-client apply`; managed aliases can only be changed by another apply.
+    #[serde(default)]
+    pub managed: bool,
+    /// One of [`REDIRECT_STATUSES`], defaulting to [`DEFAULT_REDIRECT`].
+    #[serde(default)]
+    pub redirect: Option<u16>,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// Lowercases, sorts and dedups tags, rejecting any that aren't made of letters, digits, `-` and `_`.
+pub fn normalize_tags(tags: &[String]) -> Result<Vec<String>, String> {
+    let mut out = Vec::with_capacity(tags.len());
+    for tag in tags {
+        let tag = tag.trim().to_lowercase();
+        if tag.is_empty() {
+            continue;
+        }
+        if !tag.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') {
+            return Err(format!("Invalid tag {:?}", tag));
+        }
+        out.push(tag);
+    }
+    out.sort();
+    out.dedup();
+    Ok(out)
+}
+
+/// Tags are stored space-separated so the full-text index sees each one as a word.
+pub fn join_tags(tags: &[String]) -> String {
+    tags.join(" ")
+}
+
+pub fn split_tags(tags: &str) -> Vec<String> {
+    tags.split_whitespace().map(String::from).collect()
+}
+
+/// The JSON body `aliasd` sends with error responses.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ErrorBody {
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub info: Option<String>,
+}
+
+impl std::fmt::Display for ErrorBody {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.info {
+            Some(info) => write!(f, "{} ({})", self.message, info),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// The body of a successful response that has nothing else to say. Some add the name of what changed.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Message {
+    pub message: String,
+}
+
+/// Returned when an alias is created or updated.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AliasSaved {
+    pub message: String,
+    pub domain: String,
+    pub from: String,
+    /// The destination, or the alias it resolves through.
+    pub to: String,
+}
+
+/// The JSON body of a redirect, for clients that asked for JSON. `to` is also in `Location`.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Redirected {
+    pub message: String,
     pub to: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AliasInfo {
+    pub alias: String,
+    /// Empty for the default domain.
+    #[serde(default)]
+    pub domain: String,
+    pub destination: String,
+    pub owner: String,
+    pub managed: bool,
+    pub redirect: u16,
+    pub description: String,
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub canonical: Option<String>,
+    #[serde(default)]
+    pub destinations: Vec<WeightedDestination>,
+    #[serde(default)]
+    pub strategy: Strategy,
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    #[serde(default)]
+    pub visibility: Visibility,
+    /// The last link check of the alias's worst destination, if it's been checked.
+    #[serde(default)]
+    pub health: Option<Health>,
+    pub created_at: i64,
+    pub updated_at: i64,
+    pub modified_by: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum WebhookEvent {
+    #[serde(rename = "alias.created")]
+    AliasCreated,
+    #[serde(rename = "alias.updated")]
+    AliasUpdated,
+    #[serde(rename = "alias.deleted")]
+    AliasDeleted,
+    /// The link checker found the alias's destination stopped working.
+    #[serde(rename = "alias.dead")]
+    AliasDead,
+    #[serde(rename = "user.created")]
+    UserCreated,
+    #[serde(rename = "user.deleted")]
+    UserDeleted,
+}
+
+impl WebhookEvent {
+    pub const ALL: [WebhookEvent; 6] = [
+        WebhookEvent::AliasCreated,
+        WebhookEvent::AliasUpdated,
+        WebhookEvent::AliasDeleted,
+        WebhookEvent::AliasDead,
+        WebhookEvent::UserCreated,
+        WebhookEvent::UserDeleted,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WebhookEvent::AliasCreated => "alias.created",
+            WebhookEvent::AliasUpdated => "alias.updated",
+            WebhookEvent::AliasDeleted => "alias.deleted",
+            WebhookEvent::AliasDead => "alias.dead",
+            WebhookEvent::UserCreated => "user.created",
+            WebhookEvent::UserDeleted => "user.deleted",
+        }
+    }
+}
+
+impl std::str::FromStr for WebhookEvent {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        WebhookEvent::ALL.iter()
+            .copied()
+            .find(|e| e.as_str() == s)
+            .ok_or_else(|| format!("Unknown webhook event {:?}", s))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct NewWebhook {
+    pub url: String,
+    /// The events to send; all of them when empty.
+    #[serde(default)]
+    pub events: Vec<WebhookEvent>,
+    /// Generated when not given.
+    #[serde(default)]
+    pub secret: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct WebhookEndpoint {
+    pub id: i32,
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+    pub created_at: i64,
+    /// Only returned when the endpoint is created.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct WebhookDelivery {
+    pub id: i32,
+    pub event: String,
+    pub created_at: i64,
+    pub attempts: i32,
+    pub next_attempt_at: i64,
+    pub delivered_at: Option<i64>,
+    /// Gave up after too many failed attempts.
+    pub failed: bool,
+    pub history: Vec<WebhookAttempt>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct WebhookAttempt {
+    pub attempted_at: i64,
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    pub duration_ms: i32,
+}
+
+/// A host with its own set of aliases.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct DomainInfo {
+    pub name: String,
+    /// Who can change the domain's aliases. Anyone can when it's empty.
+    pub owners: Vec<String>,
+    pub aliases: i64,
+    pub created_at: i64,
+}
+
+/// Creates a domain, or replaces its owners if it exists.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct DomainForm {
+    #[serde(default)]
+    pub owners: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Health {
+    pub checked_at: i64,
+    /// Missing when the request got no response at all.
+    pub status: Option<u16>,
+    pub latency_ms: Option<u32>,
+    pub error: Option<String>,
+    /// Failed enough checks in a row to count as a dead link.
+    pub dead: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SearchHit {
+    pub alias: String,
+    pub destination: String,
+    pub description: String,
+    pub tags: Vec<String>,
+    pub owner: String,
+}
+
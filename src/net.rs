@@ -0,0 +1,127 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::net::IpAddr;
+
+use serde::{Deserialize, Serialize};
+
+/// An address block like `10.0.0.0/8` or `2001:db8::/32`. A bare address is a block of one.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn parse(s: &str) -> Result<Cidr, String> {
+        let (addr, prefix) = match s.find('/') {
+            Some(i) => (&s[..i], Some(&s[i + 1..])),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| format!("Invalid address in {:?}", s))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p.parse::<u8>().ok().filter(|p| *p <= max)
+                .ok_or_else(|| format!("Invalid prefix length in {:?}", s))?,
+            None => max,
+        };
+        Ok(Cidr { addr, prefix })
+    }
+
+    /// IPv4 blocks also match IPv4-mapped IPv6 addresses.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let (net, ip, bits) = match (self.addr, ip) {
+            (IpAddr::V4(n), IpAddr::V4(i)) => (u32::from(n) as u128, u32::from(i) as u128, 32),
+            (IpAddr::V6(n), IpAddr::V6(i)) => (u128::from(n), u128::from(i), 128),
+            // Only `::ffff:a.b.c.d`; `to_ipv4` would also turn `::1` into 0.0.0.1.
+            (IpAddr::V4(n), IpAddr::V6(i)) => match i.segments() {
+                [0, 0, 0, 0, 0, 0xffff, ..] => (u32::from(n) as u128, u128::from(i) & 0xffff_ffff, 32),
+                _ => return false,
+            },
+            (IpAddr::V6(_), IpAddr::V4(_)) => return false,
+        };
+        if self.prefix == 0 {
+            return true;
+        }
+        let shift = bits - self.prefix as u32;
+        (net >> shift) == (ip >> shift)
+    }
+}
+
+impl TryFrom<String> for Cidr {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Cidr, String> {
+        Cidr::parse(&s)
+    }
+}
+
+impl From<Cidr> for String {
+    fn from(c: Cidr) -> String {
+        c.to_string()
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contains(cidr: &str, ip: &str) -> bool {
+        Cidr::parse(cidr).unwrap().contains(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn parses_blocks_and_addresses() {
+        assert_eq!(Cidr::parse("10.0.0.0/8").unwrap().to_string(), "10.0.0.0/8");
+        assert_eq!(Cidr::parse("10.1.2.3").unwrap().to_string(), "10.1.2.3/32");
+        assert_eq!(Cidr::parse("2001:db8::1").unwrap().to_string(), "2001:db8::1/128");
+        assert!(Cidr::parse("10.0.0.0/").is_err());
+        assert!(Cidr::parse("10.0.0/8").is_err());
+        assert!(Cidr::parse("example.com/8").is_err());
+    }
+
+    #[test]
+    fn rejects_prefixes_longer_than_the_address() {
+        assert!(Cidr::parse("10.0.0.0/33").is_err());
+        assert!(Cidr::parse("2001:db8::/129").is_err());
+        assert!(Cidr::parse("10.0.0.0/300").is_err());
+        assert!(Cidr::parse("2001:db8::/33").is_ok());
+    }
+
+    #[test]
+    fn zero_prefix_matches_everything_of_its_family() {
+        assert!(contains("0.0.0.0/0", "203.0.113.5"));
+        assert!(contains("::/0", "2001:db8::1"));
+        assert!(!contains("::/0", "203.0.113.5"));
+    }
+
+    #[test]
+    fn full_prefixes_match_one_address() {
+        assert!(contains("10.1.2.3/32", "10.1.2.3"));
+        assert!(!contains("10.1.2.3/32", "10.1.2.4"));
+        assert!(contains("2001:db8::1/128", "2001:db8::1"));
+        assert!(!contains("2001:db8::1/128", "2001:db8::2"));
+    }
+
+    #[test]
+    fn matches_within_the_block() {
+        assert!(contains("10.0.0.0/8", "10.255.0.1"));
+        assert!(!contains("10.0.0.0/8", "11.0.0.1"));
+        assert!(contains("2001:db8::/32", "2001:db8:ffff::1"));
+        assert!(!contains("2001:db8::/32", "2001:db9::1"));
+    }
+
+    #[test]
+    fn ipv4_blocks_match_mapped_addresses_only() {
+        assert!(contains("10.0.0.0/8", "::ffff:10.1.2.3"));
+        assert!(!contains("10.0.0.0/8", "::ffff:11.1.2.3"));
+        assert!(!contains("0.0.0.0/8", "::1"));
+        assert!(!contains("10.0.0.0/8", "::10.1.2.3"));
+    }
+}
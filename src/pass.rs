@@ -3,9 +3,9 @@ use once_cell::sync::Lazy;
 use rand::Rng;
 
 pub static SECRET_KEY: Lazy<String> = Lazy::new(|| {
-    std::env::var("ALIAS_SECRET_KEY").unwrap_or_else(|_| {
+    crate::config::get().auth.secret_key.clone().unwrap_or_else(|| {
         eprintln!("Using empty secret key for password hashing. \
-        Set ALIAS_SECRET_KEY= to silence this if intentional.");
+        Set auth.secret_key = \"\" (or ALIAS_SECRET_KEY=) to silence this if intentional.");
         "".to_string()
     })
 });
@@ -27,5 +27,6 @@ pub fn create_pass_hash(pass: impl AsRef<str>) -> String {
 
     let salt = rand::thread_rng().gen::<[u8; 8]>();
 
+    let _timer = crate::metrics::PASSWORD_HASH_SECONDS.with_label_values(&["hash"]).start_timer();
     argon2::hash_encoded(pass.as_ref().as_bytes(), &salt, &CONFIG).unwrap()
 }
\ No newline at end of file
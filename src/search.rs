@@ -0,0 +1,77 @@
+use diesel::prelude::*;
+use diesel::sql_types::{Integer, Text};
+
+use crate::db::conn;
+use crate::model::{split_tags, SearchHit};
+
+const MAX_RESULTS: i64 = 50;
+
+#[derive(QueryableByName)]
+struct SearchRow {
+    #[sql_type = "Text"]
+    alias: String,
+    #[sql_type = "Text"]
+    destination: String,
+    #[sql_type = "Text"]
+    description: String,
+    #[sql_type = "Text"]
+    tags: String,
+    #[sql_type = "Text"]
+    owner: String,
+}
+
+/// Turns free text into an FTS5 query matching every word as a prefix.
+///
+/// Each word is quoted, so FTS5 operators and punctuation in the input are searched for literally.
+fn fts_query(q: &str) -> Option<String> {
+    let terms: Vec<String> = q.split_whitespace()
+        .map(|w| format!("\"{}\"*", w.replace('"', "\"\"")))
+        .collect();
+
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" "))
+    }
+}
+
+/// Searches alias names, destinations, descriptions and tags, best matches first.
+///
+/// Only aliases in `domain` are searched, and other users' owner-only aliases are left out, as are
+/// domain-only ones unless `user_id` owns the domain.
+pub async fn search(q: impl AsRef<str>, domain: String, user_id: i32) -> QueryResult<Vec<SearchHit>> {
+    let query = match fts_query(q.as_ref()) {
+        Some(query) => query,
+        None => return Ok(Vec::new()),
+    };
+
+    let rows = conn().with_conn(move |c| {
+        diesel::sql_query(format!(
+            "select a.alias, a.destination, a.description, a.tags, u.username as owner \
+             from aliases_fts \
+             join aliases a on a.id = aliases_fts.rowid \
+             join users u on u.id = a.creator \
+             where aliases_fts match ? \
+               and a.domain = ? \
+               and (a.visibility not in ('owner', 'domain') or a.creator = ? \
+                    or (a.visibility = 'domain' and exists ( \
+                        select 1 from domain_owners o where o.domain = a.domain and o.user_id = ?))) \
+             order by bm25(aliases_fts) \
+             limit {}", MAX_RESULTS))
+            .bind::<Text, _>(query)
+            .bind::<Text, _>(domain)
+            .bind::<Integer, _>(user_id)
+            .bind::<Integer, _>(user_id)
+            .load::<SearchRow>(c)
+    }).await?;
+
+    Ok(rows.into_iter()
+        .map(|r| SearchHit {
+            alias: r.alias,
+            destination: r.destination,
+            description: r.description,
+            tags: split_tags(&r.tags),
+            owner: r.owner,
+        })
+        .collect())
+}
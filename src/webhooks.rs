@@ -0,0 +1,45 @@
+use diesel::prelude::*;
+use once_cell::sync::Lazy;
+use tokio::sync::Notify;
+
+use crate::model::WebhookEvent;
+
+/// Woken whenever a delivery is queued, so aliasd's dispatcher doesn't have to poll for new ones.
+pub static OUTBOX_READY: Lazy<Notify> = Lazy::new(Notify::new);
+
+/// Queues `event` for every endpoint subscribed to it.
+///
+/// Call this inside the transaction making the change, so a delivery exists if and only if the change does.
+pub fn enqueue(c: &SqliteConnection, event: WebhookEvent, data: serde_json::Value) -> QueryResult<usize> {
+    use crate::schema::{webhook_deliveries, webhook_endpoints};
+
+    let now = chrono::Local::now().timestamp();
+    let endpoints: Vec<(i32, String)> = webhook_endpoints::table
+        .select((webhook_endpoints::id, webhook_endpoints::events))
+        .load(c)?;
+
+    let payload = serde_json::json!({
+        "event": event.as_str(),
+        "created_at": now,
+        "data": data,
+    }).to_string();
+
+    let mut queued = 0;
+    for (endpoint, events) in endpoints {
+        if !events.is_empty() && !events.split_whitespace().any(|e| e == event.as_str()) {
+            continue;
+        }
+        queued += diesel::insert_into(webhook_deliveries::table)
+            .values((webhook_deliveries::endpoint.eq(endpoint),
+                     webhook_deliveries::event.eq(event.as_str()),
+                     webhook_deliveries::payload.eq(&payload),
+                     webhook_deliveries::created_at.eq(now),
+                     webhook_deliveries::next_attempt_at.eq(now)))
+            .execute(c)?;
+    }
+
+    if queued > 0 {
+        OUTBOX_READY.notify();
+    }
+    Ok(queued)
+}